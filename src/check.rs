@@ -0,0 +1,363 @@
+use itertools::Itertools;
+use log::{error, info};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{Result, SushiError};
+use crate::search;
+use crate::server;
+
+/// A reference to an url found in a generated page.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    /// the page containing the reference
+    pub page: PathBuf,
+    pub url: String,
+}
+
+#[derive(Debug)]
+pub struct BrokenReference {
+    pub reference: Reference,
+    pub reason: String,
+}
+
+/// Outcome of `check_gen_dir`.
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub pages: usize,
+    pub references: usize,
+    pub broken: Vec<BrokenReference>,
+    /// references to other sites, which are not checked
+    pub external: Vec<Reference>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.broken.is_empty()
+    }
+
+    pub fn print_summary(&self, list_external: bool) {
+        for broken in self.broken.iter() {
+            error!(
+                "{}: broken reference \"{}\": {}",
+                broken.reference.page.to_string_lossy(),
+                broken.reference.url,
+                broken.reason
+            );
+        }
+        if list_external {
+            for reference in self.external.iter() {
+                println!("{}: {}", reference.page.to_string_lossy(), reference.url);
+            }
+        }
+        info!(
+            "[ check]  {} page(s), {} reference(s) checked, {} external skipped",
+            self.pages,
+            self.references,
+            self.external.len()
+        );
+        if !self.is_ok() {
+            error!("{} broken reference(s)", self.broken.len());
+        }
+    }
+}
+
+/// Where a reference points to.
+enum Target {
+    /// a file in the gen directory, and the fragment if any
+    Internal(PathBuf, Option<String>),
+    /// another site
+    External,
+    /// `mailto:`, `data:` etc.
+    Ignored,
+    Broken(String),
+}
+
+/// References and element ids of a generated page.
+struct ParsedPage {
+    references: Vec<String>,
+    /// `href` of `<a>`, i.e. the references followed by readers
+    links: Vec<String>,
+    ids: HashSet<String>,
+}
+
+/// Links (`href` of `<a>`) of an html document, without duplicates.
+pub fn links_of(html: &str) -> Vec<String> {
+    parse_page(html).links.into_iter().unique().collect()
+}
+
+/// Check that the references (`href` of links, `src` of images, scripts
+/// etc.) in every html file of `gen_dir` point at generated files, and that
+/// their `#fragment` names an element of the target page. `site_url` tells
+/// the absolute urls of the site from those of other sites.
+pub fn check_gen_dir(gen_dir: &Path, site_url: Option<&str>) -> Result<CheckReport> {
+    let mut files = vec![];
+    collect_html_files(gen_dir, &mut files)?;
+    let pages = files
+        .par_iter()
+        .map(|path| {
+            let content = fs::read(path).map_err(|e| SushiError::io(path, e))?;
+            Ok((path.clone(), parse_page(&String::from_utf8_lossy(&content))))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    let site_url = site_url.unwrap_or_default().trim_end_matches('/');
+    let base_path = server::base_path_of_url(site_url);
+    let mut report = CheckReport {
+        pages: pages.len(),
+        ..Default::default()
+    };
+    for path in files.iter() {
+        let dir = path.parent().unwrap_or(gen_dir);
+        for url in pages[path].references.iter() {
+            report.references += 1;
+            let reference = Reference {
+                page: path.clone(),
+                url: url.clone(),
+            };
+            let (target, fragment) = match resolve(url, path, dir, gen_dir, site_url, &base_path) {
+                Target::Internal(target, fragment) => (target, fragment),
+                Target::External => {
+                    report.external.push(reference);
+                    continue;
+                }
+                Target::Ignored => continue,
+                Target::Broken(reason) => {
+                    report.broken.push(BrokenReference { reference, reason });
+                    continue;
+                }
+            };
+            let Some(fragment) = fragment.filter(|x| !x.is_empty() && x != "top") else {
+                continue;
+            };
+            // only html pages are known to have ids
+            if let Some(page) = pages.get(&target) {
+                if !page.ids.contains(&fragment) {
+                    report.broken.push(BrokenReference {
+                        reference,
+                        reason: format!(
+                            "no element with id \"{}\" in {}",
+                            fragment,
+                            target.to_string_lossy()
+                        ),
+                    });
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn collect_html_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .map_err(|e| SushiError::io(dir, e))?
+        .flatten()
+        .map(|x| x.path())
+        .collect::<Vec<_>>();
+    entries.sort();
+    for path in entries {
+        if path
+            .file_name()
+            .is_some_and(|x| x.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            collect_html_files(&path, files)?;
+        } else if path.extension().is_some_and(|x| x == "html" || x == "htm") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn resolve(
+    url: &str,
+    page: &Path,
+    dir: &Path,
+    gen_dir: &Path,
+    site_url: &str,
+    base_path: &str,
+) -> Target {
+    let url = url.trim();
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, Some(server::percent_decode(fragment))),
+        None => (url, None),
+    };
+    let url = url.split('?').next().unwrap_or_default();
+    if url.is_empty() {
+        return Target::Internal(page.to_path_buf(), fragment);
+    }
+
+    let scheme_end = url.find(':').filter(|i| !url[..*i].contains(['/', '.']));
+    let path = if let Some(i) = scheme_end {
+        let scheme = url[..i].to_lowercase();
+        if scheme != "http" && scheme != "https" {
+            return Target::Ignored;
+        }
+        match url.strip_prefix(site_url) {
+            Some(rest) if !site_url.is_empty() && (rest.is_empty() || rest.starts_with('/')) => {
+                base_path.to_string() + rest
+            }
+            _ => return Target::External,
+        }
+    } else if url.starts_with("//") {
+        return Target::External;
+    } else {
+        url.to_string()
+    };
+    let path = server::percent_decode(&path);
+
+    let (mut file, relative) = if path.starts_with('/') {
+        match path.strip_prefix(base_path) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => (gen_dir.to_path_buf(), rest),
+            _ => return Target::Broken(format!("outside the site, which is at {}/", base_path)),
+        }
+    } else {
+        (dir.to_path_buf(), path.as_str())
+    };
+    for component in Path::new(relative.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(c) => file.push(c),
+            Component::ParentDir => {
+                if file == gen_dir {
+                    return Target::Broken("outside the gen directory".to_string());
+                }
+                file.pop();
+            }
+            _ => (),
+        }
+    }
+    // the same way as the preview server
+    if file.is_dir() {
+        file.push("index.html");
+    } else if !file.exists() && file.extension().is_none() {
+        file.set_extension("html");
+    }
+    if file.is_file() {
+        Target::Internal(file, fragment)
+    } else {
+        Target::Broken(format!("{} not found", file.to_string_lossy()))
+    }
+}
+
+/// Attributes holding urls, by element.
+fn is_reference(tag: &str, attribute: &str) -> bool {
+    matches!(
+        (tag, attribute),
+        ("a" | "area" | "link", "href")
+            | (
+                "img" | "script" | "source" | "iframe" | "video" | "audio" | "embed" | "track",
+                "src"
+            )
+    )
+}
+
+fn parse_page(html: &str) -> ParsedPage {
+    let mut page = ParsedPage {
+        references: vec![],
+        links: vec![],
+        ids: HashSet::new(),
+    };
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let name_len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        if name_len == 0 {
+            continue;
+        }
+        let name = rest[..name_len].to_lowercase();
+        rest = &rest[name_len..];
+        let (attributes, after) = parse_attributes(rest);
+        rest = after;
+        for (attribute, value) in attributes {
+            if attribute == "id" || (name == "a" && attribute == "name") {
+                page.ids.insert(value);
+            } else if is_reference(&name, &attribute) {
+                if name == "a" {
+                    page.links.push(value.clone());
+                }
+                page.references.push(value);
+            }
+        }
+        // skip the content of scripts and styles
+        if name == "script" || name == "style" {
+            let closing = format!("</{}", name);
+            rest = rest
+                .as_bytes()
+                .windows(closing.len())
+                .position(|x| x.eq_ignore_ascii_case(closing.as_bytes()))
+                .map_or("", |end| &rest[end..]);
+        }
+    }
+    page
+}
+
+/// Attributes of a tag, and the html after it.
+fn parse_attributes(html: &str) -> (Vec<(String, String)>, &str) {
+    let mut attributes = vec![];
+    let mut rest = html;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            return (attributes, rest);
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            return (attributes, after);
+        }
+        let name_len = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '=' | '>' | '/'))
+            .unwrap_or(rest.len())
+            .max(1);
+        let name = rest[..name_len].to_lowercase();
+        rest = rest[name_len..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            None => String::new(),
+            Some(after) => {
+                let after = after.trim_start();
+                let (value, after) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = after[1..].find(quote).map_or(after.len(), |x| x + 1);
+                        (&after[1..end], after.get(end + 1..).unwrap_or(""))
+                    }
+                    _ => {
+                        let end = after
+                            .find(|c: char| c.is_whitespace() || c == '>')
+                            .unwrap_or(after.len());
+                        (&after[..end], &after[end..])
+                    }
+                };
+                rest = after;
+                search::decode_entities(value)
+            }
+        };
+        attributes.push((name, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_references_and_ids() {
+        let page = parse_page(
+            "<h2 id=\"intro\">Intro</h2><!-- <a href=\"x\"> -->\
+             <a class=x href='a.html?x=1&amp;y=2#intro'>a</a><img src=b.png alt=\"\">\
+             <script>if (a<b) { x = \"<a href='c'>\" }</script><a name=old></a>",
+        );
+        assert_eq!(page.references, vec!["a.html?x=1&y=2#intro", "b.png"]);
+        assert_eq!(
+            page.ids,
+            HashSet::from(["intro".to_string(), "old".to_string()])
+        );
+    }
+}
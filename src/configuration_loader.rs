@@ -1,24 +1,25 @@
 use liquid::partials::{EagerCompiler, InMemorySource};
 use liquid::ParserBuilder;
-use log::{debug, error};
+use log::debug;
+use serde::de::DeserializeOwned;
 use serde_yaml::Value;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsString;
 use std::fs;
 use std::fs::DirEntry;
 use std::option::Option;
-use std::path::PathBuf;
-use std::rc::Rc;
+use std::path::{Path, PathBuf};
 use std::string::String;
+use std::sync::Arc;
 
-use crate::converters::{Converter, DummyConverter, ExternalConverter};
+use crate::converters::{Converter, ConverterConfig, DummyConverter, ExternalConverter};
+use crate::error::{Result, SushiError};
 use crate::extract_frontmatter::extract_front_matter;
 use crate::layout::Layout;
 use crate::markdown_parser::MarkdownParser;
 
-pub fn parse_config_file(path: PathBuf) -> HashMap<String, Value> {
-    let raw_config = fs::read(&path).expect("cannot read config file");
+pub fn parse_config_file(path: PathBuf) -> Result<HashMap<String, Value>> {
+    let raw_config = fs::read(&path).map_err(|e| SushiError::io(&path, e))?;
 
     let mut is_toml = false;
     if let Some(ext_name) = path.extension() {
@@ -27,17 +28,48 @@ pub fn parse_config_file(path: PathBuf) -> HashMap<String, Value> {
         }
     }
 
-    let mut config: HashMap<String, Value> = if is_toml {
-        toml::from_slice(raw_config.as_slice()).expect("cannot parse toml config file")
+    let config: HashMap<String, Value> = if is_toml {
+        toml::from_slice(raw_config.as_slice()).map_err(|e| SushiError::Config {
+            path: path.clone(),
+            line: e
+                .span()
+                .map(|span| line_of_offset(raw_config.as_slice(), span.start)),
+            message: e.message().to_string(),
+        })?
     } else {
-        serde_yaml::from_slice(raw_config.as_slice()).expect("cannot parse yaml config file")
+        serde_yaml::from_slice(raw_config.as_slice()).map_err(|e| SushiError::Config {
+            path: path.clone(),
+            line: e.location().map(|l| l.line()),
+            message: e.to_string(),
+        })?
     };
-    config.insert(
-        String::from("time"),
-        Value::String(chrono::Local::now().to_rfc3339()),
-    );
     debug!("{:?}", config);
-    config
+    Ok(config)
+}
+
+fn line_of_offset(content: &[u8], offset: usize) -> usize {
+    content.iter().take(offset).filter(|&&b| b == b'\n').count() + 1
+}
+
+/// `#[serde(default = "confld::default_true")]` of settings on by default.
+pub fn default_true() -> bool {
+    true
+}
+
+/// Section `key` of `config`: `true` for the defaults, `false` or absent for
+/// none, or the settings.
+pub fn section_from_config<T: DeserializeOwned + Default>(
+    key: &str,
+    config: &HashMap<String, Value>,
+    path: &Path,
+) -> Result<Option<T>> {
+    match config.get(key) {
+        None | Some(Value::Bool(false)) => Ok(None),
+        Some(Value::Bool(true)) => Ok(Some(T::default())),
+        Some(value) => serde_yaml::from_value(value.clone())
+            .map(Some)
+            .map_err(|e| SushiError::config(path, format!("invalid `{}`: {}", key, e))),
+    }
 }
 
 pub fn string_from_config(
@@ -54,24 +86,22 @@ pub fn string_from_config(
 pub fn parse_includes(path: PathBuf) -> HashMap<String, PathBuf> {
     let mut partial_list: HashMap<String, PathBuf> = HashMap::new();
     if let Ok(dir) = fs::read_dir(path) {
-        for entry in dir {
-            if let Ok(entry) = entry {
-                if let Some(ext) = entry.path().extension() {
-                    if ext == "liquid" {
-                        partial_list.insert(
-                            entry
-                                .path()
-                                .file_stem()
-                                .unwrap()
-                                .to_string_lossy()
-                                .to_string(),
-                            entry.path().clone(),
-                        );
-                        debug!(
-                            "[discover] partial: \"{}\"",
-                            entry.path().file_stem().unwrap().to_string_lossy()
-                        );
-                    }
+        for entry in dir.flatten() {
+            if let Some(ext) = entry.path().extension() {
+                if ext == "liquid" {
+                    partial_list.insert(
+                        entry
+                            .path()
+                            .file_stem()
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string(),
+                        entry.path().clone(),
+                    );
+                    debug!(
+                        "[discover] partial: \"{}\"",
+                        entry.path().file_stem().unwrap().to_string_lossy()
+                    );
                 }
             }
         }
@@ -79,133 +109,203 @@ pub fn parse_includes(path: PathBuf) -> HashMap<String, PathBuf> {
     partial_list
 }
 
-pub fn compile_partials(partial_list: HashMap<String, PathBuf>) -> EagerCompiler<InMemorySource> {
+pub fn compile_partials(
+    partial_list: HashMap<String, PathBuf>,
+) -> Result<EagerCompiler<InMemorySource>> {
     let mut compiler = EagerCompiler::<InMemorySource>::empty();
     for (partial_name, partial_path) in partial_list {
         let content =
-            fs::read_to_string(&partial_path).expect("cannot open liquid partial in include");
+            fs::read_to_string(&partial_path).map_err(|e| SushiError::io(&partial_path, e))?;
         compiler.add(partial_name, content);
         debug!(
             "[compile] partial: \"{}\"",
             &partial_path.file_stem().unwrap().to_string_lossy()
         );
     }
-    compiler
+    Ok(compiler)
 }
 
-pub fn parse_templates(path: PathBuf) -> HashMap<String, PathBuf> {
-    let mut template_list: HashMap<String, PathBuf> = HashMap::new();
-    for entry in fs::read_dir(path).expect("cannot open _template dir") {
-        if let Ok(entry) = entry {
-            if let Some(ext) = entry.path().extension() {
-                if ext == "liquid" {
-                    template_list.insert(
-                        entry
-                            .path()
-                            .file_stem()
-                            .unwrap()
-                            .to_string_lossy()
-                            .to_string(),
-                        entry.path(),
-                    );
-                    debug!(
-                        "[discover] template: \"{}\"",
-                        entry.path().file_stem().unwrap().to_string_lossy()
-                    );
+/// Map every template to the partials it uses, directly or through other
+/// partials.
+pub fn template_partial_deps(
+    template_list: &HashMap<String, PathBuf>,
+    partial_list: &HashMap<String, PathBuf>,
+) -> HashMap<String, HashSet<String>> {
+    let include = regex::Regex::new(r#"\{%-?\s*(?:include|render)\s+["']?([^\s"'%,]+)"#).unwrap();
+    let direct_deps = |path: &PathBuf| -> Vec<String> {
+        let content = fs::read_to_string(path).unwrap_or_default();
+        include
+            .captures_iter(&content)
+            .map(|c| c[1].to_string())
+            .collect()
+    };
+    let partial_deps: HashMap<&String, Vec<String>> = partial_list
+        .iter()
+        .map(|(name, path)| (name, direct_deps(path)))
+        .collect();
+
+    let mut deps = HashMap::new();
+    for (template_name, template_path) in template_list {
+        let mut used = HashSet::new();
+        let mut stack = direct_deps(template_path);
+        while let Some(partial) = stack.pop() {
+            if let Some(more) = partial_deps.get(&partial) {
+                if !used.contains(&partial) {
+                    stack.extend(more.iter().cloned());
                 }
             }
+            used.insert(partial);
+        }
+        deps.insert(template_name.clone(), used);
+    }
+    deps
+}
+
+pub fn parse_templates(path: PathBuf) -> Result<HashMap<String, PathBuf>> {
+    let mut template_list: HashMap<String, PathBuf> = HashMap::new();
+    for entry in fs::read_dir(&path)
+        .map_err(|e| SushiError::io(&path, e))?
+        .flatten()
+    {
+        if let Some(ext) = entry.path().extension() {
+            if ext == "liquid" {
+                template_list.insert(
+                    entry
+                        .path()
+                        .file_stem()
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string(),
+                    entry.path(),
+                );
+                debug!(
+                    "[discover] template: \"{}\"",
+                    entry.path().file_stem().unwrap().to_string_lossy()
+                );
+            }
         }
     }
-    template_list
+    Ok(template_list)
 }
 
 pub fn compile_templates(
     partials: EagerCompiler<InMemorySource>,
     template_list: HashMap<String, PathBuf>,
-) -> HashMap<String, Layout> {
+) -> Result<HashMap<String, Layout>> {
     let mut templates = HashMap::new();
     let parser = ParserBuilder::with_stdlib()
         .partials(partials)
         .build()
-        .unwrap();
+        .map_err(|e| SushiError::TemplateCompile {
+            path: PathBuf::from("_includes"),
+            message: e.to_string(),
+        })?;
     for (template_name, template_path) in template_list {
-        let (fm, real_content) = extract_front_matter(&template_path);
-        let fm = match fm {
-            Some(fm) => fm,
-            None => HashMap::new(),
-        };
-        let template = parser.parse(real_content.as_str());
-        if let Err(e) = template {
-            error!("{}", e);
-            panic!("compile template error");
-        }
-        let template = template.unwrap();
+        let (fm, real_content, _) = extract_front_matter(&template_path)?;
+        let fm = fm.unwrap_or_default();
+        let template =
+            parser
+                .parse(real_content.as_str())
+                .map_err(|e| SushiError::TemplateCompile {
+                    path: template_path.clone(),
+                    message: e.to_string(),
+                })?;
         let layout = Layout::new(fm, template);
         debug!("[compile] template: \"{}\"", &template_name);
         templates.insert(template_name, layout);
     }
-    templates
+    Ok(templates)
 }
 
-pub fn parse_converters(path: PathBuf) -> HashMap<String, PathBuf> {
+pub fn parse_converters(path: PathBuf) -> Result<HashMap<String, PathBuf>> {
     let mut converter_list: HashMap<String, PathBuf> = HashMap::new();
-    for entry in fs::read_dir(path).expect("cannot open _converter dir") {
-        if let Ok(entry) = entry {
-            converter_list.insert(
-                entry.file_name().to_string_lossy().to_string(),
-                entry.path(),
-            );
-            debug!(
-                "[discover] converter: \"{}\"",
-                entry.file_name().to_string_lossy().to_string()
-            );
-        }
+    for entry in fs::read_dir(&path)
+        .map_err(|e| SushiError::io(&path, e))?
+        .flatten()
+    {
+        converter_list.insert(
+            entry.file_name().to_string_lossy().to_string(),
+            entry.path(),
+        );
+        debug!(
+            "[discover] converter: \"{}\"",
+            entry.file_name().to_string_lossy()
+        );
     }
-    converter_list
+    Ok(converter_list)
 }
 
 pub fn load_converters(
     converter_list: HashMap<String, PathBuf>,
-) -> HashMap<String, Rc<RefCell<dyn Converter>>> {
-    let mut converters: HashMap<String, Rc<RefCell<dyn Converter>>> = HashMap::new();
+    converter_configs: &HashMap<String, ConverterConfig>,
+    site_dir: &Path,
+    config_path: &Path,
+) -> Result<HashMap<String, Arc<dyn Converter>>> {
+    let mut converters: HashMap<String, Arc<dyn Converter>> = HashMap::new();
     for (converter_name, converter_path) in converter_list {
         debug!("[compile] converter: \"{}\"", &converter_name);
+        let config = converter_configs
+            .get(&converter_name)
+            .cloned()
+            .unwrap_or_default();
         // external converter
         converters.insert(
             converter_name.clone(),
-            Rc::new(RefCell::new(ExternalConverter {
-                name: converter_name,
-                path: converter_path,
-            })),
+            Arc::new(ExternalConverter::from_file(
+                converter_name,
+                &converter_path,
+                config,
+            )),
         );
     }
+    // converters declared as commands, which take precedence over files
+    for (converter_name, config) in converter_configs {
+        let Some(command) = &config.command else {
+            continue;
+        };
+        debug!("[compile] converter: \"{}\" ({})", converter_name, command);
+        let converter = ExternalConverter::from_command(
+            converter_name.clone(),
+            command,
+            site_dir,
+            config.clone(),
+        )
+        .map_err(|e| {
+            SushiError::config(
+                config_path,
+                format!("converter \"{}\": {}", converter_name, e),
+            )
+        })?;
+        converters.insert(converter_name.clone(), Arc::new(converter));
+    }
     // dummy converter for copy
-    converters.insert(
-        "__copy__".to_string(),
-        Rc::new(RefCell::new(DummyConverter {})),
-    );
+    converters.insert("__copy__".to_string(), Arc::new(DummyConverter {}));
     // internal converter (markdown only for now)
-    converters.insert(
-        "__internal__".to_string(),
-        Rc::new(RefCell::new(MarkdownParser::new())),
-    );
-    converters
+    converters.insert("__internal__".to_string(), Arc::new(MarkdownParser::new()));
+    Ok(converters)
 }
 
-pub fn find_dir(base_dir: &PathBuf, dir_name: &String) -> Option<Result<DirEntry, std::io::Error>> {
-    let entry = fs::read_dir(base_dir.clone()).unwrap().find(|x| {
+pub fn find_dir(
+    base_dir: &Path,
+    dir_name: &String,
+) -> Option<std::result::Result<DirEntry, std::io::Error>> {
+    fs::read_dir(base_dir).ok()?.find(|x| {
         if let Ok(file) = x {
             file.file_name() == OsString::from(&dir_name) && file.path().is_dir()
         } else {
             false
         }
-    });
-    entry
+    })
 }
 
-pub fn find_dir_or_panic(base_dir: &PathBuf, dir_name: &String) -> fs::DirEntry {
-    let entry = find_dir(&base_dir, &dir_name)
-        .expect(format!("cannot find {} dir", &dir_name).as_str())
-        .unwrap();
-    entry
+pub fn find_required_dir(base_dir: &Path, dir_name: &String) -> Result<fs::DirEntry> {
+    let mut path = base_dir.to_path_buf();
+    path.push(dir_name);
+    match find_dir(base_dir, dir_name) {
+        Some(entry) => entry.map_err(|e| SushiError::io(&path, e)),
+        None => Err(SushiError::io(
+            &path,
+            std::io::Error::new(std::io::ErrorKind::NotFound, "directory not found"),
+        )),
+    }
 }
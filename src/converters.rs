@@ -1,37 +1,422 @@
-use std::path::PathBuf;
-use subprocess::Exec;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use subprocess::{Exec, ExitStatus, Redirection};
 
-pub trait Converter {
-    fn convert(&self, content: Vec<u8>) -> Vec<u8>;
+/// Metadata a converter finds in the content, e.g. `toc`. It is merged into
+/// the page object, unless the front matter has the same keys.
+pub type Metadata = BTreeMap<String, serde_yaml::Value>;
+
+/// A page to convert.
+pub struct ConverterInput<'a> {
+    pub content: Vec<u8>,
+    /// path of the page, relative to the site directory
+    pub path: &'a Path,
+    /// extension of the generated file, e.g. `html`
+    pub output_ext: &'a str,
+    pub site_dir: &'a Path,
+    pub front_matter: &'a HashMap<String, serde_yaml::Value>,
+    /// configuration of the site, i.e. `_site.yml`
+    pub config: &'a HashMap<String, serde_yaml::Value>,
+}
+
+/// Output of a converter.
+#[derive(Debug, Default)]
+pub struct Converted {
+    pub content: Vec<u8>,
+    pub metadata: Metadata,
+}
+
+pub trait Converter: Send + Sync {
+    /// Convert `content`, or describe why it cannot be converted.
+    fn convert(&self, content: Vec<u8>) -> Result<Vec<u8>, String>;
+
+    /// Convert a page, along with its metadata. Converters that only need
+    /// the content and have no metadata only implement `convert`.
+    fn convert_page(&self, input: ConverterInput) -> Result<Converted, String> {
+        Ok(Converted {
+            content: self.convert(input.content)?,
+            metadata: Metadata::new(),
+        })
+    }
+}
+
+/// How an external converter reads pages and writes the result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// the content on stdin, the converted content on stdout
+    #[default]
+    Bytes,
+    /// a `ConverterRequest` on stdin, a `ConverterResponse` on stdout
+    Json,
 }
 
-#[allow(dead_code)]
+/// Settings of a converter, from `converters` in `_site.yml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConverterConfig {
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// command line run in the site directory, e.g. `pandoc -f markdown`,
+    /// instead of a file of the converters directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// arguments added to the command, where `{page_path}`, `{output_ext}`
+    /// and `{site_dir}` are replaced
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// environment variables, replaced like `args`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// seconds a page may take, `0` for no limit
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    60
+}
+
+impl Default for ConverterConfig {
+    fn default() -> Self {
+        Self {
+            protocol: Protocol::default(),
+            command: None,
+            args: vec![],
+            env: BTreeMap::new(),
+            timeout: default_timeout(),
+        }
+    }
+}
+
+/// What a converter speaking `Protocol::Json` reads from stdin.
+#[derive(Serialize)]
+pub struct ConverterRequest<'a> {
+    /// version of the protocol
+    pub version: u32,
+    pub path: &'a Path,
+    pub content: &'a str,
+    pub front_matter: &'a HashMap<String, serde_yaml::Value>,
+    pub config: &'a HashMap<String, serde_yaml::Value>,
+}
+
+/// What a converter speaking `Protocol::Json` writes to stdout: either the
+/// converted content and its metadata, or an error.
+#[derive(Deserialize)]
+pub struct ConverterResponse {
+    #[serde(default)]
+    pub content: Option<String>,
+    #[serde(default)]
+    pub metadata: Metadata,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// A program converting pages, either a file of the converters directory or
+/// a command of `_site.yml`. Besides `args`, it gets the page in the
+/// environment: `SUSHI_PAGE_PATH`, `SUSHI_OUTPUT_EXT` and `SUSHI_SITE_DIR`,
+/// with absolute paths.
 pub struct ExternalConverter {
     pub name: String,
-    pub path: PathBuf,
+    /// the program and its arguments
+    pub command: Vec<String>,
+    /// working directory of the program
+    pub dir: PathBuf,
+    pub args: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub timeout: Option<Duration>,
+    pub protocol: Protocol,
+}
+
+impl ExternalConverter {
+    /// Converter of a file of the converters directory, run in that
+    /// directory.
+    pub fn from_file(name: String, path: &Path, config: ConverterConfig) -> ExternalConverter {
+        let file_name = path.file_name().unwrap_or_default();
+        let program = Path::new(".").join(file_name);
+        ExternalConverter {
+            command: vec![program.to_string_lossy().to_string()],
+            dir: path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+            ..Self::new(name, config)
+        }
+    }
+
+    /// Converter of a `command` of `_site.yml`, run in the site directory.
+    pub fn from_command(
+        name: String,
+        command: &str,
+        site_dir: &Path,
+        config: ConverterConfig,
+    ) -> Result<ExternalConverter, String> {
+        let command = shlex::split(command)
+            .filter(|x| !x.is_empty())
+            .ok_or(format!("invalid command \"{}\"", command))?;
+        Ok(ExternalConverter {
+            command,
+            dir: site_dir.to_path_buf(),
+            ..Self::new(name, config)
+        })
+    }
+
+    fn new(name: String, config: ConverterConfig) -> ExternalConverter {
+        ExternalConverter {
+            name,
+            command: vec![],
+            dir: PathBuf::new(),
+            args: config.args,
+            env: config.env,
+            timeout: (config.timeout > 0).then(|| Duration::from_secs(config.timeout)),
+            protocol: config.protocol,
+        }
+    }
+
+    /// Run the program on `stdin`, for the page of `input` if any.
+    fn run(&self, stdin: Vec<u8>, input: Option<&ConverterInput>) -> Result<Vec<u8>, String> {
+        // paths are absolute, since file converters run in their own
+        // directory
+        let site_dir =
+            input.map(|x| std::path::absolute(x.site_dir).unwrap_or(x.site_dir.to_path_buf()));
+        let vars = [
+            (
+                "page_path",
+                input
+                    .zip(site_dir.as_ref())
+                    .map(|(x, dir)| dir.join(x.path).to_string_lossy().to_string()),
+            ),
+            ("output_ext", input.map(|x| x.output_ext.to_string())),
+            (
+                "site_dir",
+                site_dir.as_ref().map(|x| x.to_string_lossy().to_string()),
+            ),
+        ];
+        let expand = |s: &str| {
+            vars.iter().fold(s.to_string(), |s, (name, value)| {
+                s.replace(
+                    &format!("{{{}}}", name),
+                    value.as_deref().unwrap_or_default(),
+                )
+            })
+        };
+        let mut exec = Exec::cmd(&self.command[0])
+            .args(&self.command[1..])
+            .args(&self.args.iter().map(|x| expand(x)).collect::<Vec<_>>())
+            .cwd(&self.dir)
+            .stdin(Redirection::Pipe)
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe);
+        for (name, value) in vars.iter() {
+            let value = value.as_deref().unwrap_or_default();
+            exec = exec.env(format!("SUSHI_{}", name.to_uppercase()), value);
+        }
+        for (name, value) in self.env.iter() {
+            exec = exec.env(name, expand(value));
+        }
+
+        let start = Instant::now();
+        let mut process = exec
+            .popen()
+            .map_err(|e| format!("cannot run {}: {}", self.command[0], e))?;
+        // written aside, since a program may exit without reading all of it,
+        // and its exit status tells whether it failed
+        let mut pipe = process.stdin.take().unwrap();
+        let writer = thread::spawn(move || {
+            let _ = pipe.write_all(&stdin);
+        });
+        let mut communicator = process.communicate_start(None);
+        if let Some(timeout) = self.timeout {
+            communicator = communicator.limit_time(timeout);
+        }
+        let timed_out = || format!("timed out after {}s", self.timeout.unwrap().as_secs());
+        let (stdout, stderr) = match communicator.read() {
+            Ok((stdout, stderr)) => (stdout.unwrap_or_default(), stderr.unwrap_or_default()),
+            Err(e) => {
+                let _ = process.kill();
+                let _ = process.wait();
+                let _ = writer.join();
+                return Err(match e.kind() {
+                    ErrorKind::TimedOut => timed_out(),
+                    _ => e.error.to_string(),
+                });
+            }
+        };
+        let status = match self.timeout {
+            Some(timeout) => process.wait_timeout(timeout.saturating_sub(start.elapsed())),
+            None => process.wait().map(Some),
+        }
+        .map_err(|e| e.to_string())?;
+        if status.is_none() {
+            let _ = process.kill();
+            let _ = process.wait();
+        }
+        let _ = writer.join();
+        let stderr = String::from_utf8_lossy(&stderr);
+        match status {
+            Some(ExitStatus::Exited(0)) => {
+                if !stderr.trim().is_empty() {
+                    let path = input.map(|x| x.path.to_string_lossy()).unwrap_or_default();
+                    warn!("{}: converter \"{}\": {}", path, self.name, stderr.trim());
+                }
+                Ok(stdout)
+            }
+            Some(status) => Err(format!("exited with {:?}: {}", status, stderr.trim())),
+            None => Err(timed_out()),
+        }
+    }
 }
 
 impl Converter for ExternalConverter {
-    fn convert(&self, content: Vec<u8>) -> Vec<u8> {
-        let cur_dir = std::env::current_dir().unwrap();
-        std::env::set_current_dir(&self.path.parent().unwrap()).unwrap();
-        let mut temp_dir = PathBuf::from(".");
-        temp_dir.push(&self.path.file_name().unwrap());
-        // debug!("invoking {:?}", temp_dir);
-        let read_content = Exec::cmd(temp_dir)
-            .stdin(content)
-            .capture()
-            .expect("converter error")
-            .stdout;
-        std::env::set_current_dir(cur_dir).unwrap();
-        read_content
+    fn convert(&self, content: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.run(content, None)
+    }
+
+    fn convert_page(&self, mut input: ConverterInput) -> Result<Converted, String> {
+        if self.protocol == Protocol::Bytes {
+            let content = std::mem::take(&mut input.content);
+            return Ok(Converted {
+                content: self.run(content, Some(&input))?,
+                metadata: Metadata::new(),
+            });
+        }
+        let content = std::str::from_utf8(&input.content)
+            .map_err(|e| format!("content is not valid UTF-8: {}", e))?;
+        let request = ConverterRequest {
+            version: 1,
+            path: input.path,
+            content,
+            front_matter: input.front_matter,
+            config: input.config,
+        };
+        let request = serde_json::to_vec(&request).map_err(|e| e.to_string())?;
+        let output = self.run(request, Some(&input))?;
+        let response: ConverterResponse =
+            serde_json::from_slice(&output).map_err(|e| format!("invalid response: {}", e))?;
+        if let Some(error) = response.error {
+            return Err(error);
+        }
+        Ok(Converted {
+            content: response
+                .content
+                .ok_or("invalid response: no `content`")?
+                .into_bytes(),
+            metadata: response.metadata,
+        })
     }
 }
 
 pub struct DummyConverter {}
 
 impl Converter for DummyConverter {
-    fn convert(&self, content: Vec<u8>) -> Vec<u8> {
-        content
+    fn convert(&self, content: Vec<u8>) -> Result<Vec<u8>, String> {
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn convert(converter: &ExternalConverter, site_dir: &Path) -> Result<Converted, String> {
+        converter.convert_page(ConverterInput {
+            content: b"content".to_vec(),
+            path: Path::new("posts/a.md"),
+            output_ext: "html",
+            site_dir,
+            front_matter: &HashMap::new(),
+            config: &HashMap::new(),
+        })
+    }
+
+    fn command(script: &str, config: ConverterConfig) -> ExternalConverter {
+        let command = format!("sh -c '{}'", script);
+        ExternalConverter::from_command("test".to_string(), &command, Path::new("."), config)
+            .unwrap()
+    }
+
+    #[test]
+    fn substitute_args_and_env() {
+        // compiled into the library too, which has no `test_util`
+        let dir =
+            std::env::temp_dir().join(format!("sushi-test-converters-{}", std::process::id()));
+        let script = dir.join("_converters/conv.sh");
+        fs::create_dir_all(script.parent().unwrap()).unwrap();
+        fs::write(
+            &script,
+            "#!/bin/sh\necho warning >&2\nprintf '%s|%s|%s|' \"$1\" \"$SUSHI_PAGE_PATH\" \"$GREETING\"\ncat\n",
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let config = ConverterConfig {
+            args: vec!["{page_path}".to_string()],
+            env: BTreeMap::from([("GREETING".to_string(), "to {output_ext}".to_string())]),
+            ..Default::default()
+        };
+        let converter = ExternalConverter::from_file("conv.sh".to_string(), &script, config);
+        let converted = convert(&converter, &dir);
+        let _ = fs::remove_dir_all(&dir);
+        // the page path is absolute, the converter running in its own directory
+        let page_path = dir.join("posts/a.md");
+        let page_path = page_path.to_string_lossy();
+        assert_eq!(
+            String::from_utf8(converted.unwrap().content).unwrap(),
+            format!("{}|{}|to html|content", page_path, page_path)
+        );
+    }
+
+    #[test]
+    fn fail_on_non_zero_exit() {
+        let converter = command("cat >/dev/null; echo oops >&2; exit 3", Default::default());
+        let error = convert(&converter, Path::new(".")).unwrap_err();
+        assert!(error.contains("3"), "{}", error);
+        assert!(error.ends_with("oops"), "{}", error);
+    }
+
+    #[test]
+    fn json_protocol() {
+        let respond = |response: &str| {
+            let config = ConverterConfig {
+                protocol: Protocol::Json,
+                env: BTreeMap::from([("RESPONSE".to_string(), response.to_string())]),
+                ..Default::default()
+            };
+            let converter = command("cat >/dev/null; printf %s \"$RESPONSE\"", config);
+            convert(&converter, Path::new("."))
+        };
+        let converted =
+            respond(r#"{"content": "<p>x</p>", "metadata": {"words": 1, "toc": [{"level": 1}]}}"#)
+                .unwrap();
+        assert_eq!(converted.content, b"<p>x</p>");
+        assert_eq!(converted.metadata["words"], serde_yaml::Value::from(1));
+        assert!(converted.metadata["toc"].is_sequence());
+        assert_eq!(
+            respond(r#"{"error": "unknown directive"}"#).unwrap_err(),
+            "unknown directive"
+        );
+        assert_eq!(
+            respond(r#"{"metadata": {}}"#).unwrap_err(),
+            "invalid response: no `content`"
+        );
+        assert!(respond("<p>x</p>")
+            .unwrap_err()
+            .starts_with("invalid response: "));
+    }
+
+    #[test]
+    fn kill_on_timeout() {
+        let config = ConverterConfig {
+            timeout: 1,
+            ..Default::default()
+        };
+        let converter = command("sleep 10", config);
+        let start = Instant::now();
+        let error = convert(&converter, Path::new(".")).unwrap_err();
+        assert_eq!(error, "timed out after 1s");
+        assert!(start.elapsed() < Duration::from_secs(5));
     }
 }
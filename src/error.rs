@@ -0,0 +1,127 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub type Result<T> = std::result::Result<T, SushiError>;
+
+/// Everything that can go wrong while building a site. Each variant carries
+/// the file it is about, and the line in that file where known.
+#[derive(Debug)]
+pub enum SushiError {
+    Config {
+        path: PathBuf,
+        line: Option<usize>,
+        message: String,
+    },
+    FrontMatter {
+        path: PathBuf,
+        line: Option<usize>,
+        message: String,
+    },
+    TemplateCompile {
+        path: PathBuf,
+        message: String,
+    },
+    Render {
+        path: PathBuf,
+        template: String,
+        message: String,
+    },
+    Converter {
+        path: PathBuf,
+        converter: String,
+        message: String,
+    },
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl SushiError {
+    pub fn io(path: &Path, source: std::io::Error) -> Self {
+        SushiError::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn config(path: &Path, message: impl ToString) -> Self {
+        SushiError::Config {
+            path: path.to_path_buf(),
+            line: None,
+            message: message.to_string(),
+        }
+    }
+}
+
+fn location(path: &Path, line: &Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{}:{}", path.to_string_lossy(), line),
+        None => path.to_string_lossy().to_string(),
+    }
+}
+
+impl fmt::Display for SushiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SushiError::Config {
+                path,
+                line,
+                message,
+            } => write!(
+                f,
+                "{}: invalid configuration: {}",
+                location(path, line),
+                message
+            ),
+            SushiError::FrontMatter {
+                path,
+                line,
+                message,
+            } => write!(
+                f,
+                "{}: invalid front matter: {}",
+                location(path, line),
+                message
+            ),
+            SushiError::TemplateCompile { path, message } => write!(
+                f,
+                "{}: cannot compile template: {}",
+                path.to_string_lossy(),
+                message
+            ),
+            SushiError::Render {
+                path,
+                template,
+                message,
+            } => write!(
+                f,
+                "{}: cannot render with template \"{}\": {}",
+                path.to_string_lossy(),
+                template,
+                message
+            ),
+            SushiError::Converter {
+                path,
+                converter,
+                message,
+            } => write!(
+                f,
+                "{}: converter \"{}\" failed: {}",
+                path.to_string_lossy(),
+                converter,
+                message
+            ),
+            SushiError::Io { path, source } => write!(f, "{}: {}", path.to_string_lossy(), source),
+        }
+    }
+}
+
+impl std::error::Error for SushiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SushiError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    // `<!-- more -->`, `<!--more-->` etc.
+    static ref MORE_RE: Regex = Regex::new(r"<!--\s*more\s*-->").unwrap();
+}
+
+/// Excerpt of rendered html: what comes before `<!-- more -->`, or the first
+/// paragraph.
+pub fn excerpt_of(html: &str) -> Option<String> {
+    match MORE_RE.find(html) {
+        Some(more) => Some(html[..more.start()].trim().to_string()).filter(|x| !x.is_empty()),
+        None => first_paragraph(html),
+    }
+}
+
+/// First paragraph of rendered html.
+pub fn first_paragraph(html: &str) -> Option<String> {
+    let start = html.find("<p>").or_else(|| html.find("<p "))?;
+    let end = html[start..].find("</p>")? + start + "</p>".len();
+    Some(html[start..end].to_string())
+}
+
+/// Cut `text` down to `length` characters, at a space if there is one in its
+/// second half (text in e.g. Chinese has none), and mark the cut with `…`.
+/// `0` keeps the whole text.
+pub fn truncate(text: &str, length: usize) -> String {
+    if length == 0 || text.chars().count() <= length {
+        return text.to_string();
+    }
+    let end = text.char_indices().nth(length).unwrap().0;
+    let mut cut = &text[..end];
+    if !text[end..].starts_with(char::is_whitespace) {
+        if let Some(space) = cut.rfind(char::is_whitespace) {
+            if cut[..space].chars().count() >= length / 2 {
+                cut = &cut[..space];
+            }
+        }
+    }
+    format!("{}…", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excerpts() {
+        assert_eq!(
+            excerpt_of("<p>a</p>\n<!--more-->\n<p>b</p>").as_deref(),
+            Some("<p>a</p>")
+        );
+        assert_eq!(
+            excerpt_of("<h1>t</h1>\n<p>a</p>\n<p>b</p>").as_deref(),
+            Some("<p>a</p>")
+        );
+        assert_eq!(truncate("hello brave new world", 14), "hello brave…");
+        assert_eq!(truncate("你好世界你好世界", 4), "你好世界…");
+        assert_eq!(truncate("short", 10), "short");
+    }
+}
@@ -1,9 +1,8 @@
-use std::cell::RefCell;
 use std::path::PathBuf;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
 
-pub type ETNodeRef = Rc<RefCell<ExistingTreeNode>>;
+pub type ETNodeRef = Arc<RwLock<ExistingTreeNode>>;
 
 #[derive(Debug)]
 #[allow(dead_code)]
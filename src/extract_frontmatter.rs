@@ -1,8 +1,9 @@
+use crate::error::{Result, SushiError};
 use log::trace;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::path::Path;
 
 enum ExtractorState {
     Start,
@@ -10,10 +11,12 @@ enum ExtractorState {
     OutYaml,
 }
 
-pub fn extract_front_matter(
-    path: &PathBuf,
-) -> (Option<HashMap<String, serde_yaml::Value>>, String) {
-    let f = File::open(path).expect("cannot open file");
+pub type FrontMatter = HashMap<String, serde_yaml::Value>;
+
+/// Front matter and content of a file, and the line of the file the content
+/// starts at.
+pub fn extract_front_matter(path: &Path) -> Result<(Option<FrontMatter>, String, usize)> {
+    let f = File::open(path).map_err(|e| SushiError::io(path, e))?;
     let f = BufReader::new(f);
     let mut front_matter = String::new();
     let mut content = String::new();
@@ -22,24 +25,30 @@ pub fn extract_front_matter(
     let space = regex::Regex::new(r"^\s*$").unwrap();
 
     let mut no_fm_flag = false;
+    // line number of the first line of front matter, for error messages
+    let mut fm_first_line = 0;
+    let mut content_first_line = 1;
 
-    for line in f.lines() {
-        let line = line.unwrap();
+    for (line_no, line) in f.lines().enumerate() {
+        let line = line.map_err(|e| SushiError::io(path, e))?;
         match state {
             ExtractorState::Start => {
                 state = if space.is_match(&line) {
                     ExtractorState::Start
                 } else if delim.is_match(&line) {
+                    fm_first_line = line_no + 2;
                     ExtractorState::InYaml
                 } else {
                     content.push_str(&line);
                     content.push('\n');
+                    content_first_line = line_no + 1;
                     no_fm_flag = true;
                     ExtractorState::OutYaml
                 }
             }
             ExtractorState::InYaml => {
                 if delim.is_match(&line) {
+                    content_first_line = line_no + 2;
                     state = ExtractorState::OutYaml;
                 } else {
                     front_matter.push_str(&line);
@@ -55,17 +64,23 @@ pub fn extract_front_matter(
     trace!("{:?}, {:?}", &front_matter, &content);
 
     // try parse toml
-    let mut fm: Option<HashMap<String, serde_yaml::Value>> = None;
+    let mut fm: Option<FrontMatter> = None;
 
     if !no_fm_flag {
         match toml::from_str(front_matter.as_str()) {
             Ok(real_fm) => fm = Some(real_fm),
-            Err(_) => {
-                fm = Some(serde_yaml::from_str(front_matter.as_str()).unwrap_or(HashMap::new()))
-            }
+            Err(_) => match serde_yaml::from_str(front_matter.as_str()) {
+                Ok(real_fm) => fm = Some(real_fm),
+                Err(e) => {
+                    return Err(SushiError::FrontMatter {
+                        path: path.to_path_buf(),
+                        line: e.location().map(|l| fm_first_line + l.line() - 1),
+                        message: e.to_string(),
+                    })
+                }
+            },
         }
-        // fm = Some(serde_yaml::from_str(front_matter.as_str()).unwrap_or(HashMap::new()))
     }
 
-    (fm, content)
+    Ok((fm, content, content_first_line))
 }
@@ -0,0 +1,525 @@
+use chrono::{DateTime, FixedOffset};
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::configuration_loader as confld;
+use crate::error::{Result, SushiError};
+use crate::excerpt;
+use crate::manifest::Inputs;
+use crate::page::Page;
+use crate::site::{BuildReport, Site, FEEDS_SOURCE};
+
+/// `feeds` section of `_site.yml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeedConfig {
+    #[serde(default = "default_formats")]
+    pub formats: Vec<FeedFormat>,
+    #[serde(default)]
+    pub content: FeedContent,
+    /// maximum number of entries of a feed
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+    /// whether to generate a feed of the whole site
+    #[serde(default = "confld::default_true")]
+    pub site: bool,
+    /// taxonomies to generate a feed of each term for
+    #[serde(default)]
+    pub taxonomies: Vec<String>,
+    /// directories (relative to the site directory) to generate a feed for
+    #[serde(default)]
+    pub dirs: Vec<String>,
+    /// title of the feeds, defaults to `site_name`
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    Atom,
+    Rss,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedContent {
+    #[default]
+    Full,
+    Summary,
+}
+
+fn default_formats() -> Vec<FeedFormat> {
+    vec![FeedFormat::Atom, FeedFormat::Rss]
+}
+
+fn default_limit() -> usize {
+    20
+}
+
+impl FeedFormat {
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            FeedFormat::Atom => "atom.xml",
+            FeedFormat::Rss => "rss.xml",
+        }
+    }
+}
+
+pub struct Feed {
+    pub title: String,
+    /// absolute url of the page the feed is about
+    pub url: String,
+    pub author: Option<String>,
+    /// time of the latest entry, or of the build if there is none
+    pub updated: DateTime<FixedOffset>,
+    pub entries: Vec<FeedEntry>,
+}
+
+pub struct FeedEntry {
+    pub title: String,
+    /// absolute url of the page
+    pub url: String,
+    pub date: DateTime<FixedOffset>,
+    pub author: Option<String>,
+    pub categories: Vec<String>,
+    pub summary: Option<String>,
+    /// rendered html, unless the feed only has summaries
+    pub content: Option<String>,
+}
+
+impl Feed {
+    /// Render the feed in the given format. `self_url` is the absolute url of
+    /// the feed itself.
+    pub fn render(&self, format: FeedFormat, self_url: &str) -> String {
+        match format {
+            FeedFormat::Atom => self.atom(self_url),
+            FeedFormat::Rss => self.rss(self_url),
+        }
+    }
+
+    pub fn atom(&self, self_url: &str) -> String {
+        let mut s = String::new();
+        s.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        s.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        element(&mut s, 1, "title", &self.title);
+        element(&mut s, 1, "id", self_url);
+        let _ = writeln!(
+            s,
+            "  <link rel=\"self\" type=\"application/atom+xml\" href=\"{}\"/>",
+            escape(self_url)
+        );
+        let _ = writeln!(
+            s,
+            "  <link rel=\"alternate\" href=\"{}\"/>",
+            escape(&self.url)
+        );
+        element(&mut s, 1, "updated", &self.updated.to_rfc3339());
+        // an author is required, either for the feed or for every entry
+        let author = self.author.as_ref().unwrap_or(&self.title);
+        let _ = writeln!(s, "  <author><name>{}</name></author>", escape(author));
+        for entry in self.entries.iter() {
+            s.push_str("  <entry>\n");
+            element(&mut s, 2, "title", &entry.title);
+            element(&mut s, 2, "id", &entry.url);
+            let _ = writeln!(
+                s,
+                "    <link rel=\"alternate\" type=\"text/html\" href=\"{}\"/>",
+                escape(&entry.url)
+            );
+            element(&mut s, 2, "published", &entry.date.to_rfc3339());
+            element(&mut s, 2, "updated", &entry.date.to_rfc3339());
+            if let Some(author) = &entry.author {
+                let _ = writeln!(s, "    <author><name>{}</name></author>", escape(author));
+            }
+            for category in entry.categories.iter() {
+                let _ = writeln!(s, "    <category term=\"{}\"/>", escape(category));
+            }
+            if let Some(summary) = &entry.summary {
+                let _ = writeln!(
+                    s,
+                    "    <summary type=\"html\">{}</summary>",
+                    escape(summary)
+                );
+            }
+            if let Some(content) = &entry.content {
+                // relative links in the content are resolved against the page
+                let _ = writeln!(
+                    s,
+                    "    <content type=\"html\" xml:base=\"{}\">{}</content>",
+                    escape(&entry.url),
+                    escape(content)
+                );
+            }
+            s.push_str("  </entry>\n");
+        }
+        s.push_str("</feed>\n");
+        s
+    }
+
+    pub fn rss(&self, self_url: &str) -> String {
+        let mut s = String::new();
+        s.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        s.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n");
+        s.push_str("  <channel>\n");
+        element(&mut s, 2, "title", &self.title);
+        element(&mut s, 2, "link", &self.url);
+        element(&mut s, 2, "description", &self.title);
+        let _ = writeln!(
+            s,
+            "    <atom:link rel=\"self\" type=\"application/rss+xml\" href=\"{}\"/>",
+            escape(self_url)
+        );
+        element(&mut s, 2, "lastBuildDate", &self.updated.to_rfc2822());
+        for entry in self.entries.iter() {
+            s.push_str("    <item>\n");
+            element(&mut s, 3, "title", &entry.title);
+            element(&mut s, 3, "link", &entry.url);
+            let _ = writeln!(
+                s,
+                "      <guid isPermaLink=\"true\">{}</guid>",
+                escape(&entry.url)
+            );
+            element(&mut s, 3, "pubDate", &entry.date.to_rfc2822());
+            for category in entry.categories.iter() {
+                element(&mut s, 3, "category", category);
+            }
+            if let Some(description) = entry.content.as_ref().or(entry.summary.as_ref()) {
+                element(&mut s, 3, "description", description);
+            }
+            s.push_str("    </item>\n");
+        }
+        s.push_str("  </channel>\n");
+        s.push_str("</rss>\n");
+        s
+    }
+}
+
+fn element(s: &mut String, indent: usize, name: &str, text: &str) {
+    let _ = writeln!(
+        s,
+        "{}<{}>{}</{}>",
+        "  ".repeat(indent),
+        name,
+        escape(text),
+        name
+    );
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // characters not allowed in XML 1.0
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Site {
+    /// Generate the feeds configured in the `feeds` section of `_site.yml`.
+    pub(crate) fn gen_feeds(&self, config: &FeedConfig, report: &mut BuildReport) {
+        let key = Path::new(FEEDS_SOURCE);
+        let inputs = Inputs {
+            source: self.site_index_hash.clone(),
+            converter: Some(self._converters_hash()),
+            site: Some(self.config_hash.clone()),
+            ..Default::default()
+        };
+        if self._skip_synthetic(key, &inputs, "feeds") {
+            return;
+        }
+
+        let title = config
+            .title
+            .clone()
+            .or_else(|| confld::string_from_config("site_name", &self.config))
+            .unwrap_or_else(|| self._gen_url(&self.gen_dir));
+        // self.pages is sorted by date, latest first
+        let pages = self
+            .pages
+            .iter()
+            .filter(|x| {
+                let page = x.read().unwrap();
+                page.paginate_info().is_none()
+                    && !matches!(page.front_matter.get("feed"), Some(Value::Bool(false)))
+            })
+            .collect_vec();
+
+        // gen dir, title, pages
+        let mut feeds = vec![];
+        if config.site {
+            feeds.push((self.gen_dir.clone(), title.clone(), pages.clone()));
+        }
+        for taxo in config.taxonomies.iter() {
+            let mut terms = self
+                .taxonomies
+                .get(taxo)
+                .map(|x| x.keys().collect_vec())
+                .unwrap_or_default();
+            terms.sort();
+            for term in terms {
+                let slug = &self.term_slugs[taxo][term];
+                let term_pages = pages
+                    .iter()
+                    .filter(|x| {
+                        x.read()
+                            .unwrap()
+                            .belongs_to_kind_or_ancestors(taxo)
+                            .contains(term)
+                    })
+                    .cloned()
+                    .collect_vec();
+                feeds.push((
+                    self.gen_dir.join(taxo).join(slug),
+                    format!("{}: {}", &title, term),
+                    term_pages,
+                ));
+            }
+        }
+        for dir in config.dirs.iter() {
+            let src_dir = self.site_dir.join(dir);
+            let dir_pages = pages
+                .iter()
+                .filter(|x| x.read().unwrap().path.starts_with(&src_dir))
+                .cloned()
+                .collect_vec();
+            feeds.push((
+                self.gen_dir.join(dir),
+                format!("{}: {}", &title, dir),
+                dir_pages,
+            ));
+        }
+
+        let tree_outputs = self._tree_outputs();
+        let author = confld::string_from_config("author", &self.config);
+        let mut outputs = vec![];
+        let mut failed = false;
+        for (dir, title, pages) in feeds {
+            let entries = pages
+                .iter()
+                .take(config.limit)
+                .map(|x| self._feed_entry(config, &x.read().unwrap()))
+                .collect::<Result<Vec<_>>>();
+            let entries = match entries {
+                Ok(entries) => entries,
+                Err(e) => {
+                    report.errors.push(e);
+                    failed = true;
+                    continue;
+                }
+            };
+            let feed = Feed {
+                title,
+                url: self._gen_url(&dir) + "/",
+                author: author.clone(),
+                updated: entries
+                    .iter()
+                    .map(|x| x.date)
+                    .max()
+                    .unwrap_or(self.build_time),
+                entries,
+            };
+            if let Err(e) = fs::create_dir_all(&dir) {
+                report.errors.push(SushiError::io(&dir, e));
+                failed = true;
+                continue;
+            }
+            for format in config.formats.iter() {
+                let path = dir.join(format.file_name());
+                let rendered = feed.render(*format, &self._gen_url(&path));
+                match Self::_write_synthetic(&tree_outputs, &path, rendered, "feed") {
+                    Ok(true) => {
+                        outputs.push(path);
+                        report.feeds += 1;
+                    }
+                    Ok(false) => (),
+                    Err(e) => {
+                        report.errors.push(SushiError::io(&path, e));
+                        failed = true;
+                    }
+                }
+            }
+        }
+        // feeds are retried in the next build if any of them failed
+        if !failed {
+            self._record(key, inputs, outputs);
+        }
+    }
+
+    fn _feed_entry(&self, config: &FeedConfig, page: &Page) -> Result<FeedEntry> {
+        let fm_string = |key: &str| match page.front_matter.get(key) {
+            Some(Value::String(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let html = self._converted_html(page)?;
+        let mut taxonomies = self.taxonomies.keys().collect_vec();
+        taxonomies.sort();
+        let summary = fm_string("description").or_else(|| fm_string("summary"));
+        let (summary, content) = match config.content {
+            FeedContent::Full => (summary, Some(html)),
+            FeedContent::Summary => (summary.or_else(|| excerpt::excerpt_of(&html)), None),
+        };
+        Ok(FeedEntry {
+            title: fm_string("title").unwrap_or_else(|| {
+                page.path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string()
+            }),
+            url: page.url.clone(),
+            date: *page.date(),
+            author: fm_string("author"),
+            categories: taxonomies
+                .iter()
+                .flat_map(|x| page.belongs_to_kind(x))
+                .collect_vec(),
+            summary,
+            content,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{in_dir, site_configs, TempDir};
+    use chrono::TimeZone;
+
+    fn site(feeds: &str) -> TempDir {
+        let dir = TempDir::new("feeds");
+        dir.write(
+            "_site.yml",
+            &format!(
+                "site_name: test\nurl: https://example.com\nconvert_ext: [md]\n\
+                 converter_choice:\n  md: __internal__\n\
+                 taxonomies: [tag]\nfeeds:\n{}",
+                feeds
+            ),
+        );
+        dir.write("_templates/page.liquid", "{{ content }}");
+        let pages = [
+            ("posts/p1", "2024-01-01", "[a]"),
+            ("posts/p2", "2024-01-02", "[a, b]"),
+            ("posts/p3", "2024-01-03", "[b]"),
+            ("notes/n1", "2024-01-04", "[a]"),
+        ];
+        for (path, date, tags) in pages {
+            dir.write(
+                &format!("{}.md", path),
+                &format!(
+                    "---\ntitle: {path}\nlayout: page\ndate: {date}\ntag: {tags}\n---\n\
+                     Text of *{path}*.\n\nSecond paragraph.\n"
+                ),
+            );
+        }
+        in_dir(dir.path(), || {
+            let mut site = Site::parse_site_dir(".".into(), false, site_configs()).unwrap();
+            let report = site.generate_site();
+            assert!(report.errors.is_empty(), "{:?}", report.errors);
+        });
+        dir
+    }
+
+    fn read(dir: &TempDir, path: &str) -> String {
+        fs::read_to_string(dir.path().join("_gen").join(path)).unwrap()
+    }
+
+    /// titles of the entries of a feed, in order
+    fn titles(feed: &str) -> Vec<&str> {
+        feed.split("<entry>")
+            .skip(1)
+            .map(|x| &x[x.find("<title>").unwrap() + 7..x.find("</title>").unwrap()])
+            .collect()
+    }
+
+    #[test]
+    fn escape_text_and_attributes() {
+        let date = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2024, 1, 1, 0, 0, 0)
+            .unwrap();
+        let feed = Feed {
+            title: "Tom & Jerry".to_string(),
+            url: "https://example.com/?a=1&b=2".to_string(),
+            author: None,
+            updated: date,
+            entries: vec![FeedEntry {
+                title: "<\"quoted\" & 'single'>".to_string(),
+                url: "https://example.com/p?x=\"1\"".to_string(),
+                date,
+                author: Some("A & B".to_string()),
+                categories: vec!["c&d".to_string()],
+                summary: None,
+                content: Some("<p>x &amp; y\u{1}</p>".to_string()),
+            }],
+        };
+        let atom = feed.atom("https://example.com/atom.xml");
+        assert!(atom.contains("<title>Tom &amp; Jerry</title>"));
+        assert!(atom.contains("href=\"https://example.com/?a=1&amp;b=2\""));
+        assert!(atom.contains("<title>&lt;&quot;quoted&quot; &amp; &apos;single&apos;&gt;</title>"));
+        assert!(atom.contains("xml:base=\"https://example.com/p?x=&quot;1&quot;\""));
+        assert!(atom.contains("<category term=\"c&amp;d\"/>"));
+        assert!(atom.contains("<name>A &amp; B</name>"));
+        assert!(atom.contains(">&lt;p&gt;x &amp;amp; y&lt;/p&gt;</content>"));
+        let rss = feed.rss("https://example.com/rss.xml");
+        assert!(rss.contains("<link>https://example.com/?a=1&amp;b=2</link>"));
+        assert!(rss.contains("<category>c&amp;d</category>"));
+        assert!(rss.contains("<description>&lt;p&gt;x &amp;amp; y&lt;/p&gt;</description>"));
+    }
+
+    #[test]
+    fn site_term_and_dir_feeds() {
+        let dir = site("  limit: 2\n  taxonomies: [tag]\n  dirs: [posts, empty]\n");
+        // latest first, at most `limit` entries
+        assert_eq!(titles(&read(&dir, "atom.xml")), ["notes/n1", "posts/p3"]);
+        assert_eq!(
+            titles(&read(&dir, "posts/atom.xml")),
+            ["posts/p3", "posts/p2"]
+        );
+        assert_eq!(
+            titles(&read(&dir, "tag/a/atom.xml")),
+            ["notes/n1", "posts/p2"]
+        );
+        assert_eq!(
+            titles(&read(&dir, "tag/b/atom.xml")),
+            ["posts/p3", "posts/p2"]
+        );
+        assert!(read(&dir, "tag/b/rss.xml").contains("<title>test: b</title>"));
+
+        // a feed without entries is updated at the time of the build
+        let atom = read(&dir, "empty/atom.xml");
+        assert!(titles(&atom).is_empty());
+        assert!(atom.contains("<updated>"));
+        assert!(read(&dir, "empty/rss.xml").contains("<lastBuildDate>"));
+    }
+
+    #[test]
+    fn full_and_summary_content() {
+        let dir = site("  formats: [atom]\n");
+        let atom = read(&dir, "atom.xml");
+        assert!(atom.contains("<content type=\"html\""));
+        assert!(atom.contains("Second paragraph."));
+        assert!(!atom.contains("<summary"));
+        assert!(!dir.path().join("_gen/rss.xml").exists());
+
+        let dir = site("  formats: [atom]\n  content: summary\n");
+        let atom = read(&dir, "atom.xml");
+        assert!(atom.contains(
+            "<summary type=\"html\">&lt;p&gt;Text of &lt;em&gt;notes/n1&lt;/em&gt;.&lt;/p&gt;</summary>"
+        ));
+        assert!(!atom.contains("Second paragraph."));
+        assert!(!atom.contains("<content"));
+    }
+}
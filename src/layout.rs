@@ -1,4 +1,4 @@
-use liquid;
+use liquid::model::{KStringRef, ValueView};
 use log::trace;
 use serde_yaml::Value;
 use std::collections::HashMap;
@@ -26,8 +26,15 @@ impl Layout {
         &self.parent
     }
 
-    pub fn render(&self, globals: &mut liquid::Object) -> Result<String, liquid::Error> {
-        if let Some(liquid::model::Value::Object(page)) = globals.get_mut("page") {
+    /// Render the layout. `globals` is shared by all pages, `locals` holds
+    /// the objects of the current page (`page`, `content`, `paginator`) and
+    /// shadows `globals`.
+    pub fn render(
+        &self,
+        globals: &liquid::Object,
+        locals: &mut liquid::Object,
+    ) -> Result<String, liquid::Error> {
+        if let Some(liquid::model::Value::Object(page)) = locals.get_mut("page") {
             let temp = liquid::object!(self.front_matter);
             page.extend(temp.iter().filter_map(|(k, v)| {
                 if k == "layout" {
@@ -37,13 +44,17 @@ impl Layout {
                 }
             }));
         } else {
-            globals.insert(
+            locals.insert(
                 "page".parse().unwrap(),
                 liquid::model::value!(self.front_matter),
             );
         }
-        let temp = serde_yaml::to_string(&globals).unwrap_or("error".to_string());
-        trace!("globals {}", temp);
-        self.template.render(globals)
+        trace!("page {:?}", locals.get("page"));
+        let scope: HashMap<KStringRef, &dyn ValueView> = globals
+            .iter()
+            .chain(locals.iter())
+            .map(|(k, v)| (k.as_ref(), v as &dyn ValueView))
+            .collect();
+        self.template.render(&scope)
     }
 }
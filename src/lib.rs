@@ -1,3 +1,4 @@
-pub mod markdown_parser;
 pub mod batch_iterator;
-pub mod converters;
\ No newline at end of file
+pub mod converters;
+pub mod markdown_parser;
+pub mod slug;
@@ -1,21 +1,42 @@
 mod batch_iterator;
+mod check;
 mod configuration_loader;
 mod converters;
+mod error;
+mod excerpt;
 mod existing_tree;
 mod extract_frontmatter;
+mod feed;
 mod layout;
+mod manifest;
 mod markdown_parser;
 mod page;
 mod paginator;
+mod permalink;
+mod reading;
+mod redirect;
+mod reproducible;
+mod search;
+mod server;
 mod site;
+mod sitemap;
+mod slug;
+mod sort;
+mod taxonomy;
+mod taxonomy_page;
+#[cfg(test)]
+mod test_util;
 mod theme;
+mod watcher;
 
+use crate::server::PreviewServer;
 use crate::site::{Site, SiteConfigs};
 use clap::{CommandFactory, Parser};
 use log::{error, info};
 use shadow_rs::shadow;
 use simple_logger::SimpleLogger;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
 
 shadow!(build);
 
@@ -45,30 +66,108 @@ enum Command {
         path: PathBuf,
     },
     Build {
-        #[clap(long, short = 'A', help = "regenerate all files")]
+        #[clap(
+            long,
+            short = 'A',
+            help = "regenerate all files, ignoring the manifest of the last build"
+        )]
         regen_all: bool,
-        #[clap(long, short = 'c', default_value = "_site.yml")]
-        config: String,
-        #[clap(long, short = 'g', help = "generated files directory (_gen)")]
-        gen: Option<String>,
-        #[clap(long, help = "includes directory (_includes)")]
-        includes: Option<String>,
-        #[clap(long, help = "converters directory (_converters)")]
-        converters: Option<String>,
-        #[clap(long, help = "templates directory (_templates)")]
-        templates: Option<String>,
-        #[clap(long, help = "theme directory")]
-        theme: Option<String>,
+        #[clap(flatten)]
+        site: SiteArgs,
         #[clap(long, short = 's', help = "generate only a subpath")]
         subpath: Option<Vec<String>>,
-        #[clap(long, help = "skip all unmodified files naively")]
-        naive_skip: bool,
+        #[clap(long, help = "remove stale files from the gen directory")]
+        prune: bool,
+        #[clap(
+            long,
+            help = "list the stale files --prune would remove, without removing them"
+        )]
+        dry_run: bool,
+        #[clap(
+            long,
+            help = "check the references in the generated pages after the build"
+        )]
+        check: bool,
+    },
+    #[clap(about = "check the references in the generated pages")]
+    Check {
+        #[clap(flatten)]
+        site: SiteArgs,
+        #[clap(
+            long,
+            help = "list the references to other sites, which are not checked"
+        )]
+        external: bool,
+    },
+    #[clap(about = "build the site and serve it locally with live reload")]
+    Serve {
+        #[clap(flatten)]
+        site: SiteArgs,
+        #[clap(long, default_value = "127.0.0.1")]
+        host: String,
+        #[clap(long, short = 'p', default_value_t = 5000)]
+        port: u16,
+        #[clap(long, help = "keep the url in _site.yml instead of the local address")]
+        keep_url: bool,
+    },
+    #[clap(about = "build the site and rebuild it on file changes")]
+    Watch {
+        #[clap(long, short = 'A', help = "regenerate all files in the first build")]
+        regen_all: bool,
+        #[clap(flatten)]
+        site: SiteArgs,
     },
 }
 
-fn initialize_site(site_name: &String, theme: &PathBuf, path: &PathBuf) {
+#[derive(clap::Args)]
+struct SiteArgs {
+    #[clap(long, short = 'c', default_value = "_site.yml")]
+    config: String,
+    #[clap(long, short = 'g', help = "generated files directory (_gen)")]
+    gen: Option<String>,
+    #[clap(long, help = "includes directory (_includes)")]
+    includes: Option<String>,
+    #[clap(long, help = "converters directory (_converters)")]
+    converters: Option<String>,
+    #[clap(long, help = "templates directory (_templates)")]
+    templates: Option<String>,
+    #[clap(long, help = "theme directory")]
+    theme: Option<String>,
+    #[clap(
+        long,
+        short = 'j',
+        help = "number of pages rendered in parallel, defaults to the number of CPUs"
+    )]
+    jobs: Option<usize>,
+    #[clap(long, help = "build pages with `draft: true`")]
+    drafts: bool,
+    #[clap(long, help = "build pages dated in the future")]
+    future: bool,
+}
+
+impl SiteArgs {
+    fn site_configs(&self) -> SiteConfigs {
+        SiteConfigs {
+            config: self.config.clone(),
+            gen: self.gen.clone(),
+            converters: self.converters.clone(),
+            includes: self.includes.clone(),
+            templates: self.templates.clone(),
+            theme: self.theme.clone(),
+            subpath: None,
+            prune: false,
+            dry_run: false,
+            drafts: self.drafts,
+            future: self.future,
+            url: None,
+            jobs: self.jobs,
+        }
+    }
+}
+
+fn initialize_site(site_name: &String, theme: &Path, path: &Path) {
     // check for existence
-    let mut path = path.clone();
+    let mut path = path.to_path_buf();
     path.push(site_name);
     if path.exists() {
         error!("path {:?} exists", &path);
@@ -76,7 +175,7 @@ fn initialize_site(site_name: &String, theme: &PathBuf, path: &PathBuf) {
     }
     // look for theme
     let original_theme = theme;
-    let mut theme = theme.clone();
+    let mut theme = theme.to_path_buf();
     if !theme.exists() {
         if let Some(project_dir) = directories::ProjectDirs::from("io", "github", "sushi-gen") {
             let mut theme_dir = PathBuf::from(project_dir.config_dir());
@@ -110,6 +209,57 @@ fn initialize_site(site_name: &String, theme: &PathBuf, path: &PathBuf) {
     }
 }
 
+/// Check the gen directory of a site, see `check::check_gen_dir`. Returns
+/// whether every reference is fine.
+fn check_site(site: &Site, list_external: bool) -> bool {
+    match check::check_gen_dir(site.gen_dir(), site.site_url().as_deref()) {
+        Ok(report) => {
+            report.print_summary(list_external);
+            report.is_ok()
+        }
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    }
+}
+
+fn serve_site(site_args: &SiteArgs, host: &str, port: u16, keep_url: bool) {
+    let addr = format!("{}:{}", host, port);
+    let site_configs = || {
+        let mut site_configs = site_args.site_configs();
+        if !keep_url {
+            site_configs.url = Some(format!("http://{}", &addr));
+        }
+        site_configs
+    };
+    let site_dir = PathBuf::from(".");
+
+    let mut build_id = None;
+
+    // the url differs from the one of `build`, which changes the hash of
+    // `_site.yml` in the manifest, so pages are regenerated anyway
+    watcher::watch_site(site_dir, site_configs, false, |site| {
+        let build_id = build_id.get_or_insert_with(|| {
+            let gen_dir = site.gen_dir().clone();
+            let base_path = site
+                .site_url()
+                .as_ref()
+                .map(|url| server::base_path_of_url(url))
+                .unwrap_or_default();
+            let preview_server = PreviewServer::new(gen_dir.clone(), base_path.clone());
+            let build_id = preview_server.build_id();
+            if let Err(e) = preview_server.spawn(&addr) {
+                error!("cannot listen on {}: {}", &addr, e);
+                std::process::exit(1);
+            }
+            println!("serving {:?} at http://{}{}/", &gen_dir, &addr, &base_path);
+            build_id
+        });
+        build_id.fetch_add(1, Ordering::SeqCst);
+    });
+}
+
 fn main() {
     let cli = Cli::parse();
     if cli.version {
@@ -145,27 +295,67 @@ fn main() {
         }
         Some(Command::Build {
             regen_all,
-            config,
-            gen,
-            includes,
-            converters,
-            templates,
-            theme,
+            site,
             subpath,
-            naive_skip,
+            prune,
+            dry_run,
+            check,
         }) => {
             let site_configs = SiteConfigs {
-                config: config.clone(),
-                gen: gen.clone(),
-                converters: converters.clone(),
-                includes: includes.clone(),
-                templates: templates.clone(),
-                theme: theme.clone(),
-                subpath: subpath.clone(),
-                naive_skip: naive_skip.clone(),
+                subpath,
+                prune,
+                dry_run,
+                ..site.site_configs()
+            };
+            let mut site = match Site::parse_site_dir(".".into(), regen_all, site_configs) {
+                Ok(site) => site,
+                Err(e) => {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }
+            };
+            let report = site.generate_site();
+            report.print_summary();
+            if dry_run {
+                for path in report.pruned.iter() {
+                    println!("would remove {}", path.to_string_lossy());
+                }
+            }
+            if !report.is_ok() {
+                std::process::exit(1);
+            }
+            if check && !check_site(&site, false) {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Check { site, external }) => {
+            let site = match Site::parse_site_dir(".".into(), false, site.site_configs()) {
+                Ok(site) => site,
+                Err(e) => {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }
             };
-            let mut site = Site::parse_site_dir(".".into(), regen_all.clone(), site_configs);
-            site.generate_site();
+            if !check_site(&site, external) {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Watch { regen_all, site }) => {
+            println!("watching for changes, press Ctrl-C to stop");
+            watcher::watch_site(
+                PathBuf::from("."),
+                || site.site_configs(),
+                regen_all,
+                |_| (),
+            );
+        }
+        Some(Command::Serve {
+            site,
+            host,
+            port,
+            keep_url,
+        }) => {
+            serve_site(&site, &host, port, keep_url);
         }
     }
 }
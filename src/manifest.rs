@@ -0,0 +1,309 @@
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::converters::Metadata;
+use crate::error::{Result, SushiError};
+use crate::reading::WordCount;
+
+pub const MANIFEST_FILE: &str = ".sushi-manifest.yml";
+
+lazy_static! {
+    // global objects describing the whole site, see `Site::generate_site`
+    static ref SITE_INDEX_RE: Regex =
+        Regex::new(r"\b(sitetree|taxo|taxo_urls|all_pages|id_to_page)\b").unwrap();
+}
+
+/// Record of the last build, stored in the gen directory. For each source
+/// it keeps the hashes of everything the outputs were generated from, so
+/// that the next build only regenerates outputs whose inputs changed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    version: String,
+    /// source path (relative to the site directory) -> entry
+    sources: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub inputs: Inputs,
+    /// output paths, relative to the gen directory
+    pub outputs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversion: Option<Conversion>,
+}
+
+/// What is kept of the converted content of a page, so that backlinks and
+/// the metadata of unchanged pages are known without converting them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Conversion {
+    /// links (`href` of `<a>`)
+    #[serde(default)]
+    pub links: Vec<String>,
+    /// metadata from the converter, e.g. `toc`
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
+    /// what comes before `<!-- more -->`, or the first paragraph. Required,
+    /// so that manifests from before excerpts are rebuilt
+    pub excerpt: String,
+    pub word_count: WordCount,
+}
+
+/// Hashes of the inputs of a source. Static files only have `source`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Inputs {
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub front_matter: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layouts: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partials: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub converter: Option<String>,
+    /// `_site.yml`, plus the site index if the page's templates use it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            sources: BTreeMap::new(),
+        }
+    }
+
+    /// Load the manifest of the last build. A missing, broken or outdated
+    /// manifest is treated as empty, i.e. everything is regenerated.
+    pub fn load(gen_dir: &Path) -> Self {
+        let path = gen_dir.join(MANIFEST_FILE);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(_) => {
+                debug!("no manifest found in {:?}", gen_dir);
+                return Self::new();
+            }
+        };
+        match serde_yaml::from_str::<Manifest>(&content) {
+            Ok(manifest) if manifest.version == env!("CARGO_PKG_VERSION") => manifest,
+            Ok(_) => {
+                debug!("manifest is written by another version of sushi, ignored");
+                Self::new()
+            }
+            Err(e) => {
+                warn!("ignore broken manifest {:?}: {}", &path, e);
+                Self::new()
+            }
+        }
+    }
+
+    pub fn save(&self, gen_dir: &Path) -> Result<()> {
+        let path = gen_dir.join(MANIFEST_FILE);
+        let content = serde_yaml::to_string(self).map_err(|e| SushiError::Io {
+            path: path.clone(),
+            source: std::io::Error::other(e),
+        })?;
+        fs::write(&path, content).map_err(|e| SushiError::io(&path, e))
+    }
+
+    pub fn get(&self, source: &str) -> Option<&ManifestEntry> {
+        self.sources.get(source)
+    }
+
+    pub fn insert(&mut self, source: String, entry: ManifestEntry) {
+        self.sources.insert(source, entry);
+    }
+
+    pub fn set_conversion(&mut self, source: &str, conversion: Conversion) {
+        if let Some(entry) = self.sources.get_mut(source) {
+            entry.conversion = Some(conversion);
+        }
+    }
+
+    /// All recorded sources, and the pseudo-sources of files generated from
+    /// the whole site.
+    pub fn sources(&self) -> impl Iterator<Item = &String> {
+        self.sources.keys()
+    }
+
+    /// All outputs, relative to the gen directory.
+    pub fn outputs(&self) -> impl Iterator<Item = &String> {
+        self.sources.values().flat_map(|x| x.outputs.iter())
+    }
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    blake3::hash(bytes).to_hex().to_string()
+}
+
+/// Hash a list of parts, each of them delimited so that moving bytes from
+/// one part to the next changes the hash.
+pub fn hash_parts<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for part in parts {
+        hasher.update(&(part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+/// Hash a front matter or configuration. Keys are sorted, since the order of
+/// a `HashMap` changes between runs.
+pub fn hash_config(config: &HashMap<String, serde_yaml::Value>) -> String {
+    let sorted: BTreeMap<&String, &serde_yaml::Value> = config.iter().collect();
+    hash_bytes(
+        serde_yaml::to_string(&sorted)
+            .unwrap_or_default()
+            .as_bytes(),
+    )
+}
+
+/// Hash the content of every file in the list, by name.
+pub fn hash_files(list: &HashMap<String, PathBuf>) -> Result<HashMap<String, String>> {
+    let mut hashes = HashMap::new();
+    for (name, path) in list.iter() {
+        let content = fs::read(path).map_err(|e| SushiError::io(path, e))?;
+        hashes.insert(name.clone(), hash_bytes(&content));
+    }
+    Ok(hashes)
+}
+
+/// Names of templates that refer to the global objects describing the whole
+/// site, either directly or through their partials. Pages using them have to
+/// be regenerated whenever any page changes.
+///
+/// The names are looked for in the text of the templates, so mentions in
+/// comments or strings count too. A template including a partial by a
+/// variable, e.g. `{% include page.widget %}`, may include any of them, and
+/// is taken to use the global objects.
+pub fn templates_using_site_index(
+    template_list: &HashMap<String, PathBuf>,
+    partial_list: &HashMap<String, PathBuf>,
+    template_partials: &HashMap<String, HashSet<String>>,
+) -> HashSet<String> {
+    let uses_index = |path: &PathBuf| match fs::read_to_string(path) {
+        Ok(content) => SITE_INDEX_RE.is_match(&content),
+        // unreadable files are reported when compiling them
+        Err(_) => true,
+    };
+    let partials: HashSet<&String> = partial_list
+        .iter()
+        .filter(|(_, path)| uses_index(path))
+        .map(|(name, _)| name)
+        .collect();
+    template_list
+        .iter()
+        .filter(|(name, path)| {
+            uses_index(path)
+                || template_partials.get(*name).is_some_and(|deps| {
+                    deps.iter()
+                        .any(|x| partials.contains(x) || !partial_list.contains_key(x))
+                })
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn parts_are_delimited() {
+        let hash = |parts: &[&str]| hash_parts(parts.iter().map(|x| x.as_bytes()));
+        assert_ne!(hash(&["ab", "c"]), hash(&["a", "bc"]));
+        assert_ne!(hash(&[]), hash(&[""]));
+        assert_eq!(hash(&["a", "b"]), hash(&["a", "b"]));
+    }
+
+    #[test]
+    fn config_hash_ignores_key_order() {
+        let keys = (0..32).map(|x| x.to_string()).collect::<Vec<_>>();
+        let mut a = HashMap::new();
+        for key in keys.iter() {
+            a.insert(key.clone(), serde_yaml::Value::from(key.as_str()));
+        }
+        let mut b = HashMap::with_capacity(1024);
+        for key in keys.iter().rev() {
+            b.insert(key.clone(), serde_yaml::Value::from(key.as_str()));
+        }
+        assert_eq!(hash_config(&a), hash_config(&b));
+        b.insert("0".to_string(), serde_yaml::Value::from("changed"));
+        assert_ne!(hash_config(&a), hash_config(&b));
+    }
+
+    #[test]
+    fn site_index_through_partials() {
+        let dir = TempDir::new("manifest");
+        dir.write("nav.liquid", "{% for x in sitetree._list %}{% endfor %}");
+        dir.write("footer.liquid", "{{ site.taxonomies }}");
+        dir.write("post.liquid", "{% include \"nav\" %}");
+        dir.write("list.liquid", "{{ all_pages | size }}");
+        dir.write("page.liquid", "{% include \"footer\" %} taxonomy");
+        dir.write("widget.liquid", "{% include page.widget %}");
+        let files = |names: &[&str]| {
+            names
+                .iter()
+                .map(|x| (x.to_string(), dir.path().join(format!("{}.liquid", x))))
+                .collect::<HashMap<_, _>>()
+        };
+        let template_partials = HashMap::from([
+            ("post".to_string(), HashSet::from(["nav".to_string()])),
+            ("page".to_string(), HashSet::from(["footer".to_string()])),
+            (
+                "widget".to_string(),
+                HashSet::from(["page.widget".to_string()]),
+            ),
+        ]);
+        let templates = templates_using_site_index(
+            &files(&["post", "list", "page", "widget"]),
+            &files(&["nav", "footer"]),
+            &template_partials,
+        );
+        // a partial chosen by a variable may use them
+        assert_eq!(
+            templates,
+            HashSet::from(["post", "list", "widget"].map(String::from))
+        );
+    }
+
+    #[test]
+    fn load_and_save() {
+        let dir = TempDir::new("manifest");
+        let mut manifest = Manifest::new();
+        let entry = ManifestEntry {
+            inputs: Inputs {
+                source: hash_bytes(b"a"),
+                ..Default::default()
+            },
+            outputs: vec!["a.html".to_string()],
+            conversion: None,
+        };
+        manifest.insert("a.md".to_string(), entry);
+        manifest.save(dir.path()).unwrap();
+        let loaded = Manifest::load(dir.path());
+        assert_eq!(loaded.get("a.md").unwrap().inputs.source, hash_bytes(b"a"));
+        assert_eq!(loaded.outputs().collect::<Vec<_>>(), vec!["a.html"]);
+
+        // manifests of other versions are ignored, and so are broken ones
+        let path = dir.path().join(MANIFEST_FILE);
+        let content = fs::read_to_string(&path).unwrap();
+        let version = format!("version: {}", env!("CARGO_PKG_VERSION"));
+        fs::write(&path, content.replace(&version, "version: 0.0.0")).unwrap();
+        assert!(Manifest::load(dir.path()).get("a.md").is_none());
+        fs::write(&path, "sources: [").unwrap();
+        assert!(Manifest::load(dir.path()).get("a.md").is_none());
+    }
+}
@@ -27,6 +27,7 @@ where
     }
 }
 
+#[allow(dead_code)]
 pub struct DummyEventProcessor;
 
 impl EventProcessor for DummyEventProcessor {
@@ -38,6 +39,7 @@ impl EventProcessor for DummyEventProcessor {
     }
 }
 
+#[allow(dead_code)]
 pub struct DebugEventProcessor {}
 
 impl EventProcessor for DebugEventProcessor {
@@ -0,0 +1,215 @@
+use serde::Serialize;
+use std::collections::HashSet;
+
+use super::event_processor::EventProcessor;
+use crate::slug::slugify;
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+/// A heading of the document, in the order they appear.
+#[derive(Debug, Clone)]
+pub struct Heading {
+    pub level: usize,
+    pub id: String,
+    /// text of the heading, without markup
+    pub title: String,
+}
+
+/// Entry of a table of contents, with the headings under it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TocEntry {
+    pub level: usize,
+    pub id: String,
+    pub title: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// Gives every heading an id made from its text, e.g. `Hello World` ->
+/// `hello-world`, with `-1`, `-2`... added to repeated ones, and optionally a
+/// link to itself (`<a class="heading-anchor">`). The headings are collected
+/// in `headings`.
+pub struct HeadingEventProcessor {
+    anchors: bool,
+    /// events of the heading being read, from its start
+    pending: Option<Vec<Event<'static>>>,
+    title: String,
+    used_ids: HashSet<String>,
+    pub headings: Vec<Heading>,
+}
+
+impl HeadingEventProcessor {
+    pub fn new(anchors: bool) -> HeadingEventProcessor {
+        HeadingEventProcessor {
+            anchors,
+            pending: None,
+            title: String::new(),
+            used_ids: HashSet::new(),
+            headings: vec![],
+        }
+    }
+
+    fn unique_id(&mut self, title: &str) -> String {
+        let mut base = slugify(title);
+        if base.is_empty() {
+            base = "section".to_string();
+        }
+        let mut id = base.clone();
+        let mut n = 0;
+        while self.used_ids.contains(&id) {
+            n += 1;
+            id = format!("{}-{}", base, n);
+        }
+        self.used_ids.insert(id.clone());
+        id
+    }
+
+    pub fn process_heading_event<'a>(&mut self, event: Event<'a>) -> Vec<Event<'a>> {
+        let Some(pending) = &mut self.pending else {
+            if let Event::Start(Tag::Heading { .. }) = event {
+                self.pending = Some(vec![event.into_static()]);
+                self.title.clear();
+                return vec![];
+            }
+            return vec![event];
+        };
+        match event {
+            Event::End(TagEnd::Heading(_)) => {
+                let mut events = self.pending.take().unwrap();
+                let title = self.title.trim().to_string();
+                let id = self.unique_id(&title);
+                if let Event::Start(Tag::Heading {
+                    level,
+                    classes,
+                    attrs,
+                    ..
+                }) = events.remove(0)
+                {
+                    events.insert(
+                        0,
+                        Event::Start(Tag::Heading {
+                            level,
+                            id: Some(id.clone().into()),
+                            classes,
+                            attrs,
+                        }),
+                    );
+                    self.headings.push(Heading {
+                        level: level as usize,
+                        id: id.clone(),
+                        title,
+                    });
+                }
+                if self.anchors {
+                    events.push(Event::InlineHtml(
+                        format!(
+                            " <a class=\"heading-anchor\" href=\"#{}\" aria-hidden=\"true\">#</a>",
+                            id
+                        )
+                        .into(),
+                    ));
+                }
+                events.push(event.into_static());
+                events
+            }
+            event => {
+                if let Event::Text(text) | Event::Code(text) | Event::InlineMath(text) = &event {
+                    self.title.push_str(text);
+                }
+                pending.push(event.into_static());
+                vec![]
+            }
+        }
+    }
+}
+
+impl EventProcessor for HeadingEventProcessor {
+    fn apply<'a>(
+        &'a mut self,
+        iter: impl Iterator<Item = Event<'a>>,
+    ) -> impl Iterator<Item = Event<'a>> {
+        iter.map(move |event| self.process_heading_event(event))
+            .flat_map(|event| event.into_iter())
+    }
+}
+
+/// Nest headings into a table of contents: a heading is under the last
+/// heading of a higher level before it.
+pub fn toc(headings: &[Heading]) -> Vec<TocEntry> {
+    let mut roots = vec![];
+    // the entries the next heading may be under, from the outermost
+    let mut open: Vec<TocEntry> = vec![];
+    let close = |open: &mut Vec<TocEntry>, roots: &mut Vec<TocEntry>| {
+        let entry = open.pop().unwrap();
+        match open.last_mut() {
+            Some(parent) => parent.children.push(entry),
+            None => roots.push(entry),
+        }
+    };
+    for heading in headings {
+        while open.last().is_some_and(|x| x.level >= heading.level) {
+            close(&mut open, &mut roots);
+        }
+        open.push(TocEntry {
+            level: heading.level,
+            id: heading.id.clone(),
+            title: heading.title.clone(),
+            children: vec![],
+        });
+    }
+    while !open.is_empty() {
+        close(&mut open, &mut roots);
+    }
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown_parser::MarkdownParser;
+
+    #[test]
+    fn heading_ids_and_toc() {
+        let parser = MarkdownParser {
+            heading_anchors: true,
+            ..MarkdownParser::new()
+        };
+        let converted = parser
+            .convert_markdown(
+                "# Intro\n## `Setup` *now*\n## 安装 Rust\n# Intro\n### !!!\n"
+                    .as_bytes()
+                    .to_vec(),
+            )
+            .unwrap();
+        let html = String::from_utf8(converted.content).unwrap();
+        assert!(html.starts_with(
+            "<h1 id=\"intro\">Intro \
+             <a class=\"heading-anchor\" href=\"#intro\" aria-hidden=\"true\">#</a></h1>"
+        ));
+        assert!(html.contains("<h2 id=\"安装-rust\">"));
+        assert!(html.contains("<h1 id=\"intro-1\">"));
+        assert!(html.contains("<h3 id=\"section\">"));
+        let toc = serde_yaml::to_string(&converted.metadata["toc"]).unwrap();
+        assert_eq!(
+            toc,
+            "- level: 1
+  id: intro
+  title: Intro
+  children:
+  - level: 2
+    id: setup-now
+    title: Setup now
+    children: []
+  - level: 2
+    id: 安装-rust
+    title: 安装 Rust
+    children: []
+- level: 1
+  id: intro-1
+  title: Intro
+  children:
+  - level: 3
+    id: section
+    title: '!!!'
+    children: []
+"
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use lazy_static::lazy_static;
 use pulldown_cmark::{CodeBlockKind, Event, Tag, TagEnd};
 use syntect::html::{ClassStyle, ClassedHTMLGenerator};
 use syntect::parsing::SyntaxSet;
@@ -5,6 +6,11 @@ use syntect::util::LinesWithEndings;
 
 use super::event_processor::EventProcessor;
 
+lazy_static! {
+    // loading the syntax definitions is slow, share them between processors
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum State {
     NotCodeBlock,
@@ -14,7 +20,13 @@ pub enum State {
 pub struct HighlightEventProcessor {
     pub state: State,
     pub language: Option<String>,
-    pub syntax_set: SyntaxSet,
+    pub syntax_set: &'static SyntaxSet,
+}
+
+impl Default for HighlightEventProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HighlightEventProcessor {
@@ -22,7 +34,7 @@ impl HighlightEventProcessor {
         HighlightEventProcessor {
             state: State::NotCodeBlock,
             language: None,
-            syntax_set: SyntaxSet::load_defaults_newlines(),
+            syntax_set: &SYNTAX_SET,
         }
     }
 
@@ -43,7 +55,7 @@ impl HighlightEventProcessor {
 
                         let mut html_generator = ClassedHTMLGenerator::new_with_class_style(
                             syntax,
-                            &self.syntax_set,
+                            self.syntax_set,
                             ClassStyle::Spaced,
                         );
                         for line in LinesWithEndings::from(code.as_str()) {
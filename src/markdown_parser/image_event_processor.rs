@@ -25,6 +25,12 @@ pub struct ImageEventProcessor {
     caption: String,
 }
 
+impl Default for ImageEventProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ImageEventProcessor {
     pub fn new() -> ImageEventProcessor {
         ImageEventProcessor {
@@ -76,8 +82,11 @@ impl ImageEventProcessor {
                     // append text to caption
                     self.caption.push_str(&t);
                     vec![]
-                } else {
+                } else if self.state == State::InParagraph {
+                    // the paragraph does not start with an image
                     self.state = State::NotImage;
+                    vec![Event::Start(Tag::Paragraph), Event::Text(t)]
+                } else {
                     vec![Event::Text(t)]
                 }
             }
@@ -122,3 +131,28 @@ impl EventProcessor for ImageEventProcessor {
             .flat_map(|event| event.into_iter())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::converters::Converter;
+    use crate::markdown_parser::MarkdownParser;
+
+    #[test]
+    fn paragraphs_around_images() {
+        let converted = MarkdownParser::new()
+            .convert(
+                "Some *text*.\n\n![caption](a.png)\n\nMore text.\n"
+                    .as_bytes()
+                    .to_vec(),
+            )
+            .unwrap();
+        let html = String::from_utf8(converted).unwrap();
+        assert_eq!(
+            html,
+            "<p>Some <em>text</em>.</p>\n\
+             <figure><img src='a.png' alt='caption' id='' title='' />\
+             <figcaption>caption</figcaption></figure>\n\
+             <p>More text.</p>\n"
+        );
+    }
+}
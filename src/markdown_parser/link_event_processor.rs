@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::event_processor::EventProcessor;
+use pulldown_cmark::{Event, Tag, TagEnd};
+
+/// What a link to a page (`[text](@page_id)`) is resolved to.
+#[derive(Debug, Clone)]
+pub struct PageLink {
+    pub url: String,
+    pub title: String,
+}
+
+pub type PageLinks = Arc<HashMap<String, PageLink>>;
+
+/// Resolves links to `@page_id` (optionally followed by `#fragment`) into
+/// the url of the page. A link without text gets the title of the page.
+/// Unknown ids are left as they are and collected in `unknown`.
+pub struct LinkEventProcessor {
+    page_links: PageLinks,
+    /// title of the page linked by the link just started, until its text
+    pending_title: Option<String>,
+    pub unknown: Vec<String>,
+}
+
+impl LinkEventProcessor {
+    pub fn new(page_links: PageLinks) -> LinkEventProcessor {
+        LinkEventProcessor {
+            page_links,
+            pending_title: None,
+            unknown: vec![],
+        }
+    }
+
+    pub fn process_link_event<'a>(&mut self, event: Event<'a>) -> Vec<Event<'a>> {
+        let pending_title = self.pending_title.take();
+        match event {
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) if dest_url.starts_with('@') => {
+                let (page_id, fragment) = match dest_url[1..].split_once('#') {
+                    Some((page_id, fragment)) => (page_id, Some(fragment)),
+                    None => (&dest_url[1..], None),
+                };
+                let dest_url = match self.page_links.get(page_id) {
+                    Some(link) => {
+                        self.pending_title = Some(link.title.clone());
+                        match fragment {
+                            Some(fragment) => format!("{}#{}", link.url, fragment).into(),
+                            None => link.url.clone().into(),
+                        }
+                    }
+                    None => {
+                        self.unknown.push(page_id.to_string());
+                        dest_url
+                    }
+                };
+                vec![Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                })]
+            }
+            Event::End(TagEnd::Link) => match pending_title {
+                Some(title) => vec![Event::Text(title.into()), event],
+                None => vec![event],
+            },
+            _ => vec![event],
+        }
+    }
+}
+
+impl EventProcessor for LinkEventProcessor {
+    fn apply<'a>(
+        &'a mut self,
+        iter: impl Iterator<Item = Event<'a>>,
+    ) -> impl Iterator<Item = Event<'a>> {
+        iter.map(move |event| self.process_link_event(event))
+            .flat_map(|event| event.into_iter())
+    }
+}
+
+/// Ids of the pages a markdown document links to, found without parsing it,
+/// e.g. to tell whether it has to be converted again after a page moved.
+pub fn linked_page_ids(content: &str) -> Vec<&str> {
+    let mut ids = vec![];
+    for (i, _) in content.match_indices('@') {
+        let before = content[..i].trim_end_matches([' ', '<']);
+        if !(before.ends_with("](") || before.ends_with("]:")) {
+            continue;
+        }
+        let rest = &content[i + 1..];
+        let end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, ')' | '>' | '#'))
+            .unwrap_or(rest.len());
+        ids.push(&rest[..end]);
+    }
+    ids
+}
+
+/// Line (counting from 0) of the first link to `@page_id` in `content`.
+pub fn link_line(content: &str, page_id: &str) -> Option<usize> {
+    let needle = format!("@{}", page_id);
+    content
+        .match_indices(&needle)
+        .find(|(i, _)| {
+            content[i + needle.len()..]
+                .chars()
+                .next()
+                .is_none_or(|c| c.is_whitespace() || matches!(c, ')' | '>' | '#'))
+        })
+        .map(|(i, _)| content[..i].matches('\n').count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converters::Converter;
+    use crate::markdown_parser::MarkdownParser;
+
+    #[test]
+    fn resolve_page_links() {
+        let page_links = Arc::new(HashMap::from([(
+            "hello".to_string(),
+            PageLink {
+                url: "/posts/hello.html".to_string(),
+                title: "Hello".to_string(),
+            },
+        )]));
+        let parser = MarkdownParser::with_page_links(page_links, 5);
+        let html = parser
+            .convert(b"[](@hello) and [intro](@hello#intro)\n".to_vec())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(html).unwrap(),
+            "<p><a href=\"/posts/hello.html\">Hello</a> and \
+             <a href=\"/posts/hello.html#intro\">intro</a></p>\n"
+        );
+        assert_eq!(
+            parser.convert(b"\n[x](@nope)\n".to_vec()).unwrap_err(),
+            "line 6: link to unknown page \"nope\""
+        );
+    }
+}
@@ -1,32 +1,69 @@
 use super::event_processor::EventProcessor;
 use katex;
-use pulldown_cmark::Event;
+use pulldown_cmark::{CowStr, Event};
 
 pub struct MathEventProcessor {
+    inline_style_opts: katex::opts::Opts,
     display_style_opts: katex::opts::Opts,
+    /// expressions katex failed on, and why
+    pub errors: Vec<(String, String)>,
+}
+
+impl Default for MathEventProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MathEventProcessor {
     pub fn new() -> MathEventProcessor {
-        let opts = katex::Opts::builder().display_mode(true).build().unwrap();
+        // malformed formulas fail the page, see `math_error`
+        let inline_opts = katex::Opts::builder().throw_on_error(true).build().unwrap();
+        let display_opts = katex::Opts::builder()
+            .display_mode(true)
+            .throw_on_error(true)
+            .build()
+            .unwrap();
         MathEventProcessor {
-            display_style_opts: opts,
+            inline_style_opts: inline_opts,
+            display_style_opts: display_opts,
+            errors: vec![],
         }
     }
 
-    pub fn process_math_event<'a>(&self, event: Event<'a>) -> Event<'a> {
+    pub fn process_math_event<'a>(&mut self, event: Event<'a>) -> Vec<Event<'a>> {
         match event {
             Event::InlineMath(math_exp) => {
-                Event::InlineHtml(katex::render(&math_exp).unwrap().into())
+                match katex::render_with_opts(&math_exp, &self.inline_style_opts) {
+                    Ok(html) => vec![Event::InlineHtml(html.into())],
+                    Err(e) => self.math_error(math_exp, e, Event::InlineHtml),
+                }
             }
-            Event::DisplayMath(math_exp) => Event::Html(
-                katex::render_with_opts(&math_exp, &self.display_style_opts)
-                    .unwrap()
-                    .into(),
-            ),
-            _ => event,
+            Event::DisplayMath(math_exp) => {
+                match katex::render_with_opts(&math_exp, &self.display_style_opts) {
+                    Ok(html) => vec![Event::Html(html.into())],
+                    Err(e) => self.math_error(math_exp, e, Event::Html),
+                }
+            }
+            _ => vec![event],
         }
     }
+
+    /// Keep the failed expression as escaped text, for the page to be
+    /// reported along with it.
+    fn math_error<'a>(
+        &mut self,
+        math_exp: CowStr<'a>,
+        e: katex::Error,
+        html: fn(CowStr<'a>) -> Event<'a>,
+    ) -> Vec<Event<'a>> {
+        self.errors.push((math_exp.to_string(), e.to_string()));
+        vec![
+            html("<code class=\"math-error\">".into()),
+            Event::Text(math_exp),
+            html("</code>".into()),
+        ]
+    }
 }
 
 impl EventProcessor for MathEventProcessor {
@@ -35,5 +72,39 @@ impl EventProcessor for MathEventProcessor {
         iter: impl Iterator<Item = Event<'a>>,
     ) -> impl Iterator<Item = Event<'a>> {
         iter.map(move |event| self.process_math_event(event))
+            .flat_map(|event| event.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_math_is_reported() {
+        let mut processor = MathEventProcessor::new();
+        processor.process_math_event(Event::InlineMath("x^2".into()));
+        assert!(processor.errors.is_empty());
+        let events = processor.process_math_event(Event::DisplayMath("\\frac{1}{".into()));
+        assert_eq!(events.len(), 3);
+        assert_eq!(processor.errors.len(), 1);
+        assert_eq!(processor.errors[0].0, "\\frac{1}{");
+    }
+
+    #[test]
+    fn failed_math_is_escaped_and_reported() {
+        let mut processor = MathEventProcessor::new();
+        let error = katex::Error::JsExecError("boom".to_string());
+        let events = processor.math_error("a<b".into(), error, Event::InlineHtml);
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events.into_iter());
+        assert_eq!(html, "<code class=\"math-error\">a&lt;b</code>");
+        assert_eq!(
+            processor.errors,
+            vec![(
+                "a<b".to_string(),
+                "failed to execute js (detail: boom)".to_string()
+            )]
+        );
     }
 }
@@ -1,12 +1,17 @@
 pub mod event_processor;
+pub mod heading_event_processor;
 pub mod highlight_event_processor;
 pub mod image_event_processor;
+pub mod link_event_processor;
 pub mod math_event_processor;
 
-use crate::converters::Converter;
+use crate::converters::{Converted, Converter, ConverterInput, Metadata};
 use event_processor::ProcessWith;
+use heading_event_processor::HeadingEventProcessor;
 use highlight_event_processor::HighlightEventProcessor;
 use image_event_processor::ImageEventProcessor;
+use itertools::Itertools;
+use link_event_processor::{LinkEventProcessor, PageLinks};
 use math_event_processor::MathEventProcessor;
 use pulldown_cmark::{Options, Parser, TextMergeStream};
 use std::cell::RefCell;
@@ -32,9 +37,18 @@ macro_rules! render_pipeline {
 pub struct MarkdownParser {
     pub with_katex: bool,
     pub with_highlight: bool,
-    math_event_processor: Box<RefCell<MathEventProcessor>>,
-    highlight_event_processor: Box<RefCell<HighlightEventProcessor>>,
-    image_event_processor: Box<RefCell<ImageEventProcessor>>,
+    /// add a link to itself to every heading
+    pub heading_anchors: bool,
+    /// pages that `[text](@page_id)` may link to
+    pub page_links: PageLinks,
+    /// line of the file the converted content starts at, for error messages
+    pub first_line: usize,
+}
+
+impl Default for MarkdownParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MarkdownParser {
@@ -42,16 +56,35 @@ impl MarkdownParser {
         MarkdownParser {
             with_katex: false,
             with_highlight: false,
-            math_event_processor: Box::new(RefCell::new(MathEventProcessor::new())),
-            highlight_event_processor: Box::new(RefCell::new(HighlightEventProcessor::new())),
-            image_event_processor: Box::new(RefCell::new(ImageEventProcessor::new())),
+            heading_anchors: false,
+            page_links: PageLinks::default(),
+            first_line: 1,
+        }
+    }
+
+    pub fn with_page_links(page_links: PageLinks, first_line: usize) -> MarkdownParser {
+        MarkdownParser {
+            page_links,
+            first_line,
+            ..Self::new()
         }
     }
 }
 
 impl Converter for MarkdownParser {
-    fn convert(&self, content: Vec<u8>) -> Vec<u8> {
-        let content_utf8 = String::from_utf8(content).unwrap();
+    fn convert(&self, content: Vec<u8>) -> Result<Vec<u8>, String> {
+        self.convert_markdown(content).map(|x| x.content)
+    }
+
+    fn convert_page(&self, input: ConverterInput) -> Result<Converted, String> {
+        self.convert_markdown(input.content)
+    }
+}
+
+impl MarkdownParser {
+    /// Convert markdown to HTML. The metadata is the table of contents, `toc`.
+    pub fn convert_markdown(&self, content: Vec<u8>) -> Result<Converted, String> {
+        let content_utf8 = String::from_utf8(content).map_err(|e| e.to_string())?;
 
         let mut options = Options::empty();
         options.insert(Options::ENABLE_MATH);
@@ -77,13 +110,64 @@ impl Converter for MarkdownParser {
         */
 
         /* new solution. seems more flexible */
+        // processors keep state while walking a document, so each conversion
+        // gets its own and pages can be converted in parallel
+        let link_event_processor = RefCell::new(LinkEventProcessor::new(self.page_links.clone()));
+        let heading_event_processor =
+            RefCell::new(HeadingEventProcessor::new(self.heading_anchors));
+        let math_event_processor = RefCell::new(MathEventProcessor::new());
+        let highlight_event_processor = RefCell::new(HighlightEventProcessor::new());
+        let image_event_processor = RefCell::new(ImageEventProcessor::new());
         let html_output = render_pipeline!(
             parser,
-            self.math_event_processor,
-            self.highlight_event_processor,
-            self.image_event_processor
+            link_event_processor,
+            heading_event_processor,
+            math_event_processor,
+            highlight_event_processor,
+            image_event_processor
         );
 
-        html_output.into_bytes()
+        let line_of = |line: Option<usize>| {
+            line.map(|x| format!("line {}: ", x + self.first_line))
+                .unwrap_or_default()
+        };
+        let unknown = &link_event_processor.borrow().unknown;
+        let errors = unknown
+            .iter()
+            .unique()
+            .map(|page_id| {
+                let line = link_event_processor::link_line(&content_utf8, page_id);
+                format!("{}link to unknown page \"{}\"", line_of(line), page_id)
+            })
+            .chain(
+                math_event_processor
+                    .borrow()
+                    .errors
+                    .iter()
+                    .map(|(math_exp, e)| {
+                        let line = content_utf8
+                            .find(math_exp.as_str())
+                            .map(|i| content_utf8[..i].matches('\n').count());
+                        format!(
+                            "{}cannot render math \"{}\": {}",
+                            line_of(line),
+                            math_exp,
+                            e
+                        )
+                    }),
+            )
+            .collect_vec();
+        if !errors.is_empty() {
+            return Err(errors.join(", "));
+        }
+
+        let toc = heading_event_processor::toc(&heading_event_processor.borrow().headings);
+        Ok(Converted {
+            content: html_output.into_bytes(),
+            metadata: Metadata::from([(
+                "toc".to_string(),
+                serde_yaml::to_value(toc).map_err(|e| e.to_string())?,
+            )]),
+        })
     }
 }
@@ -1,14 +1,17 @@
-use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeZone};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, NaiveTime};
 use itertools::Itertools;
-use std::cell::RefCell;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 use std::path::PathBuf;
-use std::rc::Rc;
-use std::time::SystemTime;
+use std::sync::{Arc, RwLock};
 
-pub type PageRef = Rc<RefCell<Page>>;
+use crate::converters::Metadata;
+use crate::paginator::PaginateConfig;
+use crate::reproducible::Zone;
+use crate::taxonomy;
+
+pub type PageRef = Arc<RwLock<Page>>;
 pub type PageId = String;
 
 #[derive(Debug, Clone)]
@@ -18,13 +21,26 @@ pub struct Page {
     // pub other_attributes: HashMap<String, serde_yaml::Value>,
     pub url: String,
     pub path: PathBuf,
-    date: chrono::DateTime<Local>,
+    date: DateTime<FixedOffset>,
     next: Option<PageRef>,
     last: Option<PageRef>,
+    /// pages linking to this one, latest first
+    backlinks: Vec<PageId>,
+    /// pages sharing taxonomy terms with this one, most shared first
+    related: Vec<PageId>,
+    /// metadata from the converter, e.g. `toc`
+    metadata: Metadata,
+    /// html excerpt of the content, and its plain text
+    excerpt: String,
+    summary_text: String,
+    /// words of the converted content, and minutes it takes to read them
+    word_count: usize,
+    reading_time: usize,
     page_id: PageId,
     pub to_ext: Option<String>,
     pub content: String,
-    gen_time: SystemTime,
+    /// line of the source file `content` starts at
+    pub content_line: usize,
     pub gen_path: PathBuf,
 }
 
@@ -35,30 +51,11 @@ impl Page {
         path: PathBuf,
         to_ext: Option<String>,
         content: String,
-        gen_time: SystemTime,
+        content_line: usize,
         gen_path: PathBuf,
     ) -> Self {
-        // get or gen date
-        let date = if let Some(serde_yaml::Value::String(date)) = front_matter.get("date") {
-            match chrono::DateTime::parse_from_rfc3339(date) {
-                Ok(date) => DateTime::<Local>::from(date),
-                Err(_) => match chrono::NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S") {
-                    Ok(date) => Local.from_local_datetime(&date).unwrap(),
-                    Err(_) => match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
-                        Ok(date) => Local
-                            .from_local_datetime(&NaiveDateTime::new(
-                                date,
-                                NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
-                            ))
-                            .unwrap(),
-                        Err(_) => DateTime::<Local>::from(SystemTime::now()),
-                    },
-                },
-            }
-        } else {
-            // debug!("date is not defined in front_matter, use system time");
-            chrono::DateTime::from(chrono::DateTime::<Local>::from(SystemTime::now()))
-        };
+        let date =
+            Self::parse_date(&front_matter, Zone::Local).unwrap_or_else(|| Zone::Local.now());
         // debug!("date: {}", date);
         // get or gen id
         let page_id = if let Some(serde_yaml::Value::String(id)) = front_matter.get("page_id") {
@@ -75,15 +72,46 @@ impl Page {
             date,
             next: None,
             last: None,
+            backlinks: vec![],
+            related: vec![],
+            metadata: Metadata::new(),
+            excerpt: String::new(),
+            summary_text: String::new(),
+            word_count: 0,
+            reading_time: 0,
             page_id,
             path,
             to_ext,
             content,
-            gen_time,
+            content_line,
             gen_path,
         }
     }
 
+    /// Date of a page from its front matter, if it has a valid one.
+    pub fn parse_date(
+        front_matter: &HashMap<String, serde_yaml::Value>,
+        zone: Zone,
+    ) -> Option<DateTime<FixedOffset>> {
+        if let Some(serde_yaml::Value::String(date)) = front_matter.get("date") {
+            match chrono::DateTime::parse_from_rfc3339(date) {
+                Ok(date) => Some(zone.at(&date)),
+                Err(_) => match chrono::NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S") {
+                    Ok(date) => zone.at_naive(&date),
+                    Err(_) => match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                        Ok(date) => zone.at_naive(&NaiveDateTime::new(
+                            date,
+                            NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
+                        )),
+                        Err(_) => None,
+                    },
+                },
+            }
+        } else {
+            None
+        }
+    }
+
     pub fn get_page_config_object(&self) -> serde_yaml::Mapping {
         serde_yaml::Mapping::from_iter(
             self.get_page_config()
@@ -99,45 +127,72 @@ impl Page {
                 .iter()
                 .map(|(k, v)| (k.clone(), v.clone())),
         );
-        if config.get("url") == None {
+        if !config.contains_key("url") {
             config.insert(
                 "url".to_string(),
                 serde_yaml::Value::String(self.url.clone()),
             );
         }
-        if config.get("date") == None {
+        if !config.contains_key("date") {
             config.insert(
                 "date".to_string(),
                 serde_yaml::Value::String(self.date.to_rfc3339()),
             );
         }
-        if config.get("page_id") == None {
+        if !config.contains_key("page_id") {
             config.insert(
                 "page_id".to_string(),
                 serde_yaml::Value::String(self.page_id.clone()),
             );
         }
-        if config.get("next") == None {
+        if !config.contains_key("next") {
             if let Some(next) = &self.next {
                 config.insert(
                     "next".to_string(),
-                    serde_yaml::Value::String(next.borrow().get_page_id().clone()),
+                    serde_yaml::Value::String(next.read().unwrap().get_page_id().clone()),
                 );
             }
         }
-        if config.get("last") == None {
+        if !config.contains_key("last") {
             if let Some(last) = &self.last {
                 config.insert(
                     "last".to_string(),
-                    serde_yaml::Value::String(last.borrow().get_page_id().clone()),
+                    serde_yaml::Value::String(last.read().unwrap().get_page_id().clone()),
                 );
             }
         }
-        if config.get("search_text") == None {
-            config.insert(
-                "search_text".to_string(),
-                serde_yaml::Value::String(self.content.clone()),
-            );
+        for (key, ids) in [("backlinks", &self.backlinks), ("related", &self.related)] {
+            if !config.contains_key(key) {
+                config.insert(
+                    key.to_string(),
+                    serde_yaml::Value::Sequence(
+                        ids.iter()
+                            .map(|x| serde_yaml::Value::String(x.clone()))
+                            .collect(),
+                    ),
+                );
+            }
+        }
+        for (key, value) in [
+            ("excerpt", &self.excerpt),
+            ("summary_text", &self.summary_text),
+        ] {
+            if !config.contains_key(key) {
+                config.insert(key.to_string(), serde_yaml::Value::String(value.clone()));
+            }
+        }
+        for (key, value) in [
+            ("word_count", self.word_count),
+            ("reading_time", self.reading_time),
+        ] {
+            if !config.contains_key(key) {
+                config.insert(key.to_string(), serde_yaml::Value::from(value as u64));
+            }
+        }
+        for (key, value) in self.metadata.iter() {
+            if !config.contains_key(key) {
+                config.insert(key.clone(), value.clone());
+            }
         }
         config.insert(
             "path".to_string(),
@@ -170,47 +225,115 @@ impl Page {
         &self.last
     }
 
-    pub fn date(&self) -> &chrono::DateTime<Local> {
+    pub fn set_backlinks(&mut self, backlinks: Vec<PageId>) {
+        self.backlinks = backlinks;
+    }
+
+    pub fn set_related(&mut self, related: Vec<PageId>) {
+        self.related = related;
+    }
+
+    pub fn set_metadata(&mut self, metadata: Metadata) {
+        self.metadata = metadata;
+    }
+
+    pub fn set_date(&mut self, date: DateTime<FixedOffset>) {
+        self.date = date;
+    }
+
+    pub fn set_excerpt(&mut self, excerpt: String, summary_text: String) {
+        self.excerpt = excerpt;
+        self.summary_text = summary_text;
+    }
+
+    pub fn set_reading(&mut self, word_count: usize, reading_time: usize) {
+        self.word_count = word_count;
+        self.reading_time = reading_time;
+    }
+
+    pub fn backlinks(&self) -> &Vec<PageId> {
+        &self.backlinks
+    }
+
+    pub fn related(&self) -> &Vec<PageId> {
+        &self.related
+    }
+
+    pub fn date(&self) -> &DateTime<FixedOffset> {
         &self.date
     }
 
-    pub fn gen_time(&self) -> &SystemTime {
-        &self.gen_time
+    pub fn is_draft(&self) -> bool {
+        matches!(
+            self.front_matter.get("draft"),
+            Some(serde_yaml::Value::Bool(true))
+        )
+    }
+
+    /// Whether the page is scheduled after `now`. Pages without a `date` in
+    /// their front matter are never in the future.
+    pub fn is_future(&self, now: &DateTime<FixedOffset>) -> bool {
+        self.front_matter.contains_key("date") && self.date > *now
     }
 
-    pub fn paginate_info(&self) -> Option<(String, usize)> {
-        match (
-            self.front_matter.get("paginate"),
-            self.front_matter.get("paginate_batch"),
-        ) {
-            (Some(serde_yaml::Value::String(exp)), Some(serde_yaml::Value::Number(num))) => {
-                if num.is_u64() {
-                    Some((exp.clone(), num.as_u64().unwrap() as usize))
-                } else {
-                    None
+    /// `paginate` of the front matter, with the size of batches from
+    /// `paginate_batch` unless it has its own. Pages without a size of
+    /// batches are not paginated.
+    pub fn paginate_info(&self) -> Option<Result<PaginateConfig, String>> {
+        let paginate = self.front_matter.get("paginate")?;
+        let mut config = match paginate {
+            serde_yaml::Value::String(exp) => PaginateConfig::from_items(exp.clone()),
+            x => match serde_yaml::from_value::<PaginateConfig>(x.clone()) {
+                Ok(config) => config,
+                Err(e) => return Some(Err(format!("invalid `paginate`: {}", e))),
+            },
+        };
+        if let Some(path) = config.path.as_ref().filter(|x| !x.contains(":n")) {
+            return Some(Err(format!(
+                "invalid `paginate`: \"{}\" has no \":n\"",
+                path
+            )));
+        }
+        if config.batch.is_none() {
+            config.batch = match self.front_matter.get("paginate_batch").map(|x| x.as_u64()) {
+                Some(Some(n)) => Some(n as usize),
+                Some(None) => return Some(Err("invalid `paginate_batch`".to_string())),
+                // `paginate: <expression>` alone is a list, not a pagination
+                None if paginate.is_string() => return None,
+                None => {
+                    return Some(Err(
+                        "invalid `paginate`: no `batch`, and no `paginate_batch`".to_string(),
+                    ))
                 }
-            }
-            (_, _) => None,
+            };
         }
+        Some(Ok(config))
     }
 
-    pub fn belongs_to_kind(&self, taxo: &String) -> Vec<String> {
-        if let Some(t) = self.front_matter.get(taxo) {
-            if let serde_yaml::Value::Sequence(sq) = t {
-                sq.iter()
-                    .filter_map(|x| {
-                        if let serde_yaml::Value::String(s) = x {
-                            Some(s.clone())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect_vec()
-            } else {
-                vec![]
+    /// Terms of the page in a taxonomy, a list or a single term, e.g.
+    /// `tags: [rust, web]` or `category: dev/rust`.
+    pub fn belongs_to_kind(&self, taxo: &str) -> Vec<String> {
+        let term = |x: &serde_yaml::Value| match x {
+            serde_yaml::Value::String(s) => taxonomy::normalize(s),
+            serde_yaml::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        };
+        match self.front_matter.get(taxo) {
+            Some(serde_yaml::Value::Sequence(sq)) => {
+                sq.iter().filter_map(term).unique().collect_vec()
             }
-        } else {
-            vec![]
+            Some(x) => term(x).into_iter().collect_vec(),
+            None => vec![],
         }
     }
+
+    /// Terms of the page along with their ancestors, which list the pages of
+    /// their nested terms too.
+    pub fn belongs_to_kind_or_ancestors(&self, taxo: &str) -> Vec<String> {
+        self.belongs_to_kind(taxo)
+            .iter()
+            .flat_map(|x| taxonomy::with_ancestors(x).map(String::from).collect_vec())
+            .unique()
+            .collect_vec()
+    }
 }
@@ -1,8 +1,164 @@
 use crate::batch_iterator::BatchIterator;
+use crate::page::Page;
+use crate::sort::SortOrder;
+use crate::taxonomy;
 use liquid::model::Value;
-use std::path::PathBuf;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::slice::Iter;
 
+/// `paginate` of the front matter of a page: the expression of a list, or a
+/// map of the list, how to filter, sort and limit it, and where the batches
+/// go.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PaginateConfig {
+    /// expression of the list, e.g. `sitetree.posts._list`
+    #[serde(default = "default_items")]
+    pub items: String,
+    /// taxonomy -> term the pages must have, or one of its nested terms
+    #[serde(default)]
+    pub terms: BTreeMap<String, String>,
+    /// directory (relative to the site directory) the pages must be in
+    #[serde(default)]
+    pub dir: Option<String>,
+    /// front matter the pages must have: each key equal to its value, or
+    /// a list containing it
+    #[serde(default, rename = "where")]
+    pub where_: BTreeMap<String, serde_yaml::Value>,
+    #[serde(default)]
+    pub sort: Option<SortOrder>,
+    /// items kept after filtering and sorting
+    #[serde(default)]
+    pub limit: Option<usize>,
+    /// items of a batch, `0` for a single batch; `paginate_batch` if unset
+    #[serde(default)]
+    pub batch: Option<usize>,
+    /// url pattern of the batches after the first, with `:n` the index of
+    /// the batch (from 1, the first batch being the page itself); relative to
+    /// the url of the page unless it starts with `/`
+    #[serde(default)]
+    pub path: Option<String>,
+    /// batches before and after the current one in `paginator.window`
+    #[serde(default = "default_window")]
+    pub window: usize,
+}
+
+fn default_items() -> String {
+    "all_pages".to_string()
+}
+
+/// Batches before and after the current one in `paginator.window`, unless
+/// `paginate` sets its own.
+pub const DEFAULT_WINDOW: usize = 2;
+
+fn default_window() -> usize {
+    DEFAULT_WINDOW
+}
+
+impl PaginateConfig {
+    /// `paginate: <expression>`
+    pub fn from_items(items: String) -> Self {
+        Self {
+            items,
+            terms: BTreeMap::new(),
+            dir: None,
+            where_: BTreeMap::new(),
+            sort: None,
+            limit: None,
+            batch: None,
+            path: None,
+            window: default_window(),
+        }
+    }
+
+    /// Whether items are page ids to filter or sort.
+    pub fn selects_pages(&self) -> bool {
+        !self.terms.is_empty()
+            || self.dir.is_some()
+            || !self.where_.is_empty()
+            || self.sort.is_some()
+    }
+
+    /// Whether a page, at `rel_path` of the site directory, passes the
+    /// filters.
+    pub fn matches(&self, page: &Page, rel_path: &Path) -> bool {
+        let has_term = |(taxo, term): (&String, &String)| {
+            taxonomy::normalize(term)
+                .is_some_and(|term| page.belongs_to_kind_or_ancestors(taxo).contains(&term))
+        };
+        let has_value =
+            |(key, value): (&String, &serde_yaml::Value)| match page.front_matter.get(key) {
+                Some(serde_yaml::Value::Sequence(sq)) if !value.is_sequence() => sq.contains(value),
+                Some(x) => x == value,
+                None => value.is_null(),
+            };
+        self.terms.iter().all(has_term)
+            && self
+                .dir
+                .as_ref()
+                .is_none_or(|dir| rel_path.starts_with(dir.trim_matches('/')))
+            && self.where_.iter().all(has_value)
+    }
+}
+
+/// Value of `globals` at `expression`: keys separated by `.`, or in brackets
+/// for keys with dots, e.g. `taxo.tag["node.js"]`, and indexes of lists,
+/// e.g. `site.groups.0.items`.
+pub fn resolve<'a>(globals: &'a liquid::Object, expression: &str) -> Result<&'a Value, String> {
+    let keys = parse_expression(expression)?;
+    let mut keys = keys.iter();
+    let first = keys
+        .next()
+        .ok_or(format!("empty expression \"{}\"", expression))?;
+    let mut value = globals
+        .get(first.as_str())
+        .ok_or(format!("no \"{}\" in \"{}\"", first, expression))?;
+    for key in keys {
+        let next = match value {
+            Value::Object(obj) => obj.get(key.as_str()),
+            Value::Array(arr) => key.parse::<usize>().ok().and_then(|i| arr.get(i)),
+            _ => None,
+        };
+        value = next.ok_or(format!("no \"{}\" in \"{}\"", key, expression))?;
+    }
+    Ok(value)
+}
+
+fn parse_expression(expression: &str) -> Result<Vec<String>, String> {
+    let invalid = || format!("invalid expression \"{}\"", expression);
+    let mut keys = vec![];
+    let mut rest = expression.trim();
+    while !rest.is_empty() {
+        if let Some(bracketed) = rest.strip_prefix('[') {
+            let end = bracketed.find(']').ok_or_else(invalid)?;
+            let key = bracketed[..end].trim();
+            let key = key
+                .strip_prefix('"')
+                .and_then(|x| x.strip_suffix('"'))
+                .or_else(|| key.strip_prefix('\'').and_then(|x| x.strip_suffix('\'')))
+                .unwrap_or(key);
+            keys.push(key.to_string());
+            rest = &bracketed[end + 1..];
+        } else {
+            let end = rest.find(['.', '[']).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            keys.push(rest[..end].to_string());
+            rest = &rest[end..];
+        }
+        if let Some(after_dot) = rest.strip_prefix('.') {
+            if after_dot.is_empty() || after_dot.starts_with(['.', '[']) {
+                return Err(invalid());
+            }
+            rest = after_dot;
+        }
+    }
+    Ok(keys)
+}
+
 pub struct Paginator {
     seq: Vec<Value>,
     batch_size: usize,
@@ -12,54 +168,44 @@ pub struct Paginator {
 
 #[allow(dead_code)]
 impl Paginator {
-    pub fn new(seq: &Vec<Value>, batch_size: usize, base_path: PathBuf) -> Self {
-        let batch_paths =
-            Self::gen_batch_paths(Self::calc_batch_num(seq.len(), batch_size), &base_path);
+    /// Split `seq` into batches of `batch_size` items, a single batch if
+    /// `batch_size` is `0`. An empty `seq` still has a batch.
+    pub fn new(seq: &[Value], batch_size: usize, base_path: PathBuf, pretty: bool) -> Self {
+        let batch_size = match batch_size {
+            0 => seq.len().max(1),
+            n => n,
+        };
+        let batch_paths = Self::gen_batch_paths(
+            Self::calc_batch_num(seq.len(), batch_size),
+            &base_path,
+            pretty,
+        );
         Self {
-            seq: seq.clone(),
+            seq: seq.to_vec(),
             batch_size,
             base_path,
             batch_paths,
         }
     }
-    pub fn from_expression_and_object<'a>(
-        globals: &liquid::Object,
-        expression: &'a String,
-        batch_size: usize,
-        base_path: PathBuf,
-    ) -> Result<Self, &'a str> {
-        let exp_spl = expression.split('.');
-        let mut temp = globals;
-        let mut seq = vec![];
-        let mut flag = false;
-        for key in exp_spl {
-            if flag {
-                return Err("Invalid expression of array");
-            }
-            match temp.get(key) {
-                Some(liquid::model::Value::Object(obj)) => {
-                    temp = obj;
-                }
-                Some(liquid::model::Value::Array(arr)) => {
-                    flag = true;
-                    seq.extend(arr.iter().map(|x| x.clone()));
-                }
-                _ => return Err("Invalid expression of array"),
-            }
+    /// Put the batches after the first at `batch_path(i)`.
+    pub fn with_batch_paths(mut self, batch_path: impl Fn(usize) -> PathBuf) -> Self {
+        for (i, path) in self.batch_paths.iter_mut().enumerate().skip(1) {
+            *path = batch_path(i);
         }
-        let batch_paths =
-            Self::gen_batch_paths(Self::calc_batch_num(seq.len(), batch_size), &base_path);
-        Ok(Self {
-            seq,
-            batch_size,
-            base_path,
-            batch_paths,
-        })
+        self
     }
     pub fn batch_iter(&self) -> BatchIterator<Iter<'_, Value>, Iter<'_, PathBuf>> {
         let temp = self.seq.iter().clone();
         BatchIterator::new(temp, self.batch_paths.iter(), self.batch_size)
     }
+    /// Gen path and items of every batch.
+    pub fn batches(&self) -> Vec<(&PathBuf, Vec<&Value>)> {
+        let mut batches = self.batch_iter().collect::<Vec<_>>();
+        if batches.is_empty() {
+            batches.push((&self.batch_paths[0], vec![]));
+        }
+        batches
+    }
     pub fn base_url_dir(&self) -> PathBuf {
         let mut temp_prefix = PathBuf::from(&self.base_path);
         let stem: String = temp_prefix
@@ -72,22 +218,36 @@ impl Paginator {
         temp_prefix
     }
     pub fn calc_batch_num(seq_len: usize, batch_size: usize) -> usize {
-        (seq_len as f64 / batch_size as f64).ceil() as usize
+        ((seq_len as f64 / batch_size as f64).ceil() as usize).max(1)
     }
     pub fn batch_num(&self) -> usize {
         Self::calc_batch_num(self.seq.len(), self.batch_size)
     }
-    pub fn gen_paginator_object(&self) -> liquid::Object {
+    pub fn gen_paginator_object(&self, batch_urls: &[String]) -> liquid::Object {
         liquid::object!({
             "items": self.seq,
+            "item_num": self.seq.len(),
             "batch_num": self.batch_num(),
+            "batch_urls": batch_urls,
+            "first_url": batch_urls.first(),
+            "last_url": batch_urls.last(),
         })
     }
     pub fn batch_paths(&self) -> &Vec<PathBuf> {
         &self.batch_paths
     }
-    fn gen_batch_paths(batch_num: usize, base_path: &PathBuf) -> Vec<PathBuf> {
+    /// Batches of `a/b.html` are `a/b/1.html`, `a/b/2.html`, ..., or with
+    /// `pretty`, batches of `a/index.html` are `a/1/index.html`, ...
+    fn gen_batch_paths(batch_num: usize, base_path: &PathBuf, pretty: bool) -> Vec<PathBuf> {
         let mut batch_paths = vec![base_path.clone()];
+        if pretty {
+            let dir = base_path.parent().unwrap_or(base_path);
+            let file_name = base_path.file_name().unwrap_or_default();
+            for i in 1..batch_num {
+                batch_paths.push(dir.join(i.to_string()).join(file_name));
+            }
+            return batch_paths;
+        }
         let mut temp_prefix = PathBuf::from(base_path);
         let stem: String = temp_prefix
             .file_stem()
@@ -103,4 +263,160 @@ impl Paginator {
         }
         batch_paths
     }
+
+    /// Point the `paginator` object at the `i`th batch, with the numbers of
+    /// the batches within `window` of it.
+    pub fn set_current_batch(
+        paginator_object: &mut liquid::Object,
+        i: usize,
+        batch: &[&Value],
+        batch_urls: &[String],
+        window: usize,
+    ) {
+        let batch_num = batch_urls.len();
+        // missing batches are nil, so that templates may use them unguarded
+        let mut set = |key: &str, value: Option<Value>| {
+            paginator_object.insert(key.to_string().into(), value.unwrap_or(Value::Nil));
+        };
+        let prev = i.checked_sub(1);
+        let next = Some(i + 1).filter(|x| *x < batch_num);
+        let num = |x: usize| Value::scalar(x as i64);
+        let url = |x: usize| Value::scalar(batch_urls[x].clone());
+        set(
+            "current_batch",
+            Some(liquid::model::to_value(&batch).unwrap()),
+        );
+        set("current_batch_num", Some(num(i)));
+        set("current_url", Some(url(i)));
+        set("prev_batch_num", prev.map(num));
+        // `last_batch_num` has always been the previous batch
+        set("last_batch_num", prev.map(num));
+        set("next_batch_num", next.map(num));
+        set("prev_url", prev.map(url));
+        set("next_url", next.map(url));
+        let window = (i.saturating_sub(window)..(i + window + 1).min(batch_num))
+            .map(|x| {
+                Value::Object(liquid::object!({
+                    "num": x,
+                    "url": batch_urls[x],
+                    "current": x == i,
+                }))
+            })
+            .collect::<Vec<_>>();
+        set("window", Some(Value::Array(window)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_expressions() {
+        let globals = liquid::object!({
+            "taxo": { "tag": { "node.js": ["a", "b"] } },
+            "site": { "groups": [{ "items": [1, 2, 3] }] },
+        });
+        let len = |x: &str| match resolve(&globals, x) {
+            Ok(Value::Array(arr)) => Ok(arr.len()),
+            _ => Err(()),
+        };
+        assert_eq!(len("taxo.tag[\"node.js\"]"), Ok(2));
+        assert_eq!(len("site.groups.0.items"), Ok(3));
+        assert_eq!(len("site['groups'][0].items"), Ok(3));
+        assert!(resolve(&globals, "taxo.tag.node").is_err());
+        assert!(resolve(&globals, "taxo.tag[\"node.js\"].0.x").is_err());
+        assert!(resolve(&globals, "site..groups").is_err());
+    }
+
+    #[test]
+    fn batches_are_numbered_by_index() {
+        let seq = (0..5).map(Value::scalar).collect::<Vec<_>>();
+        let p = Paginator::new(&seq, 2, PathBuf::from("a/b.html"), false);
+        assert_eq!(
+            p.batch_paths(),
+            &[
+                PathBuf::from("a/b.html"),
+                PathBuf::from("a/b/1.html"),
+                PathBuf::from("a/b/2.html")
+            ]
+        );
+        let p = Paginator::new(&seq, 2, PathBuf::from("a/index.html"), true);
+        assert_eq!(
+            p.batch_paths(),
+            &[
+                PathBuf::from("a/index.html"),
+                PathBuf::from("a/1/index.html"),
+                PathBuf::from("a/2/index.html")
+            ]
+        );
+
+        let urls = ["/a/", "/a/1/", "/a/2/"].map(String::from);
+        let mut object = p.gen_paginator_object(&urls);
+        let batch = p.batches()[1].1.clone();
+        Paginator::set_current_batch(&mut object, 1, &batch, &urls, 1);
+        assert_eq!(object["current_batch_num"], Value::scalar(1));
+        assert_eq!(object["current_url"], Value::scalar("/a/1/"));
+        assert_eq!(object["prev_batch_num"], Value::scalar(0));
+        assert_eq!(object["next_batch_num"], Value::scalar(2));
+        let Value::Array(window) = &object["window"] else {
+            panic!("no window");
+        };
+        let nums = window
+            .iter()
+            .map(|x| match x {
+                Value::Object(x) => x["num"].clone(),
+                _ => Value::Nil,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(nums, [0, 1, 2].map(Value::scalar));
+    }
+
+    #[test]
+    fn empty_seq_has_a_batch() {
+        let p = Paginator::new(&[], 5, PathBuf::from("a/index.html"), true);
+        assert_eq!(p.batch_num(), 1);
+        assert_eq!(p.batches().len(), 1);
+    }
+
+    #[test]
+    fn batch_size_is_required() {
+        let batch = |front_matter: &str| {
+            let page = Page::new(
+                serde_yaml::from_str(front_matter).unwrap(),
+                "/index.html".to_string(),
+                PathBuf::from("index.md"),
+                Some("html".to_string()),
+                String::new(),
+                1,
+                PathBuf::from("_gen/index.html"),
+            );
+            page.paginate_info().map(|x| x.map(|x| x.batch))
+        };
+        // no pagination without a size of batches
+        assert_eq!(batch("paginate: all_pages"), None);
+        assert!(matches!(
+            batch("paginate: {items: all_pages}"),
+            Some(Err(_))
+        ));
+        // from `paginate_batch`
+        assert_eq!(
+            batch("paginate: all_pages\npaginate_batch: 4"),
+            Some(Ok(Some(4)))
+        );
+        assert_eq!(
+            batch("paginate: {dir: posts}\npaginate_batch: 4"),
+            Some(Ok(Some(4)))
+        );
+        // from `batch`, which takes precedence
+        assert_eq!(batch("paginate: {batch: 0}"), Some(Ok(Some(0))));
+        assert_eq!(
+            batch("paginate: {batch: 3}\npaginate_batch: 4"),
+            Some(Ok(Some(3)))
+        );
+        assert!(matches!(
+            batch("paginate: all_pages\npaginate_batch: many"),
+            Some(Err(_))
+        ));
+    }
 }
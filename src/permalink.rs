@@ -0,0 +1,178 @@
+use chrono::{DateTime, Datelike, FixedOffset};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::slug::slugify;
+
+/// Permalink patterns of `_site.yml`: `permalink` for the whole site, and
+/// `permalinks` for directories (relative to the site directory).
+#[derive(Debug, Default)]
+pub struct Permalinks {
+    global: Option<String>,
+    /// sorted by depth, deepest first
+    dirs: Vec<(PathBuf, String)>,
+}
+
+impl Permalinks {
+    pub fn new(global: Option<String>, dirs: HashMap<String, String>) -> Self {
+        let mut dirs = dirs
+            .into_iter()
+            .map(|(dir, pattern)| (PathBuf::from(dir.trim_matches('/')), pattern))
+            .collect::<Vec<_>>();
+        dirs.sort_by(|a, b| {
+            b.0.components()
+                .count()
+                .cmp(&a.0.components().count())
+                .then(a.0.cmp(&b.0))
+        });
+        Self { global, dirs }
+    }
+
+    /// Pattern for a page in `dir` (relative to the site directory), from the
+    /// closest directory that has one, or the global one.
+    pub fn pattern_for(&self, dir: &Path) -> Option<&String> {
+        self.dirs
+            .iter()
+            .find(|(x, _)| dir.starts_with(x))
+            .map(|(_, pattern)| pattern)
+            .or(self.global.as_ref())
+    }
+}
+
+/// Values of the placeholders of a pattern.
+pub struct PermalinkVars<'a> {
+    pub date: &'a DateTime<FixedOffset>,
+    pub slug: String,
+    pub title: Option<&'a str>,
+    /// directory of the page relative to the site directory
+    pub path: &'a Path,
+    pub stem: &'a str,
+    pub ext: &'a str,
+}
+
+/// Url path of a page, e.g. `/2024/01/hello-world/`, and the path of the
+/// generated file relative to the gen directory, e.g.
+/// `2024/01/hello-world/index.html`.
+pub struct Permalink {
+    pub url_path: String,
+    pub gen_path: PathBuf,
+}
+
+/// Expand a pattern such as `/:year/:month/:slug/`. A pattern ending with `/`
+/// generates `index.<ext>` in that directory; a pattern whose last part has
+/// no extension gets `.<ext>`.
+pub fn expand(pattern: &str, vars: &PermalinkVars) -> Result<Permalink, String> {
+    expand_with(pattern, vars.ext, |name| {
+        Some(match name {
+            "year" => format!("{:04}", vars.date.year()),
+            "month" => format!("{:02}", vars.date.month()),
+            "day" => format!("{:02}", vars.date.day()),
+            "slug" => vars.slug.clone(),
+            "title" => match vars.title.map(slugify) {
+                Some(title) if !title.is_empty() => title,
+                _ => vars.slug.clone(),
+            },
+            "path" => vars
+                .path
+                .components()
+                .map(|x| x.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            "stem" => vars.stem.to_string(),
+            "ext" => vars.ext.to_string(),
+            _ => return None,
+        })
+    })
+}
+
+/// Expand a pattern like `expand`, with the placeholders `value_of` knows.
+pub fn expand_with(
+    pattern: &str,
+    ext: &str,
+    value_of: impl Fn(&str) -> Option<String>,
+) -> Result<Permalink, String> {
+    let mut expanded = String::new();
+    let mut rest = pattern;
+    while let Some(i) = rest.find(':') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let name_len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        let name = &rest[..name_len];
+        rest = &rest[name_len..];
+        let value = value_of(name).ok_or(format!(
+            "unknown placeholder \":{}\" in \"{}\"",
+            name, pattern
+        ))?;
+        expanded.push_str(&value);
+    }
+    expanded.push_str(rest);
+
+    // placeholders may be empty, e.g. `:path` of a page in the site directory
+    let parts = expanded
+        .split('/')
+        .filter(|x| !x.is_empty() && *x != ".")
+        .collect::<Vec<_>>();
+    if parts.contains(&"..") {
+        return Err(format!("\"{}\" points outside the site", expanded));
+    }
+    let mut url_path = String::from("/") + &parts.join("/");
+    let mut gen_path = parts.iter().collect::<PathBuf>();
+    if expanded.ends_with('/') || parts.is_empty() {
+        if !parts.is_empty() {
+            url_path.push('/');
+        }
+        gen_path.push(format!("index.{}", ext));
+    } else if !parts.last().unwrap().contains('.') {
+        url_path = url_path + "." + ext;
+        gen_path.set_extension(ext);
+    }
+    Ok(Permalink { url_path, gen_path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn expand_patterns() {
+        let date = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2024, 3, 9, 0, 0, 0)
+            .unwrap();
+        let vars = PermalinkVars {
+            date: &date,
+            slug: "hello".to_string(),
+            title: Some("Hello, World!"),
+            path: Path::new("posts/2024"),
+            stem: "2024-03-09-hello",
+            ext: "html",
+        };
+        let expanded = |pattern| {
+            let permalink = expand(pattern, &vars).unwrap();
+            (permalink.url_path, permalink.gen_path)
+        };
+        assert_eq!(
+            expanded("/:year/:month/:day/:title/"),
+            (
+                "/2024/03/09/hello-world/".to_string(),
+                PathBuf::from("2024/03/09/hello-world/index.html")
+            )
+        );
+        assert_eq!(
+            expanded("/:path/:slug"),
+            (
+                "/posts/2024/hello.html".to_string(),
+                PathBuf::from("posts/2024/hello.html")
+            )
+        );
+        assert_eq!(
+            expanded("/"),
+            ("/".to_string(), PathBuf::from("index.html"))
+        );
+        assert!(expand("/:unknown/", &vars).is_err());
+        assert!(expand("/../:slug/", &vars).is_err());
+    }
+}
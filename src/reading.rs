@@ -0,0 +1,95 @@
+use serde::{Deserialize, Serialize};
+
+use crate::search::is_cjk;
+
+/// `reading_speed` section of `_site.yml`, for `page.reading_time`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReadingSpeed {
+    /// words per minute
+    #[serde(default = "default_words")]
+    pub words: usize,
+    /// CJK characters per minute
+    #[serde(default = "default_cjk")]
+    pub cjk: usize,
+}
+
+fn default_words() -> usize {
+    200
+}
+
+fn default_cjk() -> usize {
+    400
+}
+
+impl Default for ReadingSpeed {
+    fn default() -> Self {
+        Self {
+            words: default_words(),
+            cjk: default_cjk(),
+        }
+    }
+}
+
+impl ReadingSpeed {
+    /// Minutes it takes to read `count`, rounded up. Any text takes at least
+    /// a minute.
+    pub fn minutes(&self, count: &WordCount) -> usize {
+        let minutes = count.words as f64 / self.words.max(1) as f64
+            + count.cjk as f64 / self.cjk.max(1) as f64;
+        minutes.ceil() as usize
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct WordCount {
+    pub words: usize,
+    /// CJK characters, which are not separated by spaces and count one by one
+    pub cjk: usize,
+}
+
+impl WordCount {
+    pub fn total(&self) -> usize {
+        self.words + self.cjk
+    }
+}
+
+/// Count the words of plain text. Words are separated by spaces or CJK
+/// characters, and have a letter or a digit.
+pub fn count_words(text: &str) -> WordCount {
+    let mut count = WordCount::default();
+    let mut in_word = false;
+    for c in text.chars() {
+        if is_cjk(c) {
+            count.cjk += 1;
+            in_word = false;
+        } else if c.is_whitespace() {
+            in_word = false;
+        } else if c.is_alphanumeric() && !in_word {
+            count.words += 1;
+            in_word = true;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_and_time() {
+        let count = count_words("Hello, world! don't -- 静态网站 v2.0");
+        assert_eq!(count, WordCount { words: 4, cjk: 4 });
+        let speed = ReadingSpeed::default();
+        assert_eq!(speed.minutes(&count), 1);
+        assert_eq!(
+            speed.minutes(&WordCount {
+                words: 400,
+                cjk: 401
+            }),
+            4
+        );
+        assert_eq!(speed.minutes(&WordCount::default()), 0);
+    }
+}
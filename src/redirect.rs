@@ -0,0 +1,294 @@
+use itertools::Itertools;
+use log::info;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::SushiError;
+use crate::manifest::Inputs;
+use crate::site::{BuildReport, Site, REDIRECTS_SOURCE};
+
+pub const DEFAULT_REDIRECT: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{{ page.title | default: redirect_url | escape }}</title>
+<link rel=\"canonical\" href=\"{{ redirect_url | escape }}\">
+<meta name=\"robots\" content=\"noindex\">
+<meta http-equiv=\"refresh\" content=\"0; url={{ redirect_url | escape }}\">
+</head>
+<body>
+<p>Moved to <a href=\"{{ redirect_url | escape }}\">{{ redirect_url | escape }}</a>.</p>
+</body>
+</html>
+";
+
+/// `redirects` section of `_site.yml`. `redirects: true` uses the defaults.
+/// Redirect pages are generated for `aliases` anyway, this only adds a file
+/// of server-side redirects.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedirectsConfig {
+    /// path of the file, relative to the gen directory
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
+fn default_path() -> String {
+    "_redirects".to_string()
+}
+
+impl Default for RedirectsConfig {
+    fn default() -> Self {
+        Self {
+            path: default_path(),
+        }
+    }
+}
+
+/// An old url of a page, from `aliases` in its front matter.
+pub struct Alias {
+    /// url path, e.g. `/2023/old-name/`
+    pub url_path: String,
+    /// path of the redirect page relative to the gen directory, e.g.
+    /// `2023/old-name/index.html`
+    pub gen_path: PathBuf,
+}
+
+/// Parse an alias such as `/old/post.html` or `/old/post/`. An alias whose
+/// last part has no extension is a directory, redirected by its
+/// `index.html`.
+pub fn parse_alias(alias: &str) -> Result<Alias, String> {
+    let parts = alias
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .split('/')
+        .filter(|x| !x.is_empty() && *x != ".")
+        .collect::<Vec<_>>();
+    if parts.is_empty() {
+        return Err(format!("alias \"{}\" is the root of the site", alias));
+    }
+    if parts.contains(&"..") {
+        return Err(format!("alias \"{}\" points outside the site", alias));
+    }
+    let mut url_path = String::from("/") + &parts.join("/");
+    let mut gen_path = parts.iter().collect::<PathBuf>();
+    if alias.ends_with('/') || !parts.last().unwrap().contains('.') {
+        url_path.push('/');
+        gen_path.push("index.html");
+    }
+    Ok(Alias { url_path, gen_path })
+}
+
+/// File of server-side redirects, one `<from> <to> 301` per line, as read by
+/// e.g. Netlify and Cloudflare Pages.
+pub fn redirects_file(redirects: &[(String, String)]) -> String {
+    let mut s = String::new();
+    for (from, to) in redirects {
+        let _ = writeln!(s, "{} {} 301", from, to);
+    }
+    s
+}
+
+impl Site {
+    /// Generate a redirect page for each of the `aliases` of every page, and
+    /// the file of server-side redirects if `redirects` is set.
+    pub(crate) fn gen_redirects(&self, globals: &liquid::Object, report: &mut BuildReport) {
+        let mut pages = self.pages.iter().map(|x| x.read().unwrap()).collect_vec();
+        pages.sort_by(|a, b| a.path.cmp(&b.path));
+        // page, aliases
+        let mut aliased = vec![];
+        for page in pages {
+            let aliases = match page.front_matter.get("aliases") {
+                None => continue,
+                Some(Value::String(alias)) => vec![alias.clone()],
+                Some(Value::Sequence(aliases)) => {
+                    let aliases = aliases
+                        .iter()
+                        .map(|x| x.as_str().map(String::from))
+                        .collect::<Option<Vec<_>>>();
+                    match aliases {
+                        Some(aliases) => aliases,
+                        None => {
+                            report.errors.push(SushiError::FrontMatter {
+                                path: page.path.clone(),
+                                line: None,
+                                message: "`aliases` should be a list of strings".to_string(),
+                            });
+                            continue;
+                        }
+                    }
+                }
+                Some(_) => {
+                    report.errors.push(SushiError::FrontMatter {
+                        path: page.path.clone(),
+                        line: None,
+                        message: "`aliases` should be a list of strings".to_string(),
+                    });
+                    continue;
+                }
+            };
+            aliased.push((page, aliases));
+        }
+        if aliased.is_empty() && self.redirects.is_none() {
+            return;
+        }
+
+        let key = Path::new(REDIRECTS_SOURCE);
+        let inputs = Inputs {
+            source: self.site_index_hash.clone(),
+            layouts: self.template_hashes.get("redirect").cloned(),
+            site: Some(self.config_hash.clone()),
+            ..Default::default()
+        };
+        if self._skip_synthetic(key, &inputs, "redirects") {
+            return;
+        }
+
+        let tree_outputs = self._tree_outputs();
+        let parser = match self.templates.get("redirect") {
+            Some(_) => None,
+            None => match liquid::ParserBuilder::with_stdlib()
+                .build()
+                .and_then(|x| x.parse(DEFAULT_REDIRECT))
+            {
+                Ok(template) => Some(template),
+                Err(e) => {
+                    report
+                        .errors
+                        .push(Self::_render_error(&self.gen_dir, "redirect", e));
+                    return;
+                }
+            },
+        };
+        // gen path -> page redirected from it
+        let mut alias_sources: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let mut redirects = vec![];
+        let mut outputs = vec![];
+        let mut failed = false;
+        for (page, aliases) in aliased {
+            for alias in aliases {
+                let front_matter_error = |message: String| SushiError::FrontMatter {
+                    path: page.path.clone(),
+                    line: None,
+                    message,
+                };
+                let parsed = match parse_alias(&alias) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        report.errors.push(front_matter_error(e));
+                        failed = true;
+                        continue;
+                    }
+                };
+                let path = self.gen_dir.join(&parsed.gen_path);
+                if tree_outputs.contains(&path) || alias_sources.contains_key(&path) {
+                    let source = alias_sources
+                        .get(&path)
+                        .or_else(|| self.page_gen_paths.get(&path))
+                        .map(|x| x.to_string_lossy().to_string())
+                        .unwrap_or_else(|| "a static file".to_string());
+                    report.errors.push(front_matter_error(format!(
+                        "alias \"{}\" collides with {}, generated from {}",
+                        alias,
+                        path.to_string_lossy(),
+                        source
+                    )));
+                    failed = true;
+                    continue;
+                }
+                alias_sources.insert(path.clone(), page.path.clone());
+
+                let alias_url = self._site_url_of(parsed.url_path);
+                let mut locals = liquid::object!({
+                    "page": page.get_page_config(),
+                    "redirect_url": page.url,
+                    "alias_url": alias_url,
+                });
+                let rendered = match &parser {
+                    Some(template) => template.render(&locals),
+                    None => self.templates["redirect"].render(globals, &mut locals),
+                };
+                let rendered = match rendered {
+                    Ok(rendered) => rendered,
+                    Err(e) => {
+                        report
+                            .errors
+                            .push(Self::_render_error(&page.path, "redirect", e));
+                        failed = true;
+                        continue;
+                    }
+                };
+                info!("[>>redi]  {} -> {}", path.to_string_lossy(), &page.url);
+                let written = path
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| fs::write(&path, rendered));
+                match written {
+                    Ok(_) => {
+                        report.redirects += 1;
+                        redirects.push((Self::_url_path_of(&alias_url), page.url.clone()));
+                        outputs.push(path);
+                    }
+                    Err(e) => {
+                        report.errors.push(SushiError::io(&path, e));
+                        failed = true;
+                    }
+                }
+            }
+        }
+
+        if let Some(config) = &self.redirects {
+            let path = self.gen_dir.join(&config.path);
+            let content = redirects_file(&redirects);
+            match Self::_write_synthetic(&tree_outputs, &path, content, "site") {
+                Ok(true) => outputs.push(path),
+                Ok(false) => (),
+                Err(e) => {
+                    report.errors.push(SushiError::io(&path, e));
+                    failed = true;
+                }
+            }
+        }
+        if failed {
+            // retried in the next build, which withdraws the redirects of
+            // both builds that are gone by then
+            if let Some(entry) = self.manifest.get(REDIRECTS_SOURCE) {
+                outputs.extend(entry.outputs.iter().map(|x| self.gen_dir.join(x)));
+            }
+            self._record(key, Inputs::default(), outputs);
+        } else {
+            self._record(key, inputs, outputs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_aliases() {
+        let parsed = |alias| {
+            let alias = parse_alias(alias).unwrap();
+            (alias.url_path, alias.gen_path)
+        };
+        assert_eq!(
+            parsed("/old/post.html"),
+            ("/old/post.html".to_string(), PathBuf::from("old/post.html"))
+        );
+        assert_eq!(
+            parsed("old/post"),
+            (
+                "/old/post/".to_string(),
+                PathBuf::from("old/post/index.html")
+            )
+        );
+        assert!(parse_alias("/").is_err());
+        assert!(parse_alias("/../x/").is_err());
+    }
+}
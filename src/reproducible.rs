@@ -0,0 +1,174 @@
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Timelike, Utc};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use subprocess::{Exec, Redirection};
+
+/// `SOURCE_DATE_EPOCH` of the environment, the time reproducible builds
+/// are made at.
+pub fn source_date_epoch() -> Result<Option<DateTime<Utc>>, String> {
+    let Ok(value) = std::env::var("SOURCE_DATE_EPOCH") else {
+        return Ok(None);
+    };
+    value
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .and_then(|x| Utc.timestamp_opt(x, 0).single())
+        .map(Some)
+        .ok_or(format!("invalid SOURCE_DATE_EPOCH \"{}\"", value))
+}
+
+/// Timezone of the dates of a site: `timezone` of `_site.yml`, else UTC in
+/// reproducible builds, so that the output does not depend on `TZ`, and the
+/// local timezone otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl Zone {
+    pub fn utc() -> Zone {
+        Zone::Fixed(FixedOffset::east_opt(0).unwrap())
+    }
+
+    /// `UTC`, or an offset such as `+08:00`.
+    pub fn parse(zone: &str) -> Result<Zone, String> {
+        let zone = zone.trim();
+        if zone.eq_ignore_ascii_case("utc") || zone == "Z" {
+            return Ok(Zone::utc());
+        }
+        zone.parse::<FixedOffset>().map(Zone::Fixed).map_err(|_| {
+            format!(
+                "invalid timezone \"{}\", expected UTC or an offset like +08:00",
+                zone
+            )
+        })
+    }
+
+    /// `time` in the timezone.
+    pub fn at<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Local => time.with_timezone(&Local).fixed_offset(),
+            Zone::Fixed(offset) => time.with_timezone(offset),
+        }
+    }
+
+    /// A date and time without offset, taken in the timezone.
+    pub fn at_naive(&self, naive: &NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Zone::Local => Local
+                .from_local_datetime(naive)
+                .earliest()
+                .map(|x| x.fixed_offset()),
+            Zone::Fixed(offset) => offset.from_local_datetime(naive).single(),
+        }
+    }
+
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        self.at(&Utc::now())
+    }
+}
+
+/// Times of the sources of a site, for pages without a `date`: the time of
+/// the last commit changing them if the site is in a git repository, else
+/// their modification time. Modification times after `SOURCE_DATE_EPOCH`
+/// are clamped to it.
+pub struct SourceTimes {
+    site_dir: PathBuf,
+    zone: Zone,
+    epoch: Option<DateTime<Utc>>,
+    /// path relative to the site directory -> time of its last commit
+    commits: HashMap<PathBuf, DateTime<Utc>>,
+}
+
+impl SourceTimes {
+    pub fn new(site_dir: &Path, zone: Zone, epoch: Option<DateTime<Utc>>) -> SourceTimes {
+        SourceTimes {
+            site_dir: site_dir.to_path_buf(),
+            zone,
+            epoch,
+            commits: Self::_commit_times(site_dir),
+        }
+    }
+
+    fn _commit_times(site_dir: &Path) -> HashMap<PathBuf, DateTime<Utc>> {
+        let mut commits = HashMap::new();
+        // latest commits first, each a NUL and its time followed by the files
+        // it changed
+        let output = Exec::cmd("git")
+            .args(&["-c", "core.quotePath=false", "log", "--format=%x00%ct"])
+            .args(&["--name-only", "--relative", "--", "."])
+            .cwd(site_dir)
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture();
+        let output = match output {
+            Ok(output) if output.success() => output,
+            _ => return commits,
+        };
+        let mut time = None;
+        for line in output.stdout_str().lines().filter(|x| !x.is_empty()) {
+            match line.strip_prefix('\0') {
+                Some(timestamp) => {
+                    time = timestamp
+                        .parse::<i64>()
+                        .ok()
+                        .and_then(|x| Utc.timestamp_opt(x, 0).single())
+                }
+                None => {
+                    if let Some(time) = time {
+                        commits.entry(PathBuf::from(line)).or_insert(time);
+                    }
+                }
+            }
+        }
+        commits
+    }
+
+    /// Time of the latest commit, or else of the newest file of the site
+    /// outside `gen_dir`.
+    pub fn latest(&self, gen_dir: &Path) -> DateTime<Utc> {
+        if let Some(time) = self.commits.values().max() {
+            return *time;
+        }
+        let mut latest = DateTime::<Utc>::default();
+        let mut dirs = vec![self.site_dir.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if entry.file_name().to_string_lossy().starts_with('.') || path == gen_dir {
+                    continue;
+                }
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if let Some(time) = self._modified(&path) {
+                    latest = latest.max(time);
+                }
+            }
+        }
+        latest
+    }
+
+    pub fn time_of(&self, path: &Path) -> DateTime<FixedOffset> {
+        let rel_path = path.strip_prefix(&self.site_dir).unwrap_or(path);
+        let time = match self.commits.get(rel_path) {
+            Some(time) => *time,
+            None => self._modified(path).unwrap_or_default(),
+        };
+        self.zone.at(&time)
+    }
+
+    fn _modified(&self, path: &Path) -> Option<DateTime<Utc>> {
+        let time = fs::metadata(path)
+            .and_then(|x| x.modified())
+            .map(DateTime::<Utc>::from)
+            .ok()
+            .and_then(|x| x.with_nanosecond(0))?;
+        Some(match self.epoch {
+            Some(epoch) => time.min(epoch),
+            None => time,
+        })
+    }
+}
@@ -0,0 +1,338 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use crate::configuration_loader as confld;
+use crate::error::SushiError;
+use crate::manifest::Inputs;
+use crate::site::{BuildReport, Site, SEARCH_SOURCE};
+
+/// Version of the format of the search index, bumped on incompatible changes.
+pub const SEARCH_INDEX_VERSION: u32 = 1;
+
+/// `search` section of `_site.yml`. `search: true` uses the defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchConfig {
+    /// path of the index, relative to the gen directory
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// whether to include the plain text of pages, e.g. to show snippets
+    #[serde(default = "confld::default_true")]
+    pub text: bool,
+}
+
+fn default_path() -> String {
+    "search.json".to_string()
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            path: default_path(),
+            text: true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchIndex {
+    pub version: u32,
+    pub pages: Vec<SearchPage>,
+    /// token -> indices of the pages containing it, in ascending order
+    pub index: BTreeMap<String, Vec<usize>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchPage {
+    pub title: String,
+    pub url: String,
+    /// taxonomy -> terms
+    pub terms: BTreeMap<String, Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+impl SearchIndex {
+    /// Build the index of the pages, `texts` being their plain text.
+    pub fn new(pages: Vec<SearchPage>, texts: &[String], with_text: bool) -> Self {
+        let mut index: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        let mut pages = pages;
+        for (i, (page, text)) in pages.iter_mut().zip(texts.iter()).enumerate() {
+            let mut tokens = BTreeSet::new();
+            tokens.extend(tokenize(&page.title));
+            for term in page.terms.values().flatten() {
+                tokens.extend(tokenize(term));
+            }
+            tokens.extend(tokenize(text));
+            for token in tokens {
+                index.entry(token).or_default().push(i);
+            }
+            if with_text {
+                page.text = Some(text.clone());
+            }
+        }
+        Self {
+            version: SEARCH_INDEX_VERSION,
+            pages,
+            index,
+        }
+    }
+}
+
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30ff     // Hiragana, Katakana
+        | 0x3400..=0x4dbf   // CJK Extension A
+        | 0x4e00..=0x9fff   // CJK Unified Ideographs
+        | 0xac00..=0xd7af   // Hangul Syllables
+        | 0xf900..=0xfaff   // CJK Compatibility Ideographs
+        | 0x20000..=0x2ffff // CJK Extension B and later
+    )
+}
+
+/// Split text into search tokens. Runs of letters and digits become lowercase
+/// words, runs of CJK characters (which are not separated by spaces) become
+/// overlapping pairs of characters, e.g. `静态网站` -> `静态`, `态网`, `网站`.
+/// A single CJK character is a token by itself. Queries have to be split the
+/// same way.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut cjk_run: Vec<char> = vec![];
+    let flush_cjk = |run: &mut Vec<char>, tokens: &mut Vec<String>| {
+        if run.len() == 1 {
+            tokens.push(run[0].to_string());
+        }
+        for pair in run.windows(2) {
+            tokens.push(pair.iter().collect());
+        }
+        run.clear();
+    };
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            cjk_run.push(c);
+        } else if c.is_alphanumeric() {
+            flush_cjk(&mut cjk_run, &mut tokens);
+            word.extend(c.to_lowercase());
+        } else {
+            flush_cjk(&mut cjk_run, &mut tokens);
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+        }
+    }
+    flush_cjk(&mut cjk_run, &mut tokens);
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+/// Plain text of rendered html. Tags, scripts, styles and MathML (KaTeX
+/// renders formulas twice) are dropped, and whitespace is collapsed.
+pub fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut skip: Option<String> = None;
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let Some(end) = rest.find('>') else {
+                break;
+            };
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+            let closing = tag.starts_with('/');
+            let name = tag
+                .trim_start_matches('/')
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or("")
+                .to_lowercase();
+            match &skip {
+                Some(skipped) => {
+                    if closing && *skipped == name {
+                        skip = None;
+                    }
+                }
+                None => {
+                    if !closing
+                        && !tag.ends_with('/')
+                        && matches!(name.as_str(), "script" | "style" | "math")
+                    {
+                        skip = Some(name.clone());
+                    }
+                }
+            }
+            // other tags may separate words, e.g. `<td>a</td><td>b</td>`
+            if !matches!(
+                name.as_str(),
+                "a" | "abbr"
+                    | "b"
+                    | "code"
+                    | "del"
+                    | "em"
+                    | "i"
+                    | "kbd"
+                    | "mark"
+                    | "s"
+                    | "small"
+                    | "span"
+                    | "strong"
+                    | "sub"
+                    | "sup"
+            ) {
+                text.push(' ');
+            }
+            continue;
+        }
+        rest = &rest[c.len_utf8()..];
+        if skip.is_some() {
+            continue;
+        }
+        if c == '&' {
+            if let Some(end) = rest.find(';').filter(|x| *x <= 10) {
+                if let Some(decoded) = decode_entity(&rest[..end]) {
+                    text.push(decoded);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        text.push(c);
+    }
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Decode the character references of html text, e.g. in attribute values.
+pub fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        decoded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(end) = rest.find(';').filter(|x| *x <= 10) {
+            if let Some(c) = decode_entity(&rest[..end]) {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+        decoded.push('&');
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = if let Some(hex) = entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+            {
+                u32::from_str_radix(hex, 16).ok()?
+            } else {
+                entity.strip_prefix('#')?.parse().ok()?
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+impl Site {
+    /// Generate the search index of all pages, see `SearchIndex`.
+    pub(crate) fn gen_search_index(&self, config: &SearchConfig, report: &mut BuildReport) {
+        let key = Path::new(SEARCH_SOURCE);
+        let inputs = Inputs {
+            source: self.site_index_hash.clone(),
+            converter: Some(self._converters_hash()),
+            site: Some(self.config_hash.clone()),
+            ..Default::default()
+        };
+        if self._skip_synthetic(key, &inputs, "search index") {
+            return;
+        }
+
+        let mut taxonomies = self.taxonomies.keys().collect_vec();
+        taxonomies.sort();
+        let mut pages = self.pages.iter().map(|x| x.read().unwrap()).collect_vec();
+        pages.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut search_pages = vec![];
+        let mut texts = vec![];
+        for page in pages {
+            if matches!(page.front_matter.get("search"), Some(Value::Bool(false))) {
+                continue;
+            }
+            let text = match self._converted_html(&page) {
+                Ok(html) => html_to_text(&html),
+                Err(e) => {
+                    report.errors.push(e);
+                    return;
+                }
+            };
+            let title = match page.front_matter.get("title") {
+                Some(Value::String(title)) => title.clone(),
+                _ => String::new(),
+            };
+            search_pages.push(SearchPage {
+                title,
+                url: page.url.clone(),
+                terms: taxonomies
+                    .iter()
+                    .map(|x| ((*x).clone(), page.belongs_to_kind(x)))
+                    .filter(|(_, terms)| !terms.is_empty())
+                    .collect(),
+                text: None,
+            });
+            texts.push(text);
+        }
+        let index = SearchIndex::new(search_pages, &texts, config.text);
+
+        let path = self.gen_dir.join(&config.path);
+        let written = serde_json::to_string(&index)
+            .map_err(std::io::Error::other)
+            .and_then(|x| Self::_write_synthetic(&self._tree_outputs(), &path, x, "site"));
+        match written {
+            Ok(true) => self._record(key, inputs, vec![path]),
+            Ok(false) => (),
+            Err(e) => report.errors.push(SushiError::io(&path, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_mixed() {
+        assert_eq!(
+            tokenize("Sushi 是一个静态网站生成器, v0.2"),
+            vec![
+                "sushi", "是一", "一个", "个静", "静态", "态网", "网站", "站生", "生成", "成器",
+                "v0", "2"
+            ]
+        );
+        assert_eq!(tokenize("中 Rust"), vec!["中", "rust"]);
+    }
+
+    #[test]
+    fn strip_html() {
+        assert_eq!(
+            html_to_text("<h1>Title</h1>\n<p>a &amp; b<script>x()</script><br/>c</p>"),
+            "Title a & b c"
+        );
+    }
+}
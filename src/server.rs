@@ -0,0 +1,303 @@
+use log::{debug, error, info, warn};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Request, Response, Server};
+
+/// Path polled by the live-reload script, under the base path.
+const LIVE_RELOAD_PATH: &str = "/__sushi/livereload";
+
+/// `{url}` is replaced with the base path and `LIVE_RELOAD_PATH`.
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+  var build = null;
+  setInterval(function () {
+    fetch("{url}", { cache: "no-store" })
+      .then(function (r) { return r.text(); })
+      .then(function (id) {
+        if (build !== null && build !== id) { location.reload(); }
+        build = id;
+      })
+      .catch(function () {});
+  }, 1000);
+})();
+</script>
+"#;
+
+/// A tiny static file server for previewing `_gen`.
+///
+/// Every HTML response gets a live-reload script injected, which polls the
+/// current build id and refreshes the tab once it changes.
+pub struct PreviewServer {
+    gen_dir: PathBuf,
+    base_path: String,
+    live_reload_path: String,
+    live_reload_script: String,
+    build_id: Arc<AtomicUsize>,
+}
+
+impl PreviewServer {
+    /// `base_path` is the path component of the site url (e.g. `/blog` for
+    /// `https://example.com/blog`), which is stripped from request urls.
+    pub fn new(gen_dir: PathBuf, base_path: String) -> Self {
+        let base_path = base_path.trim_end_matches('/').to_string();
+        let live_reload_path = format!("{}{}", base_path, LIVE_RELOAD_PATH);
+        Self {
+            gen_dir,
+            live_reload_script: LIVE_RELOAD_SCRIPT.replace("{url}", &live_reload_path),
+            live_reload_path,
+            base_path,
+            build_id: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Handle shared with the build loop. Bump it after each rebuild.
+    pub fn build_id(&self) -> Arc<AtomicUsize> {
+        self.build_id.clone()
+    }
+
+    pub fn spawn(self, addr: &str) -> std::io::Result<thread::JoinHandle<()>> {
+        let server = Server::http(addr)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::AddrInUse, e.to_string()))?;
+        info!("[ serve] http://{}{}/", addr, self.base_path);
+        Ok(thread::spawn(move || {
+            for request in server.incoming_requests() {
+                self.handle(request);
+            }
+        }))
+    }
+
+    fn handle(&self, request: Request) {
+        let url = request.url().to_string();
+        let path = url.split(['?', '#']).next().unwrap_or("").to_string();
+        debug!("[ serve] {} {}", request.method(), &path);
+
+        if path == self.live_reload_path {
+            let id = self.build_id.load(Ordering::SeqCst).to_string();
+            let response = Response::from_string(id)
+                .with_header(content_type_header("text/plain; charset=utf-8"))
+                .with_header(Header::from_bytes("Cache-Control", "no-store").unwrap());
+            respond(request, response);
+            return;
+        }
+
+        let file = match self.resolve(&path) {
+            Some(file) => file,
+            None => {
+                let response = Response::from_string(format!("404 Not Found: {}", path))
+                    .with_status_code(404)
+                    .with_header(content_type_header("text/plain; charset=utf-8"));
+                respond(request, response);
+                return;
+            }
+        };
+
+        match fs::read(&file) {
+            Ok(content) => {
+                let mime = mime_type(&file);
+                let content = if mime.starts_with("text/html") {
+                    inject_live_reload(content, &self.live_reload_script)
+                } else {
+                    content
+                };
+                let response = Response::from_data(content)
+                    .with_header(content_type_header(mime))
+                    .with_header(Header::from_bytes("Cache-Control", "no-cache").unwrap());
+                respond(request, response);
+            }
+            Err(e) => {
+                error!("cannot read {:?}: {}", &file, e);
+                respond(request, Response::from_string("500").with_status_code(500));
+            }
+        }
+    }
+
+    /// Map a request path onto a file in the gen directory.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = percent_decode(path);
+        let path = match path.strip_prefix(&self.base_path) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+            _ => &path,
+        };
+        let mut file = self.gen_dir.clone();
+        for component in Path::new(path.trim_start_matches('/')).components() {
+            match component {
+                Component::Normal(c) => file.push(c),
+                Component::CurDir => (),
+                // never leave the gen directory
+                _ => return None,
+            }
+        }
+        if file.is_dir() {
+            file.push("index.html");
+        } else if !file.exists() && file.extension().is_none() {
+            file.set_extension("html");
+        }
+        if file.is_file() {
+            Some(file)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extract the path component of a site url, e.g. `/blog` from
+/// `https://example.com/blog/`.
+pub fn base_path_of_url(url: &str) -> String {
+    let without_scheme = match url.find("//") {
+        Some(i) => &url[i + 2..],
+        None => url,
+    };
+    match without_scheme.find('/') {
+        Some(i) => without_scheme[i..].trim_end_matches('/').to_string(),
+        None => String::new(),
+    }
+}
+
+fn respond<R: std::io::Read>(request: Request, response: Response<R>) {
+    if let Err(e) = request.respond(response) {
+        warn!("cannot send response: {}", e);
+    }
+}
+
+fn content_type_header(mime: &str) -> Header {
+    Header::from_bytes("Content-Type", mime).unwrap()
+}
+
+/// Insert `script` before the last `</body>`, or at the end. Pages are
+/// served byte for byte otherwise, whatever their encoding.
+fn inject_live_reload(mut content: Vec<u8>, script: &str) -> Vec<u8> {
+    let end_tag = b"</body>";
+    let i = content
+        .windows(end_tag.len())
+        .rposition(|x| x == end_tag)
+        .unwrap_or(content.len());
+    content.splice(i..i, script.bytes());
+    content
+}
+
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = (
+                (bytes[i + 1] as char).to_digit(16),
+                (bytes[i + 2] as char).to_digit(16),
+            );
+            if let (Some(hi), Some(lo)) = hex {
+                decoded.push((hi * 16 + lo) as u8);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .map(|x| x.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn server(base_path: &str) -> (TempDir, PreviewServer) {
+        let dir = TempDir::new("server");
+        dir.write("_gen/index.html", "home");
+        dir.write("_gen/a.html", "a");
+        dir.write("_gen/dir/index.html", "dir");
+        dir.write("secret.txt", "secret");
+        let server = PreviewServer::new(dir.path().join("_gen"), base_path.to_string());
+        (dir, server)
+    }
+
+    #[test]
+    fn resolve_within_gen_dir() {
+        let (dir, server) = server("");
+        let gen = dir.path().join("_gen");
+        let resolve = |x: &str| server.resolve(x);
+        assert_eq!(resolve("/"), Some(gen.join("index.html")));
+        assert_eq!(resolve("/a.html"), Some(gen.join("a.html")));
+        assert_eq!(resolve("/a"), Some(gen.join("a.html")));
+        assert_eq!(resolve("/dir"), Some(gen.join("dir/index.html")));
+        assert_eq!(resolve("/dir/"), Some(gen.join("dir/index.html")));
+        assert_eq!(
+            resolve("/./dir/%69ndex.html"),
+            Some(gen.join("dir/index.html"))
+        );
+        assert_eq!(resolve("/b.html"), None);
+        for path in [
+            "/../secret.txt",
+            "/dir/../../secret.txt",
+            "/%2e%2e/secret.txt",
+            "/..%2fsecret.txt",
+        ] {
+            assert_eq!(resolve(path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn strip_base_path() {
+        let (dir, server) = server("/blog/");
+        let gen = dir.path().join("_gen");
+        assert_eq!(server.resolve("/blog"), Some(gen.join("index.html")));
+        assert_eq!(server.resolve("/blog/"), Some(gen.join("index.html")));
+        assert_eq!(
+            server.resolve("/blog/dir/"),
+            Some(gen.join("dir/index.html"))
+        );
+        // only whole components are stripped
+        assert_eq!(server.resolve("/bloga.html"), None);
+        assert_eq!(server.live_reload_path, "/blog/__sushi/livereload");
+        assert!(server
+            .live_reload_script
+            .contains("fetch(\"/blog/__sushi/livereload\""));
+    }
+
+    #[test]
+    fn inject_before_the_end_of_body() {
+        let script = "<script></script>";
+        let inject = |x: &[u8]| inject_live_reload(x.to_vec(), script);
+        assert_eq!(
+            inject(b"<body>x</body></body>\n"),
+            b"<body>x</body><script></script></body>\n"
+        );
+        assert_eq!(inject(b"x"), b"x<script></script>");
+        // not utf-8, kept as it is
+        assert_eq!(
+            inject(b"\xff\xfe</body>"),
+            b"\xff\xfe<script></script></body>"
+        );
+    }
+}
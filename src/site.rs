@@ -1,31 +1,78 @@
 use itertools::Itertools;
 use log::{debug, error, info, trace, warn};
+use rayon::prelude::*;
 use serde_yaml::Value;
-use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::fs;
+use std::ops::Bound;
 use std::option::Option;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
 use std::string::String;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::SystemTime;
 use std::vec::Vec;
 
+use crate::check;
 use crate::configuration_loader as confld;
-use crate::converters::Converter;
-use crate::existing_tree::ExistingTreeNode::File;
+use crate::converters::{Converted, Converter, ConverterConfig, ConverterInput, Protocol};
+use crate::error::{Result, SushiError};
+use crate::excerpt;
 use crate::existing_tree::{ETNodeRef, ExistingTreeNode};
 use crate::extract_frontmatter::extract_front_matter;
+use crate::feed::{self, FeedConfig};
 use crate::layout::Layout;
-// use crate::markdown_parser::MarkdownParser;
-use crate::page::{Page, PageRef};
-use crate::paginator::Paginator;
+use crate::manifest::{self, Conversion, Inputs, Manifest, ManifestEntry, MANIFEST_FILE};
+use crate::markdown_parser::link_event_processor::{self, PageLink, PageLinks};
+use crate::markdown_parser::MarkdownParser;
+use crate::page::{Page, PageId, PageRef};
+use crate::paginator::{self, PaginateConfig, Paginator};
+use crate::permalink::{self, PermalinkVars, Permalinks};
+use crate::reading::{self, ReadingSpeed};
+use crate::redirect::RedirectsConfig;
+use crate::reproducible::{self, SourceTimes, Zone};
+use crate::search::{self, SearchConfig};
+use crate::server;
 use crate::site::SiteTreeNode::*;
+use crate::sitemap::SitemapConfig;
+use crate::slug;
+use crate::sort::{SortOrder, SortOrders};
+use crate::taxonomy::{self, TermFiles};
+use crate::taxonomy_page::{self, TaxonomyPagesConfig};
 use crate::theme::Theme;
 
-type NodeRef = Rc<RefCell<SiteTreeNode>>;
+type NodeRef = Arc<RwLock<SiteTreeNode>>;
 type SiteTreeObject = serde_yaml::Value;
+// sorted, so that `_keys` of `taxo` are the same in every build
+type Taxonomies = BTreeMap<String, BTreeMap<String, Vec<PageRef>>>;
+
+// manifest key of the feeds, which are not generated from a single source
+pub(crate) const FEEDS_SOURCE: &str = "_feeds";
+pub(crate) const SITEMAP_SOURCE: &str = "_sitemap";
+pub(crate) const SEARCH_SOURCE: &str = "_search";
+pub(crate) const REDIRECTS_SOURCE: &str = "_redirects";
+pub(crate) const TAXONOMY_PAGES_SOURCE: &str = "_taxonomy_pages";
+// files generated from the whole site, which are never deleted sources
+const SYNTHETIC_SOURCES: [&str; 5] = [
+    FEEDS_SOURCE,
+    SITEMAP_SOURCE,
+    SEARCH_SOURCE,
+    REDIRECTS_SOURCE,
+    TAXONOMY_PAGES_SOURCE,
+];
+
+/// A file to generate, collected from the site tree by `_collect_jobs` and
+/// run on the worker pool.
+enum GenJob {
+    Page(PageRef),
+    Static { path: PathBuf, gen_path: PathBuf },
+}
+
+enum GenOutcome {
+    Generated,
+    Copied,
+    Skipped,
+}
 
 #[derive(Debug)]
 pub enum SiteTreeNode {
@@ -42,7 +89,6 @@ pub enum SiteTreeNode {
     StaticFile {
         path: PathBuf,
         gen_path: PathBuf,
-        timestamp: SystemTime,
     },
 }
 
@@ -56,33 +102,138 @@ enum SiteTreeObjectType {
 }
 
 pub struct Site {
-    site_dir: PathBuf,
-    config: HashMap<String, serde_yaml::Value>,
+    pub(crate) site_dir: PathBuf,
+    pub(crate) config: HashMap<String, serde_yaml::Value>,
     site_url: Option<String>,
-    templates: HashMap<String, Layout>,
-    converters: HashMap<String, Rc<RefCell<dyn Converter>>>,
+    pub(crate) templates: HashMap<String, Layout>,
+    converters: HashMap<String, Arc<dyn Converter>>,
     // internal_markdown_parser: MarkdownParser,
-    gen_dir: PathBuf,
+    pub(crate) gen_dir: PathBuf,
     site_tree: Option<NodeRef>,
-    existing_map: Rc<RefCell<HashMap<PathBuf, ETNodeRef>>>,
+    existing_map: Arc<RwLock<HashMap<PathBuf, ETNodeRef>>>,
 
     convert_ext: HashSet<String>,
     converter_choice: HashMap<String, String>,
     convert_to_ext: HashMap<String, String>,
-    taxonomies: HashMap<String, HashMap<String, RefCell<Vec<PageRef>>>>,
-    pages: Vec<PageRef>,
+    pub(crate) taxonomies: Taxonomies,
+    pub(crate) pages: Vec<PageRef>,
     id_to_page: HashMap<String, PageRef>,
+    // url and title of every page, for links to `@page_id`
+    page_links: PageLinks,
+    // links and metadata of the converted content of every page
+    conversions: HashMap<PathBuf, Conversion>,
+    // whether headings of the internal converter link to themselves
+    heading_anchors: bool,
+    // number of related pages of a page
+    related_limit: usize,
+    // number of characters of `summary_text`
+    excerpt_length: usize,
+    reading_speed: ReadingSpeed,
 
     site_tree_object: Option<serde_yaml::Value>,
     taxo_object: Option<serde_yaml::Value>,
     id_to_page_object: Option<serde_yaml::Value>,
     all_pages_object: Option<serde_yaml::Value>,
 
-    regen_all: bool,
-    naive_skip: bool,
-
     theme: Option<Theme>,
     subpath: Option<Vec<PathBuf>>,
+    prune: bool,
+    dry_run: bool,
+    // worker threads of the build, `None` to build serially
+    pool: Option<rayon::ThreadPool>,
+    drafts: bool,
+    future: bool,
+    // `site.time`
+    pub(crate) build_time: chrono::DateTime<chrono::FixedOffset>,
+    // time after which pages are in the future
+    now: chrono::DateTime<chrono::FixedOffset>,
+    pub(crate) zone: Zone,
+    // times of the sources, for pages without a date in reproducible builds
+    pub(crate) source_times: Option<SourceTimes>,
+    // drafts and future pages left out of this build
+    excluded: Vec<PathBuf>,
+    permalinks: Permalinks,
+    sort_orders: SortOrders,
+    // gen path -> source of every page, to detect clashing permalinks
+    pub(crate) page_gen_paths: HashMap<PathBuf, PathBuf>,
+    feeds: Option<FeedConfig>,
+    sitemap: Option<SitemapConfig>,
+    search: Option<SearchConfig>,
+    pub(crate) redirects: Option<RedirectsConfig>,
+    taxonomy_pages: Option<TaxonomyPagesConfig>,
+    // `_taxonomies/<taxonomy>/<term>.md`, by the terms they describe
+    term_files: TermFiles,
+    // taxonomy -> term -> url component of the term
+    pub(crate) term_slugs: HashMap<String, HashMap<String, String>>,
+    taxo_urls_object: Option<serde_yaml::Value>,
+    // converted content of pages, kept for the feeds
+    converted: Mutex<HashMap<PathBuf, String>>,
+    // converter errors of pages, so that a failing (e.g. timing out) page is
+    // not converted again in the same build
+    conversion_errors: Mutex<HashMap<PathBuf, String>>,
+
+    pub(crate) config_path: PathBuf,
+    template_paths: HashMap<String, PathBuf>,
+    partial_paths: HashMap<String, PathBuf>,
+    converter_paths: HashMap<String, PathBuf>,
+    template_partials: HashMap<String, HashSet<String>>,
+
+    // manifest of the last build, and the one being built
+    pub(crate) manifest: Manifest,
+    pub(crate) next_manifest: Mutex<Manifest>,
+    pub(crate) config_hash: String,
+    pub(crate) template_hashes: HashMap<String, String>,
+    partial_hashes: HashMap<String, String>,
+    converter_hashes: HashMap<String, String>,
+    converter_configs: HashMap<String, ConverterConfig>,
+    // templates that use sitetree, taxo, all_pages or id_to_page
+    index_templates: HashSet<String>,
+    pub(crate) site_index_hash: String,
+    // errors found while loading the site tree
+    errors: Vec<SushiError>,
+}
+
+/// Outcome of `generate_site`. A page that fails is skipped and its error is
+/// recorded here, the rest of the site is still generated.
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    pub pages: usize,
+    pub feeds: usize,
+    pub redirects: usize,
+    pub copied: usize,
+    pub skipped: usize,
+    /// stale files and directories removed from the gen directory (or to be
+    /// removed, in a dry run)
+    pub pruned: Vec<PathBuf>,
+    pub errors: Vec<SushiError>,
+}
+
+impl BuildReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn print_summary(&self) {
+        for e in self.errors.iter() {
+            error!("{}", e);
+        }
+        info!(
+            "[  done]  {} page(s) generated, {} file(s) copied, {} skipped",
+            self.pages, self.copied, self.skipped
+        );
+        if self.feeds > 0 {
+            info!("[  done]  {} feed(s) generated", self.feeds);
+        }
+        if self.redirects > 0 {
+            info!("[  done]  {} redirect(s) generated", self.redirects);
+        }
+        if !self.pruned.is_empty() {
+            info!("[  done]  {} stale path(s) pruned", self.pruned.len());
+        }
+        if !self.is_ok() {
+            error!("{} error(s) occurred", self.errors.len());
+        }
+    }
 }
 
 pub struct SiteConfigs {
@@ -93,25 +244,66 @@ pub struct SiteConfigs {
     pub templates: Option<String>,
     pub theme: Option<String>,
     pub subpath: Option<Vec<String>>,
-    pub naive_skip: bool,
+    pub prune: bool,
+    pub dry_run: bool,
+    /// build pages with `draft: true`
+    pub drafts: bool,
+    /// build pages dated in the future
+    pub future: bool,
+    pub url: Option<String>,
+    /// number of worker threads, `None` for one per CPU
+    pub jobs: Option<usize>,
 }
 
 impl Site {
-    pub fn parse_site_dir(site_dir: PathBuf, regen_all: bool, site_configs: SiteConfigs) -> Self {
+    pub fn parse_site_dir(
+        site_dir: PathBuf,
+        regen_all: bool,
+        site_configs: SiteConfigs,
+    ) -> Result<Self> {
         // search for _site.yml
-        let temp_config = fs::read_dir(site_dir.clone())
-            .expect("cannot open site directory.")
-            .find(|x| {
-                if let Ok(file) = x {
-                    file.file_name() == OsString::from(&site_configs.config)
-                        && file.path().is_file()
-                } else {
-                    false
-                }
+        let temp_config = fs::read_dir(&site_dir)
+            .map_err(|e| SushiError::io(&site_dir, e))?
+            .flatten()
+            .find(|file| {
+                file.file_name() == OsString::from(&site_configs.config) && file.path().is_file()
             })
-            .expect(format! {"cannot find configuration file: {}", &site_configs.config}.as_str())
-            .unwrap();
-        let mut config = confld::parse_config_file(temp_config.path());
+            .ok_or_else(|| {
+                SushiError::config(
+                    &site_dir.join(&site_configs.config),
+                    "cannot find configuration file",
+                )
+            })?;
+        let mut config = confld::parse_config_file(temp_config.path())?;
+
+        // command line url overrides the one in _site.yml (e.g. when previewing)
+        if let Some(url) = &site_configs.url {
+            config.insert("url".to_string(), Value::String(url.clone()));
+        }
+
+        // reproducible builds: `site.time` and the dates of pages without
+        // one come from the sources rather than the clock
+        let source_date_epoch = reproducible::source_date_epoch()
+            .map_err(|e| SushiError::config(&temp_config.path(), e))?;
+        let reproducible = source_date_epoch.is_some()
+            || matches!(config.get("reproducible"), Some(Value::Bool(true)));
+        let zone = match config.get("timezone") {
+            None if reproducible => Zone::utc(),
+            None => Zone::Local,
+            Some(Value::String(zone)) => {
+                Zone::parse(zone).map_err(|e| SushiError::config(&temp_config.path(), e))?
+            }
+            Some(_) => {
+                return Err(SushiError::config(
+                    &temp_config.path(),
+                    "`timezone` should be a string",
+                ))
+            }
+        };
+        let source_times =
+            reproducible.then(|| SourceTimes::new(&site_dir, zone, source_date_epoch));
+        // scheduled pages are published by the clock, not by the sources
+        let now = zone.at(&source_date_epoch.unwrap_or_else(chrono::Utc::now));
 
         let site_url = confld::string_from_config("url", &config);
         let site_gen_dir = confld::string_from_config("gen_dir", &config);
@@ -137,7 +329,7 @@ impl Site {
             site_includes_dir,
             "_includes".to_string(),
         );
-        let _theme_dir = if site_configs.theme != None {
+        let _theme_dir = if site_configs.theme.is_some() {
             site_configs.theme.clone() // command line param
         } else {
             site_theme_dir // config file
@@ -155,7 +347,7 @@ impl Site {
         // search for _templates
         let temp_templates = confld::find_dir(&site_dir, &_templates_dir);
         let mut template_list = if let Some(Ok(temp)) = temp_templates {
-            confld::parse_templates(temp.path())
+            confld::parse_templates(temp.path())?
         } else {
             warn!("no template found");
             HashMap::new()
@@ -164,7 +356,7 @@ impl Site {
         // search for _converters
         let temp_converters = confld::find_dir(&site_dir, &_converters_dir);
         let mut converter_list = if let Some(Ok(temp)) = temp_converters {
-            confld::parse_converters(temp.path())
+            confld::parse_converters(temp.path())?
         } else {
             warn!("no converters found");
             HashMap::new()
@@ -174,30 +366,30 @@ impl Site {
 
         // load theme
         if let Some(theme_dir) = _theme_dir {
-            let temp_theme = confld::find_dir_or_panic(&site_dir, &theme_dir);
-            let real_theme = Theme::new(temp_theme.path());
+            let temp_theme = confld::find_required_dir(&site_dir, &theme_dir)?;
+            let real_theme = Theme::new(temp_theme.path())?;
 
             // combine list and config
             for (name, path) in real_theme.partial_list.iter() {
-                if partial_list.get(name) == None {
+                if !partial_list.contains_key(name) {
                     partial_list.insert(name.clone(), path.clone());
                 }
             }
 
             for (name, path) in real_theme.template_list.iter() {
-                if template_list.get(name) == None {
+                if !template_list.contains_key(name) {
                     template_list.insert(name.clone(), path.clone());
                 }
             }
 
             for (name, path) in real_theme.converter_list.iter() {
-                if converter_list.get(name) == None {
+                if !converter_list.contains_key(name) {
                     converter_list.insert(name.clone(), path.clone());
                 }
             }
 
             for (name, value) in real_theme.config.iter() {
-                if config.get(name) == None {
+                if !config.contains_key(name) {
                     config.insert(name.clone(), value.clone());
                 }
             }
@@ -206,16 +398,71 @@ impl Site {
         }
 
         // compile & load
-        let partial_compiler = confld::compile_partials(partial_list);
-        let templates = confld::compile_templates(partial_compiler, template_list);
-        let converters = confld::load_converters(converter_list);
+        let template_partials = confld::template_partial_deps(&template_list, &partial_list);
+        let index_templates =
+            manifest::templates_using_site_index(&template_list, &partial_list, &template_partials);
+        let template_hashes = manifest::hash_files(&template_list)?;
+        let partial_hashes = manifest::hash_files(&partial_list)?;
+        // settings of converters, e.g. the protocol they speak
+        let converter_configs: HashMap<String, ConverterConfig> = match config.get("converters") {
+            None => HashMap::new(),
+            Some(value) => serde_yaml::from_value(value.clone()).map_err(|e| {
+                SushiError::config(&temp_config.path(), format!("invalid `converters`: {}", e))
+            })?,
+        };
+        if let Some(name) = converter_configs
+            .iter()
+            .find(|(name, x)| x.command.is_none() && !converter_list.contains_key(*name))
+            .map(|(name, _)| name)
+        {
+            return Err(SushiError::config(
+                &temp_config.path(),
+                format!("converter \"{}\" of `converters` is not found", name),
+            ));
+        }
+        let mut converter_hashes = manifest::hash_files(&converter_list)?;
+        for (name, converter_config) in converter_configs.iter() {
+            // a command converter is only known by its settings
+            let hash = converter_hashes.entry(name.clone()).or_default();
+            let settings = serde_yaml::to_string(converter_config).unwrap_or_default();
+            *hash = manifest::hash_parts([hash.as_bytes(), settings.as_bytes()]);
+        }
+        let template_paths = template_list.clone();
+        let partial_paths = partial_list.clone();
+        let converter_paths = converter_list.clone();
+        let partial_compiler = confld::compile_partials(partial_list)?;
+        let templates = confld::compile_templates(partial_compiler, template_list)?;
+        let converters = confld::load_converters(
+            converter_list,
+            &converter_configs,
+            &site_dir,
+            &temp_config.path(),
+        )?;
 
         // parse dir
         let mut gen_dir = site_dir.clone();
         gen_dir.push(_gen_dir);
-        let existing_map = Rc::new(RefCell::new(HashMap::new()));
+        let existing_map = Arc::new(RwLock::new(HashMap::new()));
         Self::_parse_gen(&gen_dir, existing_map.clone());
         // debug!("{:?}", &existing_map);
+        // `time` changes in every build, pages using it are not regenerated
+        // for that alone
+        let build_time = match &source_times {
+            Some(source_times) => {
+                zone.at(&source_date_epoch.unwrap_or_else(|| source_times.latest(&gen_dir)))
+            }
+            None => now,
+        };
+        config.insert("time".to_string(), Value::String(build_time.to_rfc3339()));
+        let mut hashed_config = config.clone();
+        hashed_config.remove("time");
+        let config_hash = manifest::hash_config(&hashed_config);
+        // regenerate everything by ignoring the last build
+        let manifest = if regen_all {
+            Manifest::new()
+        } else {
+            Manifest::load(&gen_dir)
+        };
 
         let (convert_ext, converter_choice, convert_to_ext, taxonomies) =
             Self::_extract_important_config(&config);
@@ -223,19 +470,163 @@ impl Site {
         debug!("{:?}", converter_choice);
         debug!("{:?}", taxonomies);
 
-        let naive_skip = site_configs.naive_skip;
+        let permalink = match config.get("permalink") {
+            None => None,
+            Some(Value::String(pattern)) => Some(pattern.clone()),
+            Some(_) => {
+                return Err(SushiError::config(
+                    &temp_config.path(),
+                    "`permalink` should be a string",
+                ))
+            }
+        };
+        let dir_permalinks = match config.get("permalinks") {
+            None => HashMap::new(),
+            Some(value) => serde_yaml::from_value(value.clone()).map_err(|e| {
+                SushiError::config(&temp_config.path(), format!("invalid `permalinks`: {}", e))
+            })?,
+        };
+        let permalinks = Permalinks::new(permalink, dir_permalinks);
+
+        let sort = match config.get("sort") {
+            None => SortOrder::default(),
+            Some(value) => serde_yaml::from_value(value.clone()).map_err(|e| {
+                SushiError::config(&temp_config.path(), format!("invalid `sort`: {}", e))
+            })?,
+        };
+        let dir_sorts = match config.get("sorts") {
+            None => HashMap::new(),
+            Some(value) => serde_yaml::from_value(value.clone()).map_err(|e| {
+                SushiError::config(&temp_config.path(), format!("invalid `sorts`: {}", e))
+            })?,
+        };
+        let sort_orders = SortOrders::new(sort, dir_sorts);
+
+        let feeds = match config.get("feeds") {
+            Some(value) => {
+                let feeds: FeedConfig = serde_yaml::from_value(value.clone()).map_err(|e| {
+                    SushiError::config(&temp_config.path(), format!("invalid `feeds`: {}", e))
+                })?;
+                if let Some(taxo) = feeds
+                    .taxonomies
+                    .iter()
+                    .find(|x| !taxonomies.contains_key(*x))
+                {
+                    return Err(SushiError::config(
+                        &temp_config.path(),
+                        format!("`feeds` refers to unknown taxonomy \"{}\"", taxo),
+                    ));
+                }
+                if !site_url.as_ref().is_some_and(|x| x.contains("://")) {
+                    warn!("feeds need an absolute `url`, such as \"https://example.com\"");
+                }
+                Some(feeds)
+            }
+            None => None,
+        };
+
+        let sitemap: Option<SitemapConfig> =
+            confld::section_from_config("sitemap", &config, &temp_config.path())?;
+        if sitemap.is_some() && !site_url.as_ref().is_some_and(|x| x.contains("://")) {
+            warn!("sitemaps need an absolute `url`, such as \"https://example.com\"");
+        }
+
+        let search: Option<SearchConfig> =
+            confld::section_from_config("search", &config, &temp_config.path())?;
+
+        let taxonomy_pages: Option<TaxonomyPagesConfig> =
+            confld::section_from_config("taxonomy_pages", &config, &temp_config.path())?;
+        if let Some(pages) = &taxonomy_pages {
+            if let Some(taxo) = pages
+                .taxonomies
+                .iter()
+                .find(|x| !taxonomies.contains_key(*x))
+            {
+                return Err(SushiError::config(
+                    &temp_config.path(),
+                    format!("`taxonomy_pages` refers to unknown taxonomy \"{}\"", taxo),
+                ));
+            }
+            let patterns = [
+                (&pages.term_url, &["taxonomy", "term"][..]),
+                (&pages.taxonomy_url, &["taxonomy"][..]),
+            ];
+            for (pattern, names) in patterns {
+                permalink::expand_with(pattern, "html", |x| {
+                    names.contains(&x).then(|| "x".to_string())
+                })
+                .map_err(|e| {
+                    SushiError::config(
+                        &temp_config.path(),
+                        format!("invalid `taxonomy_pages`: {}", e),
+                    )
+                })?;
+            }
+        }
+
+        let redirects: Option<RedirectsConfig> =
+            confld::section_from_config("redirects", &config, &temp_config.path())?;
+
+        let related_limit = match config.get("related_limit") {
+            None => 5,
+            Some(Value::Number(n)) if n.as_u64().is_some() => n.as_u64().unwrap() as usize,
+            Some(_) => {
+                return Err(SushiError::config(
+                    &temp_config.path(),
+                    "`related_limit` should be a non-negative integer",
+                ))
+            }
+        };
+
+        let excerpt_length = match config.get("excerpt_length") {
+            None => 200,
+            Some(Value::Number(n)) if n.as_u64().is_some() => n.as_u64().unwrap() as usize,
+            Some(_) => {
+                return Err(SushiError::config(
+                    &temp_config.path(),
+                    "`excerpt_length` should be a non-negative integer",
+                ))
+            }
+        };
+
+        let reading_speed = match config.get("reading_speed") {
+            None => ReadingSpeed::default(),
+            Some(value) => serde_yaml::from_value(value.clone()).map_err(|e| {
+                SushiError::config(
+                    &temp_config.path(),
+                    format!("invalid `reading_speed`: {}", e),
+                )
+            })?,
+        };
+
+        let heading_anchors = matches!(config.get("heading_anchors"), Some(Value::Bool(true)));
+
+        let prune = site_configs.prune || matches!(config.get("prune"), Some(Value::Bool(true)));
 
         let mut subpath = None;
 
         if let Some(sp) = site_configs.subpath {
             let mut real_subpath = vec![];
             for p in sp {
-                real_subpath.push(PathBuf::from(p));
+                let p = PathBuf::from(p);
+                real_subpath.push(p.canonicalize().map_err(|e| SushiError::io(&p, e))?);
             }
             subpath = Some(real_subpath);
         }
 
-        Site {
+        // the worker threads are shared by the builds of the site
+        let pool = match rayon::ThreadPoolBuilder::new()
+            .num_threads(site_configs.jobs.unwrap_or(0))
+            .build()
+        {
+            Ok(pool) => Some(pool),
+            Err(e) => {
+                warn!("cannot create worker threads, build serially: {}", e);
+                None
+            }
+        };
+
+        Ok(Site {
             site_dir,
             config,
             site_url,
@@ -250,21 +641,105 @@ impl Site {
             taxonomies,
             pages: vec![],
             id_to_page: HashMap::new(),
+            page_links: PageLinks::default(),
+            conversions: HashMap::new(),
+            heading_anchors,
+            related_limit,
+            excerpt_length,
+            reading_speed,
             site_tree_object: None,
             taxo_object: None,
             all_pages_object: None,
             id_to_page_object: None,
-            regen_all,
-            naive_skip,
             theme,
             subpath,
-        }
+            prune,
+            dry_run: site_configs.dry_run,
+            pool,
+            drafts: site_configs.drafts,
+            future: site_configs.future,
+            build_time,
+            now,
+            zone,
+            source_times,
+            excluded: vec![],
+            permalinks,
+            sort_orders,
+            page_gen_paths: HashMap::new(),
+            feeds,
+            sitemap,
+            search,
+            redirects,
+            taxonomy_pages,
+            term_files: TermFiles::new(),
+            term_slugs: HashMap::new(),
+            taxo_urls_object: None,
+            converted: Mutex::new(HashMap::new()),
+            conversion_errors: Mutex::new(HashMap::new()),
+            config_path: temp_config.path(),
+            template_paths,
+            partial_paths,
+            converter_paths,
+            template_partials,
+            manifest,
+            next_manifest: Mutex::new(Manifest::new()),
+            config_hash,
+            template_hashes,
+            partial_hashes,
+            converter_hashes,
+            converter_configs,
+            index_templates,
+            site_index_hash: String::new(),
+            errors: vec![],
+        })
+    }
+
+    pub fn gen_dir(&self) -> &PathBuf {
+        &self.gen_dir
+    }
+
+    pub fn site_url(&self) -> &Option<String> {
+        &self.site_url
+    }
+
+    pub fn site_dir(&self) -> &PathBuf {
+        &self.site_dir
+    }
+
+    pub fn config_path(&self) -> &PathBuf {
+        &self.config_path
+    }
+
+    pub fn theme_dir(&self) -> Option<&PathBuf> {
+        self.theme.as_ref().map(|theme| &theme.theme_dir)
+    }
+
+    pub fn template_paths(&self) -> &HashMap<String, PathBuf> {
+        &self.template_paths
+    }
+
+    pub fn partial_paths(&self) -> &HashMap<String, PathBuf> {
+        &self.partial_paths
+    }
+
+    pub fn converter_paths(&self) -> &HashMap<String, PathBuf> {
+        &self.converter_paths
     }
 
-    pub fn generate_site(&mut self) {
+    pub fn generate_site(&mut self) -> BuildReport {
+        let mut report = BuildReport::default();
+
         // gen site tree
         let gen_dir = self.gen_dir.clone();
-        let (site_tree, _) = self._gen_site_tree(&self.site_dir.clone(), &gen_dir);
+        let site_tree = match self._gen_site_tree(&self.site_dir.clone(), &gen_dir) {
+            // the site directory is always loaded
+            Ok(Some((site_tree, _))) => site_tree,
+            Ok(None) => unreachable!(),
+            Err(e) => {
+                report.errors.push(e);
+                return report;
+            }
+        };
 
         if let Some(theme) = &self.theme {
             if let SiteTreeNode::NormalDir {
@@ -272,7 +747,7 @@ impl Site {
                 path: _,
                 gen_path: _,
                 index,
-            } = &mut *site_tree.borrow_mut()
+            } = &mut *site_tree.write().unwrap()
             {
                 self._merge_theme_site_tree(
                     theme.theme_dir.clone(),
@@ -288,12 +763,46 @@ impl Site {
 
         self.site_tree = Some(site_tree);
 
+        // latest first, whatever `sort` is, for backlinks, related pages
+        // and feeds
+        self.pages
+            .sort_by(|a, b| SortOrder::default().compare(&a.read().unwrap(), &b.read().unwrap()));
+
+        self.page_links = Arc::new(
+            self.id_to_page
+                .iter()
+                .map(|(id, page)| {
+                    let page = page.read().unwrap();
+                    let title = match page.front_matter.get("title") {
+                        Some(Value::String(title)) => title.clone(),
+                        _ => page
+                            .path
+                            .file_stem()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string(),
+                    };
+                    let link = PageLink {
+                        url: page.url.clone(),
+                        title,
+                    };
+                    (id.clone(), link)
+                })
+                .collect(),
+        );
+
+        // pages are converted before any of them is rendered, since the
+        // page objects hold backlinks
+        self._link_pages();
+
         // gen sitetree object based on self.site_tree
         let (site_tree_object, _) = self._gen_site_tree_object(self.site_tree.clone().unwrap());
         self.site_tree_object = site_tree_object;
 
         // gen taxo object based on self.taxonomies
+        self._load_term_files();
         self._gen_taxo_object();
+        self._gen_taxo_urls_object();
         // let temp = serde_yaml::to_string(&self.taxo_object).unwrap_or("error".to_string());
         // debug!("{}", temp);
 
@@ -302,143 +811,473 @@ impl Site {
         self.id_to_page_object = Some(id_to_page_object);
 
         // gen all_pages object
-        // sort all_pages
-        self.pages
-            .sort_by(|a, b| match a.borrow().date().cmp(b.borrow().date()) {
-                std::cmp::Ordering::Greater => std::cmp::Ordering::Less,
-                std::cmp::Ordering::Less => std::cmp::Ordering::Greater,
-                std::cmp::Ordering::Equal => std::cmp::Ordering::Equal,
-            });
         let all_pages_object = self._gen_all_pages_object();
         self.all_pages_object = Some(all_pages_object);
 
+        self.site_index_hash = self._site_index_hash();
+
         // assemble global object
         let globals = liquid::object!({
             "site": self.config,
             "sitetree": self.site_tree_object,
             "taxo": self.taxo_object,
+            "taxo_urls": self.taxo_urls_object,
             "all_pages": self.all_pages_object,
             "id_to_page": self.id_to_page_object,
         });
 
+        // pages that failed to load are reported along with the others
+        report.errors.append(&mut self.errors);
+
         // gen _gen
-        self._generate(self.site_tree.clone().unwrap(), globals);
+        self._generate(&globals, &mut report);
+        // before the sitemap, which lists them
+        if let Some(taxonomy_pages) = &self.taxonomy_pages {
+            self.gen_taxonomy_pages(taxonomy_pages, &globals, &mut report);
+        }
+
+        if let Some(feeds) = &self.feeds {
+            self.gen_feeds(feeds, &mut report);
+        }
+        if let Some(sitemap) = &self.sitemap {
+            self.gen_sitemap(sitemap, &globals, &mut report);
+        }
+        if let Some(search) = &self.search {
+            self.gen_search_index(search, &mut report);
+        }
+        self.gen_redirects(&globals, &mut report);
+
+        self._remove_withdrawn(&mut report);
+
+        if self.prune || self.dry_run {
+            if report.is_ok() {
+                self._prune(&mut report);
+            } else {
+                // outputs of failed pages would be taken for stale ones
+                warn!("build failed, stale files are not pruned");
+            }
+        }
+        // a dry run removes nothing, the next build removes the outputs of
+        // deleted sources from their records
+        if self.dry_run {
+            for key in self._deleted_sources() {
+                self._keep_record(Path::new(&key));
+            }
+        }
+
+        if gen_dir.is_dir() {
+            if let Err(e) = self.next_manifest.lock().unwrap().save(&gen_dir) {
+                report.errors.push(e);
+            }
+        }
+        // a page that fails to convert also fails the feeds and the search
+        // index, report it once
+        let mut seen = HashSet::new();
+        report.errors.retain(|e| seen.insert(e.to_string()));
+        report
+    }
+
+    fn _generate(&self, globals: &liquid::Object, report: &mut BuildReport) {
+        // directories are created beforehand, so that jobs do not depend on
+        // each other
+        let mut jobs = vec![];
+        self._collect_jobs(self.site_tree.clone().unwrap(), &mut jobs, report);
+
+        let outcomes = self._in_pool(|| {
+            jobs.par_iter()
+                .map(|job| self._run_job(job, globals))
+                .collect::<Vec<_>>()
+        });
+
+        // outcomes are in the order of the site tree, whatever the order
+        // they were generated in
+        for outcome in outcomes {
+            match outcome {
+                Ok(GenOutcome::Generated) => report.pages += 1,
+                Ok(GenOutcome::Copied) => report.copied += 1,
+                Ok(GenOutcome::Skipped) => report.skipped += 1,
+                Err(e) => report.errors.push(e),
+            }
+        }
+    }
+
+    /// Run `f` on the worker threads, which `f` uses through rayon.
+    fn _in_pool<T: Send>(&self, f: impl FnOnce() -> T + Send) -> T {
+        match &self.pool {
+            Some(pool) => pool.install(f),
+            None => f(),
+        }
+    }
+
+    /// Find the links between pages, and set the backlinks, the related
+    /// pages and the converter metadata of every page. Pages whose converted
+    /// content is not known from the last build are converted here, and not
+    /// again when they are generated.
+    fn _link_pages(&mut self) {
+        let conversions = self._in_pool(|| {
+            self.pages
+                .par_iter()
+                .map(|x| {
+                    let page = x.read().unwrap();
+                    (page.path.clone(), self._conversion_of(&page))
+                })
+                .collect::<Vec<_>>()
+        });
+        // pages that fail to convert have no links, their error is reported
+        // when they are generated
+        self.conversions = conversions
+            .into_iter()
+            .filter_map(|(path, conversion)| Some((path, conversion?)))
+            .collect();
+        for page in self.pages.iter() {
+            let mut page = page.write().unwrap();
+            let conversion = self.conversions.get(&page.path);
+            if let Some(conversion) = conversion {
+                page.set_metadata(conversion.metadata.clone());
+            }
+            // a summary in the front matter is used as it is
+            let summary = ["summary", "description"]
+                .iter()
+                .find_map(|key| page.front_matter.get(*key).and_then(|x| x.as_str()));
+            let (excerpt, summary_text) = match summary {
+                Some(summary) => (
+                    format!("<p>{}</p>", feed::escape(summary)),
+                    summary.to_string(),
+                ),
+                None => {
+                    let excerpt = conversion.map(|x| x.excerpt.clone()).unwrap_or_default();
+                    let text = search::html_to_text(&excerpt);
+                    (excerpt, excerpt::truncate(&text, self.excerpt_length))
+                }
+            };
+            page.set_excerpt(excerpt, summary_text);
+            let word_count = conversion.map(|x| x.word_count).unwrap_or_default();
+            page.set_reading(word_count.total(), self.reading_speed.minutes(&word_count));
+        }
+
+        let mut url_to_id: HashMap<String, PageId> = HashMap::new();
+        for page in self.pages.iter() {
+            let page = page.read().unwrap();
+            let url_path = Self::_url_path_of(&page.url);
+            // the ways a server finds the page, see `server::PreviewServer`
+            let mut variants = vec![url_path.clone()];
+            if let Some(dir) = url_path.strip_suffix('/') {
+                variants.push(url_path.clone() + "index.html");
+                variants.push(dir.to_string());
+            } else if let Some(stem) = url_path.strip_suffix(".html") {
+                variants.push(stem.to_string());
+            }
+            for variant in variants {
+                url_to_id
+                    .entry(variant)
+                    .or_insert_with(|| page.get_page_id().clone());
+            }
+        }
+
+        // self.pages is sorted by date, latest first
+        let mut backlinks: HashMap<PageId, Vec<PageId>> = HashMap::new();
+        for page in self.pages.iter() {
+            let page = page.read().unwrap();
+            let Some(conversion) = self.conversions.get(&page.path) else {
+                continue;
+            };
+            for link in conversion.links.iter() {
+                let Some(target) = self
+                    ._resolve_link(&page.url, link)
+                    .and_then(|x| url_to_id.get(&x))
+                else {
+                    continue;
+                };
+                let sources = backlinks.entry(target.clone()).or_default();
+                if target != page.get_page_id() && !sources.contains(page.get_page_id()) {
+                    sources.push(page.get_page_id().clone());
+                }
+            }
+        }
+
+        let mut taxonomies = self.taxonomies.keys().cloned().collect_vec();
+        taxonomies.sort();
+        let terms = self
+            .pages
+            .iter()
+            .map(|x| {
+                let page = x.read().unwrap();
+                taxonomies
+                    .iter()
+                    .flat_map(|taxo| {
+                        page.belongs_to_kind(taxo)
+                            .into_iter()
+                            .map(move |x| (taxo, x))
+                    })
+                    .collect_vec()
+            })
+            .collect_vec();
+        let mut term_pages: HashMap<_, Vec<usize>> = HashMap::new();
+        for (i, page_terms) in terms.iter().enumerate() {
+            for term in page_terms {
+                term_pages.entry(term).or_default().push(i);
+            }
+        }
+        for (i, page) in self.pages.iter().enumerate() {
+            let mut shared: HashMap<usize, usize> = HashMap::new();
+            for term in terms[i].iter() {
+                for j in term_pages[term].iter().filter(|j| **j != i) {
+                    *shared.entry(*j).or_default() += 1;
+                }
+            }
+            // ties are broken by date, latest first
+            let related = shared
+                .into_iter()
+                .sorted_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)))
+                .take(self.related_limit)
+                .map(|(j, _)| self.pages[j].read().unwrap().get_page_id().clone())
+                .collect_vec();
+            let mut page = page.write().unwrap();
+            let page_backlinks = backlinks.remove(page.get_page_id()).unwrap_or_default();
+            page.set_backlinks(page_backlinks);
+            page.set_related(related);
+        }
+    }
+
+    /// Links and metadata of the converted content of a page, from the
+    /// manifest if the page has not changed since the last build. `None` if
+    /// the page cannot be converted.
+    fn _conversion_of(&self, page: &Page) -> Option<Conversion> {
+        let converter_choice = self._converter_choice(page);
+        if let Some(entry) = self.manifest.get(&self._source_key(&page.path)) {
+            if entry.conversion.is_some()
+                && entry.inputs.source == manifest::hash_bytes(page.content.as_bytes())
+                && entry.inputs.converter == Some(self._converter_input(page, &converter_choice))
+            {
+                return entry.conversion.clone();
+            }
+        }
+        let converted = self._convert(page, &converter_choice).ok()?;
+        let html = self._cache_converted(page, converted.content);
+        Some(Conversion {
+            links: check::links_of(&html),
+            metadata: converted.metadata,
+            excerpt: excerpt::excerpt_of(&html).unwrap_or_default(),
+            word_count: reading::count_words(&search::html_to_text(&html)),
+        })
+    }
+
+    /// Url path (e.g. `/posts/hello.html`) a link in the page at `from_url`
+    /// points to, if it points into the site.
+    fn _resolve_link(&self, from_url: &str, link: &str) -> Option<String> {
+        let link = link.split(['#', '?']).next().unwrap_or_default();
+        if link.is_empty() || link.starts_with("//") {
+            return None;
+        }
+        let path = if link.contains("://") {
+            let site_url = self.site_url.as_ref().filter(|x| !x.is_empty())?;
+            link.strip_prefix(site_url.trim_end_matches('/'))?;
+            Self::_url_path_of(link)
+        } else if link.contains(':') && !link.starts_with(['/', '.']) {
+            // mailto: etc.
+            return None;
+        } else if link.starts_with('/') {
+            link.to_string()
+        } else {
+            let from = Self::_url_path_of(from_url);
+            from[..from.rfind('/').map_or(0, |x| x + 1)].to_string() + link
+        };
+        let path = server::percent_decode(&path);
+        let mut parts: Vec<&str> = vec![];
+        for part in path.split('/').skip(1) {
+            match part {
+                "." => (),
+                ".." => {
+                    parts.pop()?;
+                }
+                _ => parts.push(part),
+            }
+        }
+        Some(String::from("/") + &parts.join("/"))
     }
 
-    fn _generate(&self, current_node: NodeRef, mut globals: liquid::Object) -> liquid::Object {
-        match &mut *current_node.clone().borrow_mut() {
+    fn _collect_jobs(
+        &self,
+        current_node: NodeRef,
+        jobs: &mut Vec<GenJob>,
+        report: &mut BuildReport,
+    ) {
+        match &*current_node.read().unwrap() {
             SiteTreeNode::NormalDir {
                 children,
                 path: _,
                 gen_path,
                 ..
             } => {
-                // let dest_path = self._get_dest_path(path, false, None);
-                let dest_path = gen_path.clone();
-                // match path.file_name() {
-                //     Some(file_name) => {
-                //         dest_path.push(file_name);
-                //     },
-                //     None => ()
-                // }
-                debug!("[+mkdir]  {:?}", dest_path);
-                fs::create_dir_all(&dest_path).expect("cannot create dir");
-                let mut globals = globals;
+                debug!("[+mkdir]  {:?}", gen_path);
+                if let Err(e) = fs::create_dir_all(gen_path) {
+                    report.errors.push(SushiError::io(gen_path, e));
+                    return;
+                }
                 for child in children.iter() {
-                    globals = self._generate(child.clone(), globals)
+                    self._collect_jobs(child.clone(), jobs, report);
                 }
-                globals
-            }
-            SiteTreeNode::PageFile { path: _, page } => {
-                self.gen_page(page.clone(), &mut globals);
-                globals
             }
-            SiteTreeNode::StaticFile {
-                path,
-                gen_path,
-                timestamp,
-            } => {
-                // let dest_path = self._get_dest_path(path, false, None);
-                let dest_path = gen_path.clone();
+            SiteTreeNode::PageFile { path: _, page } => jobs.push(GenJob::Page(page.clone())),
+            SiteTreeNode::StaticFile { path, gen_path } => jobs.push(GenJob::Static {
+                path: path.clone(),
+                gen_path: gen_path.clone(),
+            }),
+        }
+    }
+
+    fn _run_job(&self, job: &GenJob, globals: &liquid::Object) -> Result<GenOutcome> {
+        match job {
+            GenJob::Page(page) => match self.gen_page(page.clone(), globals)? {
+                true => Ok(GenOutcome::Generated),
+                false => Ok(GenOutcome::Skipped),
+            },
+            GenJob::Static { path, gen_path } => {
                 // check whether skip copy
-                let src_timestamp = timestamp;
-                let mut do_copy = self._decide_not_skip_static(&dest_path, src_timestamp);
-                if self.subpath != None {
-                    if self.naive_skip {
-                        do_copy = self._in_subpath(&path);
-                    } else {
-                        do_copy &= self._in_subpath(&path);
-                    }
-                }
-                if do_copy {
+                let content = fs::read(path).map_err(|e| SushiError::io(path, e))?;
+                let inputs = Inputs {
+                    source: manifest::hash_bytes(&content),
+                    ..Default::default()
+                };
+                if self._decide_not_skip(path, &inputs) {
                     info!(
                         "[--copy]  {} -> {}",
-                        path.clone().to_string_lossy(),
-                        &dest_path.to_string_lossy()
+                        path.to_string_lossy(),
+                        gen_path.to_string_lossy()
                     );
-                    fs::copy(path.clone(), dest_path).unwrap();
+                    fs::copy(path, gen_path).map_err(|e| SushiError::io(gen_path, e))?;
+                    self._record(path, inputs, vec![gen_path.clone()]);
+                    Ok(GenOutcome::Copied)
                 } else {
-                    debug!("[  skip]  {}", path.clone().to_string_lossy());
+                    debug!("[  skip]  {}", path.to_string_lossy());
+                    self._keep_record(path);
+                    Ok(GenOutcome::Skipped)
                 }
-                globals
             }
         }
     }
 
-    fn _gen_site_tree(&mut self, path: &PathBuf, gen_path: &PathBuf) -> (NodeRef, Option<PageRef>) {
+    /// Converted content of a page, from this build if the page has been
+    /// generated, otherwise converted again.
+    pub(crate) fn _converted_html(&self, page: &Page) -> Result<String> {
+        if let Some(html) = self.converted.lock().unwrap().get(&page.path) {
+            return Ok(html.clone());
+        }
+        let converted = self._convert(page, &self._converter_choice(page))?;
+        Ok(self._cache_converted(page, converted.content))
+    }
+
+    /// Keep the converted content of a page for the rest of the build.
+    fn _cache_converted(&self, page: &Page, converted: Vec<u8>) -> String {
+        match String::from_utf8(converted) {
+            Ok(html) => {
+                self.converted
+                    .lock()
+                    .unwrap()
+                    .insert(page.path.clone(), html.clone());
+                html
+            }
+            // not kept, since the page itself is generated from the bytes
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).to_string(),
+        }
+    }
+
+    /// Hash of all converters, for outputs built from the converted content
+    /// of many pages.
+    pub(crate) fn _converters_hash(&self) -> String {
+        let mut converters = self.converter_hashes.iter().collect_vec();
+        converters.sort();
+        manifest::hash_parts(
+            converters
+                .iter()
+                .flat_map(|(name, hash)| [name.as_bytes(), hash.as_bytes()]),
+        )
+    }
+
+    /// Url of a path in the gen directory.
+    pub(crate) fn _gen_url(&self, path: &Path) -> String {
+        let relative = path
+            .strip_prefix(&self.gen_dir)
+            .unwrap_or(path)
+            .components()
+            .map(|x| x.as_os_str().to_string_lossy())
+            .join("/");
+        let base = self.site_url.clone().unwrap_or_default();
+        if relative.is_empty() {
+            base
+        } else {
+            base + "/" + &relative
+        }
+    }
+
+    /// Load a file or directory into the site tree. Returns `None` for pages
+    /// left out of the build, i.e. drafts and pages dated in the future.
+    fn _gen_site_tree(
+        &mut self,
+        path: &Path,
+        gen_path: &Path,
+    ) -> Result<Option<(NodeRef, Option<PageRef>)>> {
         if path.is_dir() {
             let mut children: Vec<NodeRef> = vec![];
             let mut index: Option<PageRef> = None;
-            let mut new_gen_path = gen_path.clone();
+            let mut new_gen_path = gen_path.to_path_buf();
             match path.file_name() {
                 None => (),
                 Some(file_name) => {
                     new_gen_path.push(file_name);
                 }
             }
-            for entry in path.read_dir().unwrap() {
-                if let Ok(entry) = entry {
-                    if entry
-                        .path()
-                        .file_name()
-                        .unwrap()
-                        .to_string_lossy()
-                        .starts_with(|ch: char| ch == '.' || ch == '_')
-                    {
+            let entries = path.read_dir().map_err(|e| SushiError::io(path, e))?;
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with(['.', '_']) {
+                    continue;
+                }
+                let (child, index_) = match self._gen_site_tree(&entry.path(), &new_gen_path) {
+                    Ok(Some(loaded)) => loaded,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        // skip the broken file, report it after the build
+                        self.errors.push(e);
                         continue;
                     }
-                    let (child, index_) = self._gen_site_tree(&entry.path(), &new_gen_path);
-                    match &*child.borrow() {
-                        PageFile { .. } => index = index_,
-                        _ => (),
-                    }
-                    children.push(child);
+                };
+                if let PageFile { .. } = &*child.read().unwrap() {
+                    index = index_
                 }
+                children.push(child);
             }
-            Self::_sort_children(&mut children);
+            self._sort_children(&new_gen_path, &mut children);
             // return node
-            let node = Rc::new(RefCell::new(SiteTreeNode::NormalDir {
+            let node = Arc::new(RwLock::new(SiteTreeNode::NormalDir {
                 children,
-                path: path.clone(),
+                path: path.to_path_buf(),
                 gen_path: new_gen_path,
                 index,
             }));
-            (node, None)
+            Ok(Some((node, None)))
         } else if path.is_file() {
-            self._load_file(&path, &gen_path)
+            self._load_file(path, gen_path)
         } else {
-            error!("unknown type");
-            panic!();
+            Err(Self::_unknown_type(path))
         }
     }
 
-    fn _load_file(&mut self, path: &PathBuf, gen_path: &PathBuf) -> (NodeRef, Option<PageRef>) {
+    fn _unknown_type(path: &Path) -> SushiError {
+        SushiError::io(
+            path,
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "neither a regular file nor a directory",
+            ),
+        )
+    }
+
+    fn _load_file(
+        &mut self,
+        path: &Path,
+        gen_path: &Path,
+    ) -> Result<Option<(NodeRef, Option<PageRef>)>> {
         // check whether it is page file by extension name
-        let timestamp = if let Ok(metadata) = path.metadata() {
-            metadata.modified().unwrap_or(SystemTime::now())
-        } else {
-            SystemTime::now()
-        };
         if self.is_page(path) {
             let ext = path
                 .extension()
@@ -446,7 +1285,7 @@ impl Site {
                 .to_string_lossy()
                 .to_string();
 
-            let (fm, content) = extract_front_matter(path);
+            let (fm, content, content_line) = extract_front_matter(path)?;
 
             let mut fm_file_name = OsString::from("_");
             fm_file_name.push(path.file_name().unwrap());
@@ -454,16 +1293,17 @@ impl Site {
             let fm_file_path = path.with_file_name(fm_file_name);
             let mut fm_from_file: HashMap<String, serde_yaml::Value> = HashMap::new();
             if fm_file_path.exists() {
-                let fm_content =
-                    fs::read_to_string(fm_file_path).expect("Unable to read front matter file");
+                let fm_content = fs::read_to_string(&fm_file_path)
+                    .map_err(|e| SushiError::io(&fm_file_path, e))?;
                 fm_from_file =
-                    serde_yaml::from_str(&fm_content).expect("Unable to parse front matter file");
+                    serde_yaml::from_str(&fm_content).map_err(|e| SushiError::FrontMatter {
+                        line: e.location().map(|l| l.line()),
+                        path: fm_file_path.clone(),
+                        message: e.to_string(),
+                    })?;
             }
 
-            let mut fm = match fm {
-                Some(fm) => fm,
-                None => HashMap::new(),
-            };
+            let mut fm = fm.unwrap_or_default();
 
             for (key, value) in fm_from_file {
                 fm.entry(key).or_insert(value);
@@ -492,32 +1332,62 @@ impl Site {
                 }
             };
 
-            let mut new_gen_path = gen_path.clone();
-            new_gen_path.push(path.file_stem().unwrap());
-            new_gen_path.set_extension(&to_ext);
-
-            let url = self.get_page_url(path, to_ext.clone());
-            let page = Rc::new(RefCell::new(Page::new(
+            // undated pages get the same date in their permalink and `page.date`
+            let date = self._page_date(path, &fm);
+            let (url, new_gen_path) = self._page_location(path, gen_path, &fm, &to_ext, &date)?;
+            if let Some(other) = self.page_gen_paths.get(&new_gen_path) {
+                return Err(SushiError::FrontMatter {
+                    path: path.to_path_buf(),
+                    line: None,
+                    message: format!(
+                        "{} is already generated from {}, check the permalinks",
+                        new_gen_path.to_string_lossy(),
+                        other.to_string_lossy()
+                    ),
+                });
+            }
+            let mut page = Page::new(
                 fm,
                 url,
-                path.clone(),
+                path.to_path_buf(),
                 Some(to_ext),
                 content,
-                timestamp.clone(),
+                content_line,
                 new_gen_path,
-            )));
+            );
+            page.set_date(date);
+            let page = Arc::new(RwLock::new(page));
+            if page.read().unwrap().is_draft() && !self.drafts {
+                info!("[-draft]  {}", path.to_string_lossy());
+                self.excluded.push(path.to_path_buf());
+                return Ok(None);
+            }
+            if page.read().unwrap().is_future(&self.now) && !self.future {
+                info!("[future]  {}", path.to_string_lossy());
+                self.excluded.push(path.to_path_buf());
+                return Ok(None);
+            }
             // check whether page_id is unique
-            let page_id = page.borrow().get_page_id().clone();
-            if self.id_to_page.contains_key(&page_id) {
-                error!("id \"{}\" is not unique!", page_id);
-                panic!();
+            let page_id = page.read().unwrap().get_page_id().clone();
+            if let Some(other) = self.id_to_page.get(&page_id) {
+                return Err(SushiError::FrontMatter {
+                    path: path.to_path_buf(),
+                    line: None,
+                    message: format!(
+                        "id \"{}\" is not unique, already used by {}",
+                        page_id,
+                        other.read().unwrap().path.to_string_lossy()
+                    ),
+                });
             }
             // add page to self.pages and self.id_to_page
+            self.page_gen_paths
+                .insert(page.read().unwrap().gen_path.clone(), path.to_path_buf());
             self.pages.push(page.clone());
             self.id_to_page.insert(page_id, page.clone());
             // return node and ref of index
-            let node = Rc::new(RefCell::new(SiteTreeNode::PageFile {
-                path: path.clone(),
+            let node = Arc::new(RwLock::new(SiteTreeNode::PageFile {
+                path: path.to_path_buf(),
                 page: page.clone(),
             }));
             let index = if self.is_index(path) {
@@ -525,130 +1395,132 @@ impl Site {
             } else {
                 None
             };
-            (node, index)
+            Ok(Some((node, index)))
         } else {
-            let mut new_gen_path = gen_path.clone();
+            let mut new_gen_path = gen_path.to_path_buf();
             new_gen_path.push(path.file_name().unwrap());
-            let node = Rc::new(RefCell::new(SiteTreeNode::StaticFile {
-                path: path.clone(),
+            let node = Arc::new(RwLock::new(SiteTreeNode::StaticFile {
+                path: path.to_path_buf(),
                 gen_path: new_gen_path,
-                timestamp,
             }));
-            (node, None)
+            Ok(Some((node, None)))
         }
     }
 
     fn _merge_theme_site_tree(
         &mut self,
         path: PathBuf,
-        gen_path: &PathBuf,
+        gen_path: &Path,
         children: &mut Vec<NodeRef>,
         mut index: Option<PageRef>,
     ) {
-        for entry in path.read_dir().unwrap() {
-            if let Ok(entry) = entry {
-                if entry
-                    .path()
-                    .file_name()
-                    .unwrap()
-                    .to_string_lossy()
-                    .starts_with(|ch: char| ch == '.' || ch == '_')
-                {
-                    continue;
-                }
-                if entry.path().is_dir() {
-                    let correspond_node = children.iter().find(|x| {
+        let entries = match path.read_dir() {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.errors.push(SushiError::io(&path, e));
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with(['.', '_']) {
+                continue;
+            }
+            if entry.path().is_dir() {
+                let correspond_node = children.iter().find(|x| {
+                    if let SiteTreeNode::NormalDir {
+                        children: _, path, ..
+                    } = &*x.read().unwrap()
+                    {
+                        path.file_name() == entry.path().file_name()
+                    } else {
+                        false
+                    }
+                });
+                match correspond_node {
+                    Some(node) => {
                         if let SiteTreeNode::NormalDir {
-                            children: _, path, ..
-                        } = &*x.borrow()
+                            children,
+                            path: _,
+                            gen_path,
+                            index,
+                        } = &mut *node.write().unwrap()
                         {
-                            path.file_name() == entry.path().file_name()
-                        } else {
-                            false
-                        }
-                    });
-                    match correspond_node {
-                        Some(node) => {
-                            if let SiteTreeNode::NormalDir {
-                                children,
-                                path: _,
+                            self._merge_theme_site_tree(
+                                entry.path(),
                                 gen_path,
-                                index,
-                            } = &mut *node.borrow_mut()
-                            {
-                                self._merge_theme_site_tree(
-                                    entry.path(),
-                                    &gen_path,
-                                    children,
-                                    index.clone(),
-                                );
-                            };
-                        }
-                        None => {
-                            let (new_node, _) = self._gen_site_tree(&entry.path(), &gen_path);
+                                children,
+                                index.clone(),
+                            );
+                        };
+                    }
+                    None => match self._gen_site_tree(&entry.path(), gen_path) {
+                        Ok(Some((new_node, _))) => children.push(new_node.clone()),
+                        Ok(None) => (),
+                        Err(e) => self.errors.push(e),
+                    },
+                };
+            } else if entry.path().is_file() {
+                let correspond_node = children.iter().find(|x| match &*x.read().unwrap() {
+                    SiteTreeNode::PageFile { path, .. } => {
+                        path.file_name() == entry.path().file_name()
+                    }
+                    SiteTreeNode::StaticFile { path, .. } => {
+                        path.file_name() == entry.path().file_name()
+                    }
+                    _ => false,
+                });
+                if correspond_node.is_none() {
+                    match self._load_file(&entry.path(), gen_path) {
+                        Ok(Some((new_node, new_index))) => {
                             children.push(new_node.clone());
+                            if index.is_none() {
+                                index = new_index
+                            }
                         }
-                    };
-                } else if entry.path().is_file() {
-                    let correspond_node = children.iter().find(|x| match &*x.borrow() {
-                        SiteTreeNode::PageFile { path, .. } => {
-                            path.file_name() == entry.path().file_name()
-                        }
-                        SiteTreeNode::StaticFile { path, .. } => {
-                            path.file_name() == entry.path().file_name()
-                        }
-                        _ => false,
-                    });
-                    match correspond_node {
-                        None => {
-                            let (new_node, new_index) = self._load_file(&entry.path(), &gen_path);
-                            children.push(new_node.clone());
-                            match index {
-                                None => index = new_index,
-                                _ => (),
-                            }
-                        }
-                        _ => (),
+                        Ok(None) => (),
+                        Err(e) => self.errors.push(e),
                     }
-                } else {
-                    error!("unknown type");
-                    panic!();
                 }
+            } else {
+                self.errors.push(Self::_unknown_type(&entry.path()));
             }
         }
-        Self::_sort_children(children);
+        self._sort_children(gen_path, children);
     }
 
-    fn _sort_children(children: &mut Vec<NodeRef>) {
+    /// Order of the pages in the directory generated at `gen_path`.
+    fn _sort_order_of(&self, gen_path: &Path) -> &SortOrder {
+        let rel_dir = gen_path
+            .strip_prefix(&self.gen_dir)
+            .unwrap_or(Path::new(""));
+        self.sort_orders.order_for(rel_dir)
+    }
+
+    fn _sort_children(&self, gen_path: &Path, children: &mut [NodeRef]) {
         // set page.next and page.last
         let mut list = children
             .iter()
-            .filter_map(|x| match &*x.borrow() {
+            .filter_map(|x| match &*x.read().unwrap() {
                 PageFile { page, .. } => Some(page.clone()),
                 NormalDir { index, .. } => index.clone(),
                 _ => None,
             })
             .collect_vec();
-        // sort according to date
-        // TODO: add more criteria for flexibility
-        list.sort_by(|a, b| match a.borrow().date().cmp(b.borrow().date()) {
-            std::cmp::Ordering::Greater => std::cmp::Ordering::Less,
-            std::cmp::Ordering::Less => std::cmp::Ordering::Greater,
-            std::cmp::Ordering::Equal => std::cmp::Ordering::Equal,
-        });
+        let order = self._sort_order_of(gen_path);
+        list.sort_by(|a, b| order.compare(&a.read().unwrap(), &b.read().unwrap()));
         for (i, n) in list.iter().enumerate() {
-            if i as i64 - 1 >= 0 {
+            if i as i64 > 0 {
                 if let Some(p) = list.get(i - 1) {
-                    n.borrow_mut().set_last(Some(p.clone()));
+                    n.write().unwrap().set_last(Some(p.clone()));
                 }
             }
             if let Some(p) = list.get(i + 1) {
-                n.borrow_mut().set_next(Some(p.clone()));
+                n.write().unwrap().set_next(Some(p.clone()));
             }
         }
     }
 
-    fn is_page(&self, path: &PathBuf) -> bool {
+    fn is_page(&self, path: &Path) -> bool {
         self.convert_ext.contains(
             &path
                 .extension()
@@ -658,7 +1530,7 @@ impl Site {
         )
     }
 
-    fn is_index(&self, path: &PathBuf) -> bool {
+    fn is_index(&self, path: &Path) -> bool {
         let filename = path
             .file_stem()
             .unwrap_or(OsStr::new(""))
@@ -669,190 +1541,353 @@ impl Site {
             .unwrap_or(OsStr::new(""))
             .to_string_lossy()
             .to_string();
-        filename == "index" && self.convert_ext.get(ext.as_str()) != None
+        filename == "index" && self.convert_ext.contains(ext.as_str())
     }
 
-    pub fn gen_page(&self, page: PageRef, base_globals: &mut liquid::Object) {
-        let dest_path = page.borrow().gen_path.clone();
+    /// Convert and render a page, returns whether it has been generated or
+    /// skipped.
+    pub fn gen_page(&self, page: PageRef, globals: &liquid::Object) -> Result<bool> {
+        let dest_path = page.read().unwrap().gen_path.clone();
         // let mut dest_path = path.clone();
-        // dest_path.set_extension(page.borrow().to_ext.clone().unwrap());
-        // let dest_path = self._get_dest_path(path, true, page.borrow().to_ext.clone());
-
-        let paginator = page.borrow().paginate_info();
-        let mut do_gen = self._decide_not_skip_page(page.clone());
-        if let Some(_) = paginator {
-            if !self.naive_skip {
-                do_gen = true;
-            }
-        }
-        if !do_gen {
-            debug!("[skip]  {}", page.borrow().path.clone().to_string_lossy());
-            return;
+        // dest_path.set_extension(page.read().unwrap().to_ext.clone().unwrap());
+        // let dest_path = self._get_dest_path(path, true, page.read().unwrap().to_ext.clone());
+
+        let paginator = page.read().unwrap().paginate_info();
+        let src_path = page.read().unwrap().path.clone();
+        let converter_choice = self._converter_choice(&page.read().unwrap());
+
+        let inputs = self._page_inputs(&page.read().unwrap(), &converter_choice);
+        if !self._decide_not_skip(&src_path, &inputs) {
+            debug!("[skip]  {}", src_path.to_string_lossy());
+            self._keep_record(&src_path);
+            self._record_conversion(&src_path);
+            return Ok(false);
         }
+        let mut outputs = vec![];
 
-        //let (_, content) = extract_front_matter(path);
-        let content = page.borrow().content.clone().as_bytes().to_vec();
-
-        let mut converted = content;
-        let mut converter_choice = String::new();
-        if let Some(choice) = self.converter_choice.get(
-            page.borrow()
-                .path
-                .extension()
-                .unwrap_or(OsStr::new(""))
-                .to_str()
-                .unwrap(),
-        ) {
-            converter_choice = choice.clone();
-        }
+        // most pages have been converted by `_link_pages`
+        let cached = self.converted.lock().unwrap().get(&src_path).cloned();
+        let converted = match cached {
+            Some(html) => html.into_bytes(),
+            None => {
+                self._convert(&page.read().unwrap(), &converter_choice)?
+                    .content
+            }
+        };
+        let converter_error = |message: String| SushiError::Converter {
+            path: src_path.clone(),
+            converter: converter_choice.clone(),
+            message,
+        };
 
-        if let Some(converter) = self.converters.get(&converter_choice) {
-            converted = converter.borrow().convert(converted);
-        } else {
-            debug!("no converter is set, copy by default");
-        }
+        let page_config = page.read().unwrap().get_page_config();
 
-        let page_config = page.borrow().get_page_config();
+        let layout = match page_config.get("layout") {
+            Some(Value::String(layout)) => Some(layout.as_str()),
+            _ => None,
+        };
+        let mut locals = liquid::object!({ "page": page_config });
 
         match paginator {
             None => {
-                let layout = page_config.get("layout");
-                let mut rendered = converted;
-                if let Some(Value::String(layout_str)) = layout {
-                    let mut rendered_str =
-                        String::from_utf8(rendered).expect("Invalid UTF-8 sequence");
-                    debug!("try to use layout {}", layout_str);
-                    let mut current_layout = layout_str;
-                    while let Some(template) = self.templates.get(current_layout) {
-                        debug!("current template {}", current_layout);
-                        base_globals.insert(
-                            "page".parse().unwrap(),
-                            liquid::model::to_value(&page_config).unwrap(),
-                        );
-                        base_globals.insert(
-                            "content".parse().unwrap(),
-                            liquid::model::to_value(&rendered_str).unwrap(),
-                        );
-                        let render_result = template.render(base_globals);
-                        if render_result.is_err() {
-                            error!("{}", render_result.err().unwrap());
-                            panic!("render failed");
-                        }
-                        let current_rendered = render_result.unwrap();
-                        rendered_str = current_rendered;
-                        current_layout = template.get_parent();
+                let rendered = match layout {
+                    Some(layout) => {
+                        let content = String::from_utf8(converted).map_err(|_| {
+                            converter_error("output is not valid UTF-8".to_string())
+                        })?;
+                        self._render_layouts(layout, None, &locals, content, globals, &src_path)?
+                            .into_bytes()
                     }
-                    rendered = rendered_str.as_bytes().to_vec();
-                } else {
-                    debug!("no layout set, copy by default");
-                }
-                info!("[>>conv]  {}", page.borrow().path.clone().to_string_lossy());
+                    None => {
+                        debug!("no layout set, copy by default");
+                        converted
+                    }
+                };
+                info!(
+                    "[>>conv]  {}",
+                    page.read().unwrap().path.clone().to_string_lossy()
+                );
                 debug!("[>>conv] to {:?}", &dest_path);
-                match fs::write(&dest_path, rendered) {
-                    Ok(_) => (),
-                    Err(_) => error!("cannot write to {:?}", dest_path),
+                // permalinks may lead outside the directories of the site
+                if let Some(dir) = dest_path.parent() {
+                    fs::create_dir_all(dir).map_err(|e| SushiError::io(dir, e))?;
                 }
+                fs::write(&dest_path, rendered).map_err(|e| SushiError::io(&dest_path, e))?;
+                outputs.push(dest_path);
             }
-            Some((exp, batch_size)) => {
-                info!("[>>conv]  {}", page.borrow().path.clone().to_string_lossy());
-                match Paginator::from_expression_and_object(
-                    base_globals,
-                    &exp,
-                    batch_size,
+            Some(config) => {
+                info!(
+                    "[>>conv]  {}",
+                    page.read().unwrap().path.clone().to_string_lossy()
+                );
+                let front_matter_error = |message| SushiError::FrontMatter {
+                    path: src_path.clone(),
+                    line: None,
+                    message,
+                };
+                let config = config.map_err(front_matter_error)?;
+                let items = self
+                    ._paginate_items(&config, globals)
+                    .map_err(front_matter_error)?;
+                let pretty = page.read().unwrap().url.ends_with('/');
+                let mut p = Paginator::new(
+                    &items,
+                    config.batch.unwrap_or_default(),
                     dest_path.clone(),
-                ) {
-                    Ok(p) => {
-                        fs::remove_dir(p.base_url_dir())
-                            .unwrap_or(trace!("cannot remove {:?}", p.base_url_dir()));
-                        fs::create_dir(p.base_url_dir())
-                            .unwrap_or(trace!("cannot create {:?}", p.base_url_dir()));
-                        let mut rendered =
-                            String::from_utf8(converted).expect("Invalid UTF-8 sequence");
-                        let mut paginator_object = p.gen_paginator_object();
-                        let batch_urls = p
-                            .batch_paths()
-                            .iter()
-                            .map(|x| self._get_batch_url_from_dest(x))
-                            .collect_vec();
-                        paginator_object.insert(
-                            "batch_urls".parse().unwrap(),
-                            liquid::model::to_value(&batch_urls).unwrap(),
-                        );
-                        for (i, (dest_path, batch)) in p.batch_iter().enumerate() {
-                            let layout = page_config.get("layout");
-                            paginator_object.insert(
-                                "current_batch".parse().unwrap(),
-                                liquid::model::to_value(&batch).unwrap(),
-                            );
-                            paginator_object.insert(
-                                "current_batch_num".parse().unwrap(),
-                                liquid::model::to_value(&i).unwrap(),
-                            );
-                            if i > 0 {
-                                paginator_object.insert(
-                                    "last_batch_num".parse().unwrap(),
-                                    liquid::model::to_value(&(i - 1)).unwrap(),
-                                );
-                            } else {
-                                paginator_object.remove("last_batch_num");
-                            }
-                            if i < batch_urls.len() - 1 {
-                                paginator_object.insert(
-                                    "next_batch_num".parse().unwrap(),
-                                    liquid::model::to_value(&(i + 1)).unwrap(),
-                                );
-                            } else {
-                                paginator_object.remove("next_batch_num");
-                            }
-                            if let Some(Value::String(layout_str)) = layout {
-                                debug!("try to use layout {}", layout_str);
-                                let mut current_layout = layout_str;
-                                while let Some(template) = self.templates.get(current_layout) {
-                                    debug!("current template {}", current_layout);
-                                    base_globals.insert(
-                                        "page".parse().unwrap(),
-                                        liquid::model::to_value(&page_config).unwrap(),
-                                    );
-                                    base_globals.insert(
-                                        "content".parse().unwrap(),
-                                        liquid::model::to_value(&rendered).unwrap(),
-                                    );
-                                    base_globals.insert(
-                                        "paginator".parse().unwrap(),
-                                        liquid::model::to_value(&paginator_object).unwrap(),
-                                    );
-                                    let render_result = template.render(base_globals);
-                                    if render_result.is_err() {
-                                        error!("{}", render_result.err().unwrap());
-                                        panic!("render failed");
-                                    }
-                                    let current_rendered = render_result.unwrap();
-                                    rendered = current_rendered;
-                                    current_layout = template.get_parent();
-                                }
-                            } else {
-                                trace!("no layout set, copy by default");
-                            }
-                            match fs::write(&dest_path, &rendered) {
-                                Ok(_) => (),
-                                Err(_) => error!("cannot write to {:?}", dest_path),
-                            }
+                    pretty,
+                );
+                let mut batch_urls = p
+                    .batch_paths()
+                    .iter()
+                    .map(|x| self._get_batch_url_from_dest(x, pretty))
+                    .collect_vec();
+                if let Some(pattern) = &config.path {
+                    let locations = (1..p.batch_num())
+                        .map(|i| self._batch_location(pattern, &dest_path, pretty, i))
+                        .collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(front_matter_error)?;
+                    batch_urls.truncate(1);
+                    batch_urls.extend(locations.iter().map(|(url, _)| url.clone()));
+                    p = p.with_batch_paths(|i| locations[i - 1].1.clone());
+                }
+                for batch_dir in p.batch_paths().iter().filter_map(|x| x.parent()) {
+                    fs::create_dir_all(batch_dir).map_err(|e| SushiError::io(batch_dir, e))?;
+                }
+                let content = String::from_utf8(converted)
+                    .map_err(|_| converter_error("output is not valid UTF-8".to_string()))?;
+                let mut paginator_object = p.gen_paginator_object(&batch_urls);
+                for (i, (dest_path, batch)) in p.batches().into_iter().enumerate() {
+                    Paginator::set_current_batch(
+                        &mut paginator_object,
+                        i,
+                        &batch,
+                        &batch_urls,
+                        config.window,
+                    );
+                    locals.insert(
+                        "paginator".into(),
+                        liquid::model::Value::Object(paginator_object.clone()),
+                    );
+                    // every batch starts from the content of the page
+                    let rendered = match layout {
+                        Some(layout) => self._render_layouts(
+                            layout,
+                            None,
+                            &locals,
+                            content.clone(),
+                            globals,
+                            &src_path,
+                        )?,
+                        None => {
+                            trace!("no layout set, copy by default");
+                            content.clone()
                         }
-                    }
-                    Err(_) => {
-                        error!("cannot parse {:?} to a list", &exp);
-                    }
+                    };
+                    fs::write(dest_path, &rendered).map_err(|e| SushiError::io(dest_path, e))?;
+                    outputs.push(dest_path.clone());
                 }
             }
         }
+        self._record(&src_path, inputs, outputs);
+        self._record_conversion(&src_path);
+        Ok(true)
+    }
+
+    /// Render `content` with the template `layout` (`default` if there is
+    /// none), then with its parent layouts in turn. `locals` holds `page`
+    /// and the other objects of the page besides `content`.
+    pub(crate) fn _render_layouts(
+        &self,
+        layout: &str,
+        default: Option<&Layout>,
+        locals: &liquid::Object,
+        mut content: String,
+        globals: &liquid::Object,
+        path: &Path,
+    ) -> Result<String> {
+        debug!("try to use layout {}", layout);
+        let mut current_layout = layout;
+        let mut template = self.templates.get(layout).or(default);
+        while let Some(current) = template {
+            debug!("current template {}", current_layout);
+            let mut locals = locals.clone();
+            locals.insert("content".into(), liquid::model::Value::scalar(content));
+            content = current
+                .render(globals, &mut locals)
+                .map_err(|e| Self::_render_error(path, current_layout, e))?;
+            current_layout = current.get_parent();
+            template = self.templates.get(current_layout);
+        }
+        Ok(content)
+    }
+
+    /// Items of a paginated page: the list of its `paginate` expression,
+    /// filtered, sorted and limited.
+    fn _paginate_items(
+        &self,
+        config: &PaginateConfig,
+        globals: &liquid::Object,
+    ) -> std::result::Result<Vec<liquid::model::Value>, String> {
+        let mut items = match paginator::resolve(globals, &config.items)? {
+            liquid::model::Value::Array(items) => items.clone(),
+            _ => {
+                return Err(format!(
+                    "cannot paginate over \"{}\", not a list",
+                    &config.items
+                ))
+            }
+        };
+        if config.selects_pages() {
+            let mut pages = items
+                .iter()
+                .map(|x| {
+                    let id = match x {
+                        liquid::model::Value::Scalar(id) => {
+                            Some(id.clone().into_string().to_string())
+                        }
+                        _ => None,
+                    };
+                    id.and_then(|id| self.id_to_page.get(&id))
+                        .cloned()
+                        .ok_or(format!(
+                            "cannot filter or sort \"{}\", not a list of page ids",
+                            &config.items
+                        ))
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            pages.retain(|x| {
+                let page = x.read().unwrap();
+                config.matches(
+                    &page,
+                    page.path.strip_prefix(&self.site_dir).unwrap_or(&page.path),
+                )
+            });
+            if let Some(order) = &config.sort {
+                pages.sort_by(|a, b| order.compare(&a.read().unwrap(), &b.read().unwrap()));
+            }
+            items = pages
+                .iter()
+                .map(|x| liquid::model::Value::scalar(x.read().unwrap().get_page_id().clone()))
+                .collect();
+        }
+        if let Some(limit) = config.limit {
+            items.truncate(limit);
+        }
+        Ok(items)
+    }
+
+    /// Url and gen path of the `i`th batch of a paginated page at
+    /// `gen_path`, from `path` of its `paginate`.
+    fn _batch_location(
+        &self,
+        pattern: &str,
+        gen_path: &Path,
+        pretty: bool,
+        i: usize,
+    ) -> std::result::Result<(String, PathBuf), String> {
+        let pattern = if pattern.starts_with('/') {
+            pattern.to_string()
+        } else {
+            // relative to `a/` for `a/index.html`, and to `a/b/` for
+            // `a/b.html`
+            let rel_path = gen_path.strip_prefix(&self.gen_dir).unwrap_or(gen_path);
+            let dir = if pretty || rel_path.file_stem().is_some_and(|x| x == "index") {
+                rel_path.parent().unwrap_or(Path::new("")).to_path_buf()
+            } else {
+                rel_path.with_extension("")
+            };
+            let dir = dir
+                .components()
+                .map(|x| x.as_os_str().to_string_lossy())
+                .join("/");
+            format!("/{}/{}", dir, pattern)
+        };
+        let permalink = permalink::expand_with(&pattern, "html", |name| {
+            (name == "n").then(|| i.to_string())
+        })?;
+        Ok((
+            self._site_url_of(permalink.url_path),
+            self.gen_dir.join(permalink.gen_path),
+        ))
+    }
+
+    fn _converter_choice(&self, page: &Page) -> String {
+        self.converter_choice
+            .get(
+                page.path
+                    .extension()
+                    .unwrap_or(OsStr::new(""))
+                    .to_string_lossy()
+                    .as_ref(),
+            )
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Run the converter of a page on its content.
+    fn _convert(&self, page: &Page, converter_choice: &str) -> Result<Converted> {
+        let converter_error = |message| SushiError::Converter {
+            path: page.path.clone(),
+            converter: converter_choice.to_string(),
+            message,
+        };
+        if let Some(message) = self.conversion_errors.lock().unwrap().get(&page.path) {
+            return Err(converter_error(message.clone()));
+        }
+        let content = page.content.as_bytes().to_vec();
+        let path = self._source_key(&page.path);
+        // the internal converter resolves links to other pages
+        let internal;
+        let converter = if converter_choice == "__internal__" {
+            internal = MarkdownParser {
+                heading_anchors: self.heading_anchors,
+                ..MarkdownParser::with_page_links(self.page_links.clone(), page.content_line)
+            };
+            Some(&internal as &dyn Converter)
+        } else {
+            self.converters.get(converter_choice).map(|x| x.as_ref())
+        };
+        match converter {
+            Some(converter) => converter
+                .convert_page(ConverterInput {
+                    content,
+                    path: Path::new(&path),
+                    output_ext: page.to_ext.as_deref().unwrap_or("html"),
+                    site_dir: &self.site_dir,
+                    front_matter: &page.front_matter,
+                    config: &self.config,
+                })
+                .map_err(|message| {
+                    self.conversion_errors
+                        .lock()
+                        .unwrap()
+                        .insert(page.path.clone(), message.clone());
+                    converter_error(message)
+                }),
+            None => {
+                debug!("no converter is set, copy by default");
+                Ok(Converted {
+                    content,
+                    ..Default::default()
+                })
+            }
+        }
+    }
+
+    pub(crate) fn _render_error(path: &Path, template: &str, e: liquid::Error) -> SushiError {
+        SushiError::Render {
+            path: path.to_path_buf(),
+            template: template.to_string(),
+            message: e.to_string(),
+        }
     }
 
     fn _gen_site_tree_object(&self, node: NodeRef) -> (Option<SiteTreeObject>, SiteTreeObjectType) {
-        match &*node.borrow() {
+        match &*node.read().unwrap() {
             NormalDir {
                 children,
                 path,
-                gen_path: _,
+                gen_path,
                 index,
             } => {
                 let mut list = vec![];
@@ -880,13 +1915,9 @@ impl Site {
                         _ => (),
                     }
                 }
-                list.sort_by(|a, b| {
-                    let ((_, a_), (_, b_)) = (a, b);
-                    match a_.clone().borrow().date().cmp(b_.clone().borrow().date()) {
-                        std::cmp::Ordering::Greater => std::cmp::Ordering::Less,
-                        std::cmp::Ordering::Less => std::cmp::Ordering::Greater,
-                        std::cmp::Ordering::Equal => std::cmp::Ordering::Equal,
-                    }
+                let order = self._sort_order_of(gen_path);
+                list.sort_by(|(_, a), (_, b)| {
+                    order.compare(&a.read().unwrap(), &b.read().unwrap())
                 });
                 let list = list.iter().map(|(a, _)| a.clone()).collect_vec();
                 object.insert(
@@ -898,11 +1929,11 @@ impl Site {
                 } else if let Some(page) = index {
                     debug!(
                         "with index: {}",
-                        path.file_stem().unwrap().to_string_lossy().to_string()
+                        path.file_stem().unwrap().to_string_lossy()
                     );
                     SiteTreeObjectType::DirWithIndexPage(
                         path.file_stem().unwrap().to_string_lossy().to_string(),
-                        serde_yaml::Value::String(page.borrow().get_page_id().clone()),
+                        serde_yaml::Value::String(page.read().unwrap().get_page_id().clone()),
                         page.clone(),
                     )
                 } else {
@@ -913,7 +1944,7 @@ impl Site {
             }
             PageFile { page, .. } => (
                 Some(serde_yaml::Value::String(
-                    page.borrow().get_page_id().clone(),
+                    page.read().unwrap().get_page_id().clone(),
                 )),
                 SiteTreeObjectType::Page(page.clone()),
             ),
@@ -921,19 +1952,86 @@ impl Site {
         }
     }
 
+    /// Load `_taxonomies` of the site directory, the files describing terms.
+    fn _load_term_files(&mut self) {
+        let dir = self.site_dir.join("_taxonomies");
+        if !dir.is_dir() {
+            return;
+        }
+        let (files, mut errors) =
+            taxonomy::load_term_files(&dir, self.converters["__internal__"].as_ref());
+        self.errors.append(&mut errors);
+        for (taxo, files) in files {
+            if self.taxonomies.contains_key(&taxo) {
+                self.term_files.insert(taxo, files);
+            } else {
+                warn!(
+                    "{} is not a taxonomy of the site, ignored",
+                    dir.join(&taxo).to_string_lossy()
+                );
+            }
+        }
+    }
+
     fn _gen_taxo_object(&mut self) {
-        // gen self.taxonomies
+        // gen self.taxonomies, pages of nested terms also belonging to their
+        // ancestors
         for page in self.pages.iter() {
             for (taxo, v) in self.taxonomies.iter_mut() {
-                for kind in page.borrow().belongs_to_kind(taxo).iter() {
-                    if let None = v.get(kind) {
-                        v.insert(kind.clone(), RefCell::new(vec![]));
-                    }
-                    v[kind].borrow_mut().push(page.clone());
+                for kind in page.read().unwrap().belongs_to_kind_or_ancestors(taxo) {
+                    v.entry(kind).or_default().push(page.clone());
                 }
             }
         }
 
+        // files of terms are named after the term or its slug
+        for (taxo, files) in self.term_files.iter_mut() {
+            let terms = &self.taxonomies[taxo];
+            let by_slug: HashMap<String, &String> =
+                taxonomy_page::term_slugs(terms.keys(), |_| None)
+                    .into_iter()
+                    .map(|(term, slug)| (slug, terms.get_key_value(&term).unwrap().0))
+                    .collect();
+            *files = std::mem::take(files)
+                .into_iter()
+                .filter_map(|(name, file)| {
+                    let term = match terms.get_key_value(&name) {
+                        Some((term, _)) => term,
+                        None => match by_slug.get(&name) {
+                            Some(term) => *term,
+                            None => {
+                                warn!(
+                                    "{}.md describes no term of {}, ignored",
+                                    self.site_dir
+                                        .join("_taxonomies")
+                                        .join(taxo)
+                                        .join(&name)
+                                        .to_string_lossy(),
+                                    taxo
+                                );
+                                return None;
+                            }
+                        },
+                    };
+                    Some((term.clone(), file))
+                })
+                .collect();
+        }
+        self.term_slugs = self
+            .taxonomies
+            .iter()
+            .map(|(taxo, terms)| {
+                let files = self.term_files.get(taxo);
+                let slugs = taxonomy_page::term_slugs(terms.keys(), |term| {
+                    match files?.get(term)?.front_matter.get("slug") {
+                        Some(Value::String(slug)) => Some(slug.clone()),
+                        _ => None,
+                    }
+                });
+                (taxo.clone(), slugs)
+            })
+            .collect();
+
         // gen self.taxo_object based on self.taxonomies
         let mut taxo_to_kind = serde_yaml::Mapping::new();
         for (taxo, v) in self.taxonomies.iter() {
@@ -941,10 +2039,9 @@ impl Site {
             for (kind, pages) in v.iter() {
                 let mut seq = serde_yaml::Sequence::new();
                 seq.extend(
-                    pages
-                        .borrow()
-                        .iter()
-                        .map(|x| serde_yaml::Value::String(x.borrow().get_page_id().clone())),
+                    pages.iter().map(|x| {
+                        serde_yaml::Value::String(x.read().unwrap().get_page_id().clone())
+                    }),
                 );
                 kind_to_vec.insert(
                     serde_yaml::Value::String(kind.clone()),
@@ -958,6 +2055,17 @@ impl Site {
                     serde_yaml::Value::String(x.clone())
                 }))),
             );
+            kind_to_vec.insert(
+                serde_yaml::Value::String("_terms".to_string()),
+                serde_yaml::Value::Mapping(
+                    v.keys()
+                        .map(|x| {
+                            let term = serde_yaml::Value::Mapping(self._term_object(taxo, x));
+                            (serde_yaml::Value::String(x.clone()), term)
+                        })
+                        .collect(),
+                ),
+            );
             taxo_to_kind.insert(
                 serde_yaml::Value::String(taxo.clone()),
                 serde_yaml::Value::Mapping(kind_to_vec),
@@ -975,12 +2083,82 @@ impl Site {
         self.taxo_object = Some(serde_yaml::Value::Mapping(taxo_to_kind));
     }
 
+    /// `taxo.<taxonomy>._terms.<term>`: the front matter of the file of the
+    /// term, with its name, title, description, slug, count, parent and
+    /// children.
+    pub(crate) fn _term_object(&self, taxo: &str, term: &String) -> serde_yaml::Mapping {
+        let terms = &self.taxonomies[taxo];
+        let file = self.term_files.get(taxo).and_then(|x| x.get(term));
+        let mut object: serde_yaml::Mapping = file
+            .map(|x| {
+                x.front_matter
+                    .iter()
+                    .map(|(k, v)| (Value::String(k.clone()), v.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let title = match object.get("title") {
+            Some(Value::String(title)) => title.clone(),
+            _ => taxonomy::name(term).to_string(),
+        };
+        let children = terms
+            .range::<String, _>((Bound::Excluded(term), Bound::Unbounded))
+            .map(|(x, _)| x)
+            .filter(|x| taxonomy::parent(x) == Some(term.as_str()))
+            .map(|x| Value::String(x.clone()))
+            .collect();
+        let fields = [
+            ("name", Value::String(term.clone())),
+            ("title", Value::String(title)),
+            (
+                "description",
+                file.and_then(|x| x.description.clone())
+                    .map_or(Value::Null, Value::String),
+            ),
+            ("slug", Value::String(self.term_slugs[taxo][term].clone())),
+            ("count", Value::Number(terms[term].len().into())),
+            (
+                "parent",
+                taxonomy::parent(term).map_or(Value::Null, |x| Value::String(x.to_string())),
+            ),
+            ("children", Value::Sequence(children)),
+        ];
+        for (k, v) in fields {
+            object.insert(Value::String(k.to_string()), v);
+        }
+        object
+    }
+
+    /// Urls of the pages of taxonomies (`_index`) and of their terms, with
+    /// `taxonomy_pages`.
+    fn _gen_taxo_urls_object(&mut self) {
+        let Some(config) = &self.taxonomy_pages else {
+            return;
+        };
+        let mut object = serde_yaml::Mapping::new();
+        for taxo in self._taxonomies_with_pages(config) {
+            let mut urls = serde_yaml::Mapping::new();
+            let terms = [None]
+                .into_iter()
+                .chain(self.taxonomies[taxo].keys().map(Some));
+            for term in terms {
+                let Ok((url, _)) = self._taxonomy_page_location(config, taxo, term) else {
+                    continue;
+                };
+                let key = term.cloned().unwrap_or("_index".to_string());
+                urls.insert(Value::String(key), Value::String(url));
+            }
+            object.insert(Value::String(taxo.clone()), Value::Mapping(urls));
+        }
+        self.taxo_urls_object = Some(Value::Mapping(object));
+    }
+
     fn _gen_id_to_page_object(&self) -> serde_yaml::Value {
         let mut obj = serde_yaml::Mapping::new();
         for (k, v) in self.id_to_page.iter() {
             obj.insert(
                 serde_yaml::Value::String(k.clone()),
-                serde_yaml::Value::Mapping(v.borrow().get_page_config_object()),
+                serde_yaml::Value::Mapping(v.read().unwrap().get_page_config_object()),
             );
         }
         serde_yaml::Value::Mapping(obj)
@@ -988,26 +2166,143 @@ impl Site {
 
     fn _gen_all_pages_object(&self) -> serde_yaml::Value {
         let mut obj = serde_yaml::Sequence::new();
-        for p in self.pages.iter() {
-            obj.push(serde_yaml::Value::String(p.borrow().get_page_id().clone()))
+        let order = self.sort_orders.global();
+        let pages = self
+            .pages
+            .iter()
+            .sorted_by(|a, b| order.compare(&a.read().unwrap(), &b.read().unwrap()));
+        for p in pages {
+            obj.push(serde_yaml::Value::String(
+                p.read().unwrap().get_page_id().clone(),
+            ))
         }
         serde_yaml::Value::Sequence(obj)
     }
 
-    pub fn get_page_url(&self, path: &PathBuf, to_ext: String) -> String {
-        let mut temp = PathBuf::from(path.strip_prefix(&self.site_dir).unwrap());
-        let stem = temp.clone();
-        let stem = stem.file_stem().unwrap();
-        temp.pop();
-        temp.push(stem);
-        if let Some(s) = &self.site_url {
-            s.to_string() + "/" + temp.to_str().unwrap() + "." + to_ext.as_str()
-        } else {
-            String::from("/") + temp.to_str().unwrap() + "." + to_ext.as_str()
+    /// Date of a page: its `date`, or in reproducible builds the time of its
+    /// source, else the current time.
+    fn _page_date(
+        &self,
+        path: &Path,
+        fm: &HashMap<String, Value>,
+    ) -> chrono::DateTime<chrono::FixedOffset> {
+        Page::parse_date(fm, self.zone).unwrap_or_else(|| match &self.source_times {
+            Some(source_times) => source_times.time_of(path),
+            None => self.zone.now(),
+        })
+    }
+
+    /// Url and gen path of a page dated `date`. `gen_path` is the gen
+    /// directory mirroring the directory of the page. The permalink pattern comes from
+    /// the front matter, or `permalinks`/`permalink` of `_site.yml` (which do
+    /// not apply to index pages); without one, the page mirrors its source.
+    fn _page_location(
+        &self,
+        path: &Path,
+        gen_path: &Path,
+        fm: &HashMap<String, Value>,
+        to_ext: &str,
+        date: &chrono::DateTime<chrono::FixedOffset>,
+    ) -> Result<(String, PathBuf)> {
+        let rel_dir = gen_path
+            .strip_prefix(&self.gen_dir)
+            .unwrap_or(Path::new(""));
+        let stem = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let front_matter_error = |message: String| SushiError::FrontMatter {
+            path: path.to_path_buf(),
+            line: None,
+            message,
+        };
+        let pattern = match fm.get("permalink") {
+            Some(Value::String(pattern)) => Some(pattern),
+            Some(_) => {
+                return Err(front_matter_error(
+                    "`permalink` should be a string".to_string(),
+                ))
+            }
+            None if self.is_index(path) => None,
+            None => self.permalinks.pattern_for(rel_dir),
+        };
+        let url_path = match pattern {
+            None => {
+                let gen_path = gen_path.join(format!("{}.{}", &stem, to_ext));
+                let url_path = rel_dir
+                    .components()
+                    .map(|x| x.as_os_str().to_string_lossy().to_string())
+                    .chain([format!("{}.{}", &stem, to_ext)])
+                    .join("/");
+                return Ok((self._site_url_of("/".to_string() + &url_path), gen_path));
+            }
+            Some(pattern) => pattern,
+        };
+        let slug = match fm.get("slug") {
+            Some(Value::String(slug)) => slug.clone(),
+            _ => {
+                // an index page is named after its directory
+                let name = if self.is_index(path) {
+                    rel_dir
+                        .file_name()
+                        .map(|x| x.to_string_lossy().to_string())
+                        .unwrap_or_default()
+                } else {
+                    stem.clone()
+                };
+                match slug::slugify(&name) {
+                    slug if slug.is_empty() => name,
+                    slug => slug,
+                }
+            }
+        };
+        let title = match fm.get("title") {
+            Some(Value::String(title)) => Some(title.as_str()),
+            _ => None,
+        };
+        let vars = PermalinkVars {
+            date,
+            slug,
+            title,
+            path: rel_dir,
+            stem: &stem,
+            ext: to_ext,
+        };
+        let permalink = permalink::expand(url_path, &vars).map_err(front_matter_error)?;
+        Ok((
+            self._site_url_of(permalink.url_path),
+            self.gen_dir.join(permalink.gen_path),
+        ))
+    }
+
+    /// Prefix an absolute url path (e.g. `/posts/`) with the site url.
+    pub(crate) fn _site_url_of(&self, url_path: String) -> String {
+        match &self.site_url {
+            Some(s) => s.to_string() + &url_path,
+            None => url_path,
+        }
+    }
+
+    /// Path of an url, i.e. the url without scheme and host, as server-side
+    /// redirects expect.
+    pub(crate) fn _url_path_of(url: &str) -> String {
+        match url.split_once("://") {
+            Some((_, rest)) => match rest.find('/') {
+                Some(i) => rest[i..].to_string(),
+                None => "/".to_string(),
+            },
+            None => url.to_string(),
         }
     }
 
-    fn _get_batch_url_from_dest(&self, path: &PathBuf) -> String {
+    /// Url of a batch of a paginated page, from its gen path. Batches of a
+    /// page with a pretty url (ending with `/`) are `index.html` of their own
+    /// directory.
+    pub(crate) fn _get_batch_url_from_dest(&self, path: &Path, pretty: bool) -> String {
+        if pretty {
+            return self._gen_url(path.parent().unwrap_or(path)) + "/";
+        }
         let mut temp = PathBuf::from(path.strip_prefix(&self.gen_dir).unwrap());
         let stem = temp.clone();
         let stem = stem.file_stem().unwrap();
@@ -1043,7 +2338,7 @@ impl Site {
         HashSet<String>,
         HashMap<String, String>,
         HashMap<String, String>,
-        HashMap<String, HashMap<String, RefCell<Vec<PageRef>>>>,
+        Taxonomies,
     ) {
         let mut convert_ext = HashSet::new();
         if let Some(Value::Sequence(ext)) = config.get("convert_ext") {
@@ -1074,11 +2369,11 @@ impl Site {
             }
         }
 
-        let mut taxonomies = HashMap::new();
+        let mut taxonomies = Taxonomies::new();
         if let Some(Value::Sequence(taxo)) = config.get("taxonomies") {
             taxonomies.extend(taxo.iter().filter_map(|x| {
                 if let Value::String(s) = x {
-                    Some((s.clone(), HashMap::new()))
+                    Some((s.clone(), BTreeMap::new()))
                 } else {
                     None
                 }
@@ -1089,34 +2384,38 @@ impl Site {
 
     fn _parse_gen(
         path: &PathBuf,
-        existing_map: Rc<RefCell<HashMap<PathBuf, ETNodeRef>>>,
+        existing_map: Arc<RwLock<HashMap<PathBuf, ETNodeRef>>>,
     ) -> Option<ETNodeRef> {
         if path.is_dir() {
             let mut children: Vec<ETNodeRef> = vec![];
-            for entry in path.read_dir().unwrap() {
-                if let Ok(entry) = entry {
-                    let child = Self::_parse_gen(&entry.path(), existing_map.clone());
-                    if let Some(child) = child {
-                        children.push(child);
-                    }
+            for entry in path.read_dir().into_iter().flatten().flatten() {
+                let child = Self::_parse_gen(&entry.path(), existing_map.clone());
+                if let Some(child) = child {
+                    children.push(child);
                 }
             }
             debug!("scan _gen: dir {:?}", path);
-            let ret = Rc::new(RefCell::new(ExistingTreeNode::NormalDir {
+            let ret = Arc::new(RwLock::new(ExistingTreeNode::NormalDir {
                 children,
                 path: path.clone(),
             }));
-            existing_map.borrow_mut().insert(path.clone(), ret.clone());
+            existing_map
+                .write()
+                .unwrap()
+                .insert(path.clone(), ret.clone());
             Some(ret)
         } else if path.is_file() {
             if let Ok(metadata) = path.metadata() {
                 let time = metadata.modified().unwrap_or(SystemTime::now());
                 debug!("scan _gen: file {:?} at {:?}", path, time);
-                let ret = Rc::new(RefCell::new(ExistingTreeNode::File {
+                let ret = Arc::new(RwLock::new(ExistingTreeNode::File {
                     path: path.clone(),
                     timestamp: time,
                 }));
-                existing_map.borrow_mut().insert(path.clone(), ret.clone());
+                existing_map
+                    .write()
+                    .unwrap()
+                    .insert(path.clone(), ret.clone());
                 Some(ret)
             } else {
                 None
@@ -1128,65 +2427,269 @@ impl Site {
     }
 
     fn lookup_existing_map(&self, path: &PathBuf) -> Option<ETNodeRef> {
-        if let Some(et) = self.existing_map.borrow().get(path) {
-            Some(et.clone())
-        } else {
-            None
-        }
+        self.existing_map.read().unwrap().get(path).cloned()
     }
 
-    fn _decide_not_skip_static(&self, dest_path: &PathBuf, src_timestamp: &SystemTime) -> bool {
-        if self.regen_all {
-            return true;
+    /// Whether the outputs of `path` have to be (re)generated, i.e. any of
+    /// its inputs changed since the last build or an output is missing.
+    fn _decide_not_skip(&self, path: &Path, inputs: &Inputs) -> bool {
+        // with `--subpath`, sources elsewhere are left as they are
+        if !self._in_subpath(path) {
+            return false;
+        }
+        match self.manifest.get(&self._source_key(path)) {
+            Some(entry) => {
+                entry.inputs != *inputs
+                    || entry
+                        .outputs
+                        .iter()
+                        .any(|x| self.lookup_existing_map(&self.gen_dir.join(x)).is_none())
+            }
+            None => true,
         }
-        self.is_src_newer(dest_path, src_timestamp)
     }
 
-    fn _decide_not_skip_page(&self, page: PageRef) -> bool {
-        if self.regen_all {
-            return true;
+    pub(crate) fn _source_key(&self, path: &Path) -> String {
+        path.strip_prefix(&self.site_dir)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    pub(crate) fn _record(&self, path: &Path, inputs: Inputs, outputs: Vec<PathBuf>) {
+        let outputs = outputs
+            .iter()
+            .map(|x| {
+                x.strip_prefix(&self.gen_dir)
+                    .unwrap_or(x)
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect_vec();
+        self.next_manifest.lock().unwrap().insert(
+            self._source_key(path),
+            ManifestEntry {
+                inputs,
+                outputs,
+                conversion: None,
+            },
+        );
+    }
+
+    /// Carry the entry of a skipped source over to the next manifest.
+    fn _keep_record(&self, path: &Path) {
+        let key = self._source_key(path);
+        if let Some(entry) = self.manifest.get(&key) {
+            self.next_manifest
+                .lock()
+                .unwrap()
+                .insert(key, entry.clone());
         }
+    }
 
-        if self.subpath != None {
-            return self._in_subpath(&page.borrow().path);
+    /// Whether the files generated from the whole site under `key` (feeds,
+    /// sitemap...) are up to date, keeping their record if so.
+    pub(crate) fn _skip_synthetic(&self, key: &Path, inputs: &Inputs, name: &str) -> bool {
+        if self._decide_not_skip(key, inputs) {
+            return false;
         }
+        debug!("[  skip]  {}", name);
+        self._keep_record(key);
+        true
+    }
 
-        let self_is_newer = self.is_src_newer(&page.borrow().gen_path, page.borrow().gen_time());
+    /// Gen paths of the site tree, which files generated from the whole site
+    /// never overwrite.
+    pub(crate) fn _tree_outputs(&self) -> HashSet<PathBuf> {
+        let mut tree_outputs = HashSet::new();
+        if let Some(site_tree) = &self.site_tree {
+            Self::_collect_gen_paths(site_tree.clone(), &mut tree_outputs);
+        }
+        tree_outputs
+    }
 
-        if self.naive_skip {
-            return self_is_newer;
+    /// Write a file generated from the whole site, unless a file of the site
+    /// tree is generated at `path`. Returns whether it is written.
+    pub(crate) fn _write_synthetic(
+        tree_outputs: &HashSet<PathBuf>,
+        path: &Path,
+        content: impl AsRef<[u8]>,
+        kind: &str,
+    ) -> std::io::Result<bool> {
+        if tree_outputs.contains(path) {
+            warn!(
+                "{:?} is generated from a source file, not overwritten",
+                path
+            );
+            return Ok(false);
         }
+        info!("[>>{}]  {}", kind, path.to_string_lossy());
+        path.parent().map_or(Ok(()), fs::create_dir_all)?;
+        fs::write(path, content)?;
+        Ok(true)
+    }
 
-        let mut next_is_newer = false;
-        let mut last_is_newer = false;
-        if let Some(next_page) = page.borrow().next() {
-            next_is_newer =
-                self.is_src_newer(&next_page.borrow().gen_path, next_page.borrow().gen_time());
+    fn _record_conversion(&self, path: &Path) {
+        if let Some(conversion) = self.conversions.get(path) {
+            self.next_manifest
+                .lock()
+                .unwrap()
+                .set_conversion(&self._source_key(path), conversion.clone());
         }
-        if let Some(last_page) = page.borrow().last() {
-            last_is_newer =
-                self.is_src_newer(&last_page.borrow().gen_path, last_page.borrow().gen_time());
+    }
+
+    fn _page_inputs(&self, page: &Page, converter_choice: &str) -> Inputs {
+        let neighbour_id = |x: &Option<PageRef>| {
+            x.as_ref()
+                .map(|x| x.read().unwrap().get_page_id().clone())
+                .unwrap_or_default()
+        };
+        let front_matter = manifest::hash_parts([
+            manifest::hash_config(&page.front_matter).as_bytes(),
+            page.url.as_bytes(),
+            page.to_ext.clone().unwrap_or_default().as_bytes(),
+            neighbour_id(page.next()).as_bytes(),
+            neighbour_id(page.last()).as_bytes(),
+            page.backlinks().join(" ").as_bytes(),
+            page.related().join(" ").as_bytes(),
+        ]);
+
+        let layout = match page.front_matter.get("layout") {
+            Some(Value::String(layout)) => layout.as_str(),
+            _ => "",
+        };
+        let (layouts, partials, uses_index) = self._layout_inputs(&[layout]);
+        let uses_index = uses_index || page.paginate_info().is_some();
+
+        let converter = self._converter_input(page, converter_choice);
+        let site_index = if uses_index {
+            self.site_index_hash.as_str()
+        } else {
+            ""
+        };
+
+        Inputs {
+            source: manifest::hash_bytes(page.content.as_bytes()),
+            front_matter: Some(front_matter),
+            layouts: Some(layouts),
+            partials: Some(partials),
+            converter: Some(converter),
+            site: Some(manifest::hash_parts([
+                self.config_hash.as_bytes(),
+                site_index.as_bytes(),
+            ])),
         }
-        self_is_newer || next_is_newer || last_is_newer
     }
 
-    fn is_src_newer(&self, dest_path: &PathBuf, src_timestamp: &SystemTime) -> bool {
-        if let Some(et) = self.lookup_existing_map(dest_path) {
-            // check time
-            match &*et.clone().borrow() {
-                File { path: _, timestamp } => {
-                    if timestamp < src_timestamp {
-                        true
-                    } else {
-                        // dest is newer than src
-                        false
+    /// Hashes of the layout chains starting at `layouts` and of the partials
+    /// they include, and whether any of them uses the site index.
+    pub(crate) fn _layout_inputs(&self, layouts: &[&str]) -> (String, String, bool) {
+        let mut chain = vec![];
+        let mut partials = HashSet::new();
+        let mut uses_index = false;
+        for layout in layouts {
+            let mut current_layout = *layout;
+            while let Some(template) = self.templates.get(current_layout) {
+                chain.push(current_layout.to_string());
+                chain.push(
+                    self.template_hashes
+                        .get(current_layout)
+                        .cloned()
+                        .unwrap_or_default(),
+                );
+                if let Some(deps) = self.template_partials.get(current_layout) {
+                    // a partial included by a variable may be any of them
+                    if deps.iter().any(|x| !self.partial_hashes.contains_key(x)) {
+                        partials.extend(self.partial_hashes.keys().cloned());
                     }
+                    partials.extend(deps.iter().cloned());
                 }
-                _ => true,
+                uses_index |= self.index_templates.contains(current_layout);
+                current_layout = template.get_parent();
+            }
+        }
+        let mut partials = partials
+            .into_iter()
+            .flat_map(|x| {
+                let hash = self.partial_hashes.get(&x).cloned().unwrap_or_default();
+                [x, hash]
+            })
+            .collect_vec();
+        partials.sort();
+        (
+            manifest::hash_parts(chain.iter().map(|x| x.as_bytes())),
+            manifest::hash_parts(partials.iter().map(|x| x.as_bytes())),
+            uses_index,
+        )
+    }
+
+    /// Hash of the converter of a page, and of everything else the
+    /// converted content depends on.
+    fn _converter_input(&self, page: &Page, converter_choice: &str) -> String {
+        let mut parts = vec![];
+        // converters speaking json also read the front matter and `_site.yml`
+        let protocol = self
+            .converter_configs
+            .get(converter_choice)
+            .map(|x| x.protocol);
+        if protocol == Some(Protocol::Json) {
+            parts.push(manifest::hash_config(&page.front_matter));
+            parts.push(self.config_hash.clone());
+        }
+        // options of the internal converter, and the url and title of the
+        // pages linked by `@page_id`
+        if converter_choice == "__internal__" {
+            if self.heading_anchors {
+                parts.push("heading_anchors".to_string());
+            }
+            for page_id in link_event_processor::linked_page_ids(&page.content) {
+                parts.push(page_id.to_string());
+                if let Some(link) = self.page_links.get(page_id) {
+                    parts.push(link.url.clone());
+                    parts.push(link.title.clone());
+                }
+            }
+        }
+        manifest::hash_parts(
+            [
+                converter_choice.as_bytes(),
+                self.converter_hashes
+                    .get(converter_choice)
+                    .map(|x| x.as_str())
+                    .unwrap_or_default()
+                    .as_bytes(),
+            ]
+            .into_iter()
+            .chain(parts.iter().map(|x| x.as_bytes())),
+        )
+    }
+
+    /// Hash of everything the global objects (sitetree, taxo, all_pages and
+    /// id_to_page) are generated from.
+    fn _site_index_hash(&self) -> String {
+        let mut pages = self.pages.iter().map(|x| x.read().unwrap()).collect_vec();
+        pages.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut parts = vec![];
+        for page in pages {
+            parts.push(page.path.to_string_lossy().to_string());
+            parts.push(page.url.clone());
+            parts.push(page.get_page_id().clone());
+            parts.push(manifest::hash_config(&page.front_matter));
+            parts.push(manifest::hash_bytes(page.content.as_bytes()));
+        }
+        let mut sources = self
+            .source_paths()
+            .iter()
+            .map(|x| x.to_string_lossy().to_string())
+            .collect_vec();
+        sources.sort();
+        parts.extend(sources);
+        for (taxo, files) in self.term_files.iter() {
+            for (term, file) in files.iter() {
+                parts.extend([taxo.clone(), term.clone(), file.hash.clone()]);
             }
-        } else {
-            true
         }
+        manifest::hash_parts(parts.iter().map(|x| x.as_bytes()))
     }
 
     fn _decide_site_config(
@@ -1206,35 +2709,213 @@ impl Site {
         }
     }
 
-    fn _in_subpath(&self, path: &PathBuf) -> bool {
+    fn _in_subpath(&self, path: &Path) -> bool {
         match &self.subpath {
             None => true,
             Some(real_subpath) => {
-                for p in real_subpath {
-                    if p.is_dir() {
-                        if path.canonicalize().unwrap().starts_with(
-                            p.canonicalize().expect(
-                                format!("improper subpath {}", p.to_str().unwrap()).as_str(),
-                            ),
-                        ) {
-                            return true;
-                        }
-                    } else if p.is_file() {
-                        if p.canonicalize()
-                            .expect(format!("improper subpath {}", p.to_str().unwrap()).as_str())
-                            == path.canonicalize().unwrap()
-                        {
-                            return true;
-                        }
+                // subpaths are canonicalized in `parse_site_dir`
+                let path = match path.canonicalize() {
+                    Ok(path) => path,
+                    Err(_) => return false,
+                };
+                real_subpath.iter().any(|p| path.starts_with(p))
+            }
+        }
+    }
+
+    /// Remove files in the gen directory that are not generated from any
+    /// source, and directories left empty. Dotfiles (e.g. `.git`) are kept.
+    fn _prune(&self, report: &mut BuildReport) {
+        let mut expected = HashSet::new();
+        if let Some(site_tree) = &self.site_tree {
+            Self::_collect_gen_paths(site_tree.clone(), &mut expected);
+        }
+        expected.extend(
+            self.next_manifest
+                .lock()
+                .unwrap()
+                .outputs()
+                .map(|x| self.gen_dir.join(x)),
+        );
+        expected.insert(self.gen_dir.join(MANIFEST_FILE));
+        let expected_dirs: HashSet<PathBuf> = expected
+            .iter()
+            .flat_map(|x| x.ancestors().skip(1).map(|x| x.to_path_buf()))
+            .collect();
+        if let Some(root) = self.lookup_existing_map(&self.gen_dir) {
+            self._prune_node(root, &expected, &expected_dirs, report);
+        }
+    }
+
+    /// Returns whether the node has been removed.
+    fn _prune_node(
+        &self,
+        node: ETNodeRef,
+        expected: &HashSet<PathBuf>,
+        expected_dirs: &HashSet<PathBuf>,
+        report: &mut BuildReport,
+    ) -> bool {
+        match &*node.read().unwrap() {
+            ExistingTreeNode::NormalDir { children, path } => {
+                if Self::_is_dotfile(path) {
+                    return false;
+                }
+                let mut all_removed = true;
+                for child in children.iter() {
+                    all_removed &= self._prune_node(child.clone(), expected, expected_dirs, report);
+                }
+                if all_removed && *path != self.gen_dir && !expected_dirs.contains(path) {
+                    self._prune_path(path, report)
+                } else {
+                    false
+                }
+            }
+            ExistingTreeNode::File { path, .. } => {
+                if Self::_is_dotfile(path) || expected.contains(path) {
+                    return false;
+                }
+                self._prune_path(path, report)
+            }
+        }
+    }
+
+    /// Remove outputs of earlier builds that must not stay published: those
+    /// of deleted sources, of drafts and future pages, and feeds (or other
+    /// files generated from all pages) that are no longer generated, e.g.
+    /// the feed of a term only used by a draft.
+    fn _remove_withdrawn(&self, report: &mut BuildReport) {
+        let mut keys = self
+            .excluded
+            .iter()
+            .map(|x| self._source_key(x))
+            .collect_vec();
+        keys.extend(self._deleted_sources());
+        // these are not recorded when they fail, keep them until they succeed
+        if report.is_ok() {
+            keys.extend(SYNTHETIC_SOURCES.map(String::from));
+            // batches of paginated pages come and go with their lists
+            keys.extend(self.pages.iter().filter_map(|x| {
+                let page = x.read().unwrap();
+                page.paginate_info()
+                    .is_some()
+                    .then(|| self._source_key(&page.path))
+            }));
+        }
+        let expected: HashSet<String> = self
+            .next_manifest
+            .lock()
+            .unwrap()
+            .outputs()
+            .cloned()
+            .collect();
+        for key in keys {
+            let Some(entry) = self.manifest.get(&key) else {
+                continue;
+            };
+            for output in entry.outputs.iter().filter(|x| !expected.contains(*x)) {
+                let output = self.gen_dir.join(output);
+                if self.lookup_existing_map(&output).is_none()
+                    || !self._prune_path(&output, report)
+                    || self.dry_run
+                {
+                    continue;
+                }
+                // and the directories left empty
+                for dir in output.ancestors().skip(1) {
+                    let is_empty = fs::read_dir(dir).is_ok_and(|mut x| x.next().is_none());
+                    if dir == self.gen_dir || !is_empty || !self._prune_path(dir, report) {
+                        break;
                     }
                 }
+            }
+        }
+    }
+
+    /// Sources of the last build that no longer exist.
+    fn _deleted_sources(&self) -> Vec<String> {
+        self.manifest
+            .sources()
+            .filter(|x| !SYNTHETIC_SOURCES.contains(&x.as_str()))
+            .filter(|x| !self.site_dir.join(x).exists())
+            .cloned()
+            .collect_vec()
+    }
+
+    fn _prune_path(&self, path: &Path, report: &mut BuildReport) -> bool {
+        // removed along with a deleted source before pruning
+        if report.pruned.iter().any(|x| x == path) {
+            return true;
+        }
+        report.pruned.push(path.to_path_buf());
+        if self.dry_run {
+            info!("[-prune]  {} (dry run)", path.to_string_lossy());
+            return true;
+        }
+        info!("[-prune]  {}", path.to_string_lossy());
+        let result = if path.is_dir() {
+            fs::remove_dir(path)
+        } else {
+            fs::remove_file(path)
+        };
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                report.pruned.pop();
+                report.errors.push(SushiError::io(path, e));
                 false
             }
         }
     }
 
+    fn _is_dotfile(path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|x| x.to_string_lossy().starts_with('.'))
+    }
+
+    fn _collect_gen_paths(current_node: NodeRef, paths: &mut HashSet<PathBuf>) {
+        match &*current_node.read().unwrap() {
+            NormalDir {
+                children, gen_path, ..
+            } => {
+                paths.insert(gen_path.clone());
+                for child in children.iter() {
+                    Self::_collect_gen_paths(child.clone(), paths);
+                }
+            }
+            PageFile { page, .. } => {
+                paths.insert(page.read().unwrap().gen_path.clone());
+            }
+            StaticFile { gen_path, .. } => {
+                paths.insert(gen_path.clone());
+            }
+        }
+    }
+
+    /// Paths of all page and static files in the site tree (including those
+    /// merged from the theme). Only meaningful after `generate_site`.
+    pub fn source_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![];
+        if let Some(site_tree) = &self.site_tree {
+            Self::_collect_source_paths(site_tree.clone(), &mut paths);
+        }
+        paths
+    }
+
+    fn _collect_source_paths(current_node: NodeRef, paths: &mut Vec<PathBuf>) {
+        match &*current_node.read().unwrap() {
+            SiteTreeNode::NormalDir { children, .. } => {
+                for child in children.iter() {
+                    Self::_collect_source_paths(child.clone(), paths);
+                }
+            }
+            SiteTreeNode::PageFile { path, .. } | SiteTreeNode::StaticFile { path, .. } => {
+                paths.push(path.clone());
+            }
+        }
+    }
+
     fn _print_site_tree(current_node: NodeRef, indent: &String) {
-        match &*current_node.borrow() {
+        match &*current_node.read().unwrap() {
             SiteTreeNode::NormalDir { children, path, .. } => {
                 let new_indent = indent.clone() + " |";
                 debug!(
@@ -1265,3 +2946,575 @@ impl Site {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{in_dir, read_tree, site_configs, TempDir};
+
+    fn build(site_dir: &Path) -> BuildReport {
+        build_with(site_dir, site_configs())
+    }
+
+    fn build_with(site_dir: &Path, site_configs: SiteConfigs) -> BuildReport {
+        let report = in_dir(site_dir, || {
+            let mut site = Site::parse_site_dir(".".into(), false, site_configs).unwrap();
+            site.generate_site()
+        });
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        report
+    }
+
+    #[test]
+    fn incremental_builds() {
+        let dir = TempDir::new("site");
+        dir.write("_site.yml", "site_name: test\nconvert_ext: [md]\n");
+        dir.write("_includes/footer.liquid", "footer v1");
+        dir.write(
+            "_templates/page.liquid",
+            "{{ page.title }} {% include \"footer\" %}",
+        );
+        dir.write("_templates/plain.liquid", "{{ page.title }}");
+        dir.write("a.md", "---\ntitle: A\nlayout: page\n---\n");
+        dir.write("b.md", "---\ntitle: B\nlayout: plain\n---\n");
+        let read = |x: &str| fs::read_to_string(dir.path().join("_gen").join(x)).unwrap();
+
+        let report = build(dir.path());
+        assert_eq!((report.pages, report.skipped), (2, 0));
+        assert_eq!(read("a.html"), "A footer v1\n");
+
+        // nothing changed, nothing regenerated
+        let report = build(dir.path());
+        assert_eq!((report.pages, report.skipped), (0, 2));
+
+        // only the pages of templates including the partial are regenerated
+        dir.write("_includes/footer.liquid", "footer v2");
+        let report = build(dir.path());
+        assert_eq!((report.pages, report.skipped), (1, 1));
+        assert_eq!(read("a.html"), "A footer v2\n");
+        assert_eq!(read("b.html"), "B\n");
+    }
+
+    #[test]
+    fn subpath_builds_are_incremental() {
+        let dir = TempDir::new("site");
+        dir.write("_site.yml", "site_name: test\nconvert_ext: [md]\n");
+        dir.write("_templates/page.liquid", "{{ page.title }}");
+        dir.write("a.md", "---\ntitle: A\nlayout: page\n---\n");
+        dir.write("posts/b.md", "---\ntitle: B\nlayout: page\n---\n");
+        dir.write("posts/c.md", "---\ntitle: C\nlayout: page\n---\n");
+        build(dir.path());
+        let read = |x: &str| fs::read_to_string(dir.path().join("_gen").join(x)).unwrap();
+        let subpath = |x: &str| SiteConfigs {
+            subpath: Some(vec![x.to_string()]),
+            ..site_configs()
+        };
+
+        dir.write("a.md", "---\ntitle: A2\nlayout: page\n---\n");
+        dir.write("posts/b.md", "---\ntitle: B2\nlayout: page\n---\n");
+        // only the changed page under the subpath
+        let report = build_with(dir.path(), subpath("posts"));
+        assert_eq!(report.pages, 1);
+        assert_eq!(
+            (read("a.html"), read("posts/b.html")),
+            ("A\n".into(), "B2\n".into())
+        );
+        let report = build_with(dir.path(), subpath("posts"));
+        assert_eq!(report.pages, 0);
+        // the rest is left for the next build
+        let report = build(dir.path());
+        assert_eq!(report.pages, 1);
+        assert_eq!(read("a.html"), "A2\n");
+    }
+
+    #[test]
+    fn outputs_of_deleted_sources_are_removed() {
+        let dir = TempDir::new("site");
+        dir.write("_site.yml", "site_name: test\nconvert_ext: [md]\n");
+        dir.write("_templates/page.liquid", "{{ page.title }}");
+        dir.write("a.md", "---\ntitle: A\nlayout: page\n---\n");
+        dir.write("old/b.md", "---\ntitle: B\nlayout: page\n---\n");
+        dir.write("assets/x.css", "body {}");
+        build(dir.path());
+        dir.write("_gen/stray.txt", "");
+        fs::remove_dir_all(dir.path().join("old")).unwrap();
+        fs::remove_dir_all(dir.path().join("assets")).unwrap();
+        let gen = dir.path().join("_gen");
+        let pruned = |report: BuildReport| {
+            let mut pruned = report
+                .pruned
+                .iter()
+                .map(|x| {
+                    x.strip_prefix("./_gen")
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect_vec();
+            pruned.sort();
+            pruned
+        };
+
+        // a dry run keeps them for the next build
+        let report = build_with(
+            dir.path(),
+            SiteConfigs {
+                dry_run: true,
+                ..site_configs()
+            },
+        );
+        assert!(gen.join("old/b.html").exists());
+        assert!(pruned(report).contains(&"old/b.html".to_string()));
+
+        // without `--prune`, only what was generated from them
+        let report = build(dir.path());
+        assert_eq!(
+            pruned(report),
+            ["assets", "assets/x.css", "old", "old/b.html"]
+        );
+        assert!(!gen.join("old").exists() && !gen.join("assets").exists());
+        assert!(gen.join("a.html").exists() && gen.join("stray.txt").exists());
+    }
+
+    #[test]
+    fn dynamic_includes_are_rebuilt_with_the_site() {
+        let dir = TempDir::new("site");
+        dir.write("_site.yml", "site_name: test\nconvert_ext: [md]\n");
+        dir.write("_includes/count.liquid", "{{ all_pages | size }}");
+        dir.write("_templates/page.liquid", "{% include page.widget %}");
+        dir.write("_templates/plain.liquid", "{{ page.title }}");
+        dir.write("a.md", "---\nlayout: page\nwidget: count\n---\n");
+        dir.write("b.md", "---\ntitle: B\nlayout: plain\n---\n");
+        build(dir.path());
+        let read = |x: &str| fs::read_to_string(dir.path().join("_gen").join(x)).unwrap();
+        assert_eq!(read("a.html"), "2\n");
+
+        dir.write("c.md", "---\ntitle: C\nlayout: plain\n---\n");
+        let report = build(dir.path());
+        assert_eq!((report.pages, report.skipped), (2, 1));
+        assert_eq!(read("a.html"), "3\n");
+    }
+
+    #[test]
+    fn parallel_builds_are_deterministic() {
+        let fixture = |dir: &TempDir| {
+            dir.write(
+                "_site.yml",
+                "site_name: test\nurl: https://example.com\nconvert_ext: [md]\n\
+                 taxonomies: [tag]\nfeeds:\n  dirs: [posts]\nsitemap: true\nsearch: true\n",
+            );
+            dir.write(
+                "_templates/page.liquid",
+                "<h1>{{ page.title }}</h1>{{ content }}\
+                 {% for id in all_pages %}{{ id_to_page[id].url }} {% endfor %}",
+            );
+            dir.write(
+                "_templates/list.liquid",
+                "{% for id in paginator.current_batch %}{{ id_to_page[id].title }} {% endfor %}",
+            );
+            dir.write(
+                "index.md",
+                "---\nlayout: list\npaginate: sitetree.posts._list\npaginate_batch: 3\n---\n",
+            );
+            for i in 0..24 {
+                dir.write(
+                    &format!("posts/p{}.md", i),
+                    &format!(
+                        "---\ntitle: Post {i}\nlayout: page\ndate: 2024-01-{:02}\n\
+                         tag: [t{}, all]\n---\n# Post {i}\n\nText of *post* {i}.\n",
+                        i + 1,
+                        i % 4
+                    ),
+                );
+            }
+            dir.write("assets/style.css", "body {}");
+        };
+        let (a, b) = (TempDir::new("site"), TempDir::new("site"));
+        fixture(&a);
+        fixture(&b);
+        let one = build_with(
+            a.path(),
+            SiteConfigs {
+                jobs: Some(1),
+                ..site_configs()
+            },
+        );
+        let many = build_with(
+            b.path(),
+            SiteConfigs {
+                jobs: Some(8),
+                ..site_configs()
+            },
+        );
+        assert_eq!(one.pages, many.pages);
+        let (one, many) = (
+            read_tree(&a.path().join("_gen")),
+            read_tree(&b.path().join("_gen")),
+        );
+        assert!(one.len() > 24);
+        assert_eq!(
+            one.keys().collect::<Vec<_>>(),
+            many.keys().collect::<Vec<_>>()
+        );
+        for (path, content) in one.iter() {
+            assert!(&many[path] == content, "{:?} differs", path);
+        }
+    }
+
+    #[test]
+    fn reproducible_builds_are_identical() {
+        let fixture = |dir: &TempDir| {
+            dir.write(
+                "_site.yml",
+                "site_name: test\nurl: https://example.com\nconvert_ext: [md]\n\
+                 feeds:\n  dirs: [posts]\nsitemap: true\nsearch: true\n",
+            );
+            dir.write(
+                "_templates/page.liquid",
+                "{{ page.title }} {{ page.date }} {{ site.time }}",
+            );
+            dir.write(
+                "posts/dated.md",
+                "---\ntitle: Dated\nlayout: page\ndate: 2024-01-01T12:00:00\n---\n",
+            );
+            dir.write(
+                "posts/undated.md",
+                "---\ntitle: Undated\nlayout: page\n---\n",
+            );
+        };
+        let build_at_epoch = |dir: &TempDir| {
+            in_dir(dir.path(), || {
+                std::env::set_var("SOURCE_DATE_EPOCH", "1700000000");
+                let site = Site::parse_site_dir(".".into(), false, site_configs());
+                std::env::remove_var("SOURCE_DATE_EPOCH");
+                let report = site.unwrap().generate_site();
+                assert!(report.errors.is_empty(), "{:?}", report.errors);
+            });
+            read_tree(&dir.path().join("_gen"))
+        };
+        let (a, b) = (TempDir::new("site"), TempDir::new("site"));
+        fixture(&a);
+        let one = build_at_epoch(&a);
+        // sources written and built a second later
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        fixture(&b);
+        let two = build_at_epoch(&b);
+        assert_eq!(
+            one.keys().collect::<Vec<_>>(),
+            two.keys().collect::<Vec<_>>()
+        );
+        for (path, content) in one.iter() {
+            assert!(&two[path] == content, "{:?} differs", path);
+        }
+        let undated = String::from_utf8(one[Path::new("posts/undated.html")].clone()).unwrap();
+        assert_eq!(
+            undated,
+            "Undated 2023-11-14T22:13:20+00:00 2023-11-14T22:13:20+00:00\n"
+        );
+    }
+
+    #[test]
+    fn batches_are_numbered_by_index() {
+        let dir = TempDir::new("site");
+        dir.write("_site.yml", "site_name: test\nconvert_ext: [md]\n");
+        dir.write(
+            "_templates/list.liquid",
+            "{{ paginator.current_batch_num }}:\
+             {% for b in paginator.window %} {{ b.num }}={{ b.url }}{% endfor %}",
+        );
+        dir.write(
+            "list.md",
+            "---\nlayout: list\npaginate: all_pages\npaginate_batch: 2\n---\n",
+        );
+        dir.write(
+            "blog/index.md",
+            "---\nlayout: list\npaginate: {batch: 2, path: \"page/:n/\"}\n---\n",
+        );
+        for i in 0..4 {
+            dir.write(&format!("p{}.md", i), "");
+        }
+        build(dir.path());
+        let read = |x: &str| fs::read_to_string(dir.path().join("_gen").join(x)).unwrap();
+        // 6 pages, in batches 0, 1 and 2
+        assert_eq!(
+            read("list/1.html"),
+            "1: 0=/list.html 1=/list/1.html 2=/list/2.html\n"
+        );
+        assert!(dir.path().join("_gen/list/2.html").exists());
+        assert_eq!(
+            read("blog/page/2/index.html"),
+            "2: 0=/blog/index.html 1=/blog/page/1/ 2=/blog/page/2/\n"
+        );
+    }
+
+    #[test]
+    fn converter_metadata_is_merged() {
+        let dir = TempDir::new("site");
+        dir.write(
+            "_site.yml",
+            r#"site_name: test
+convert_ext: [md]
+converter_choice:
+  md: meta
+converters:
+  meta:
+    command: sh -c 'cat >/dev/null; printf %s "$RESPONSE"'
+    protocol: json
+    env:
+      RESPONSE: '{"content": "<p>x</p>", "metadata": {"title": "Found", "words": 3}}'
+"#,
+        );
+        dir.write(
+            "_templates/page.liquid",
+            "{{ page.title }} {{ page.words }} {{ content }}",
+        );
+        dir.write("a.md", "---\ntitle: A\nlayout: page\n---\n# Found\n");
+        dir.write("b.md", "---\nlayout: page\n---\n# Found\n");
+        build(dir.path());
+        let read = |x: &str| fs::read_to_string(dir.path().join("_gen").join(x)).unwrap();
+        // the front matter takes precedence
+        assert_eq!(read("a.html"), "A 3 <p>x</p>\n");
+        assert_eq!(read("b.html"), "Found 3 <p>x</p>\n");
+    }
+
+    #[test]
+    fn drafts_and_future_pages() {
+        let build_site = |site_configs: SiteConfigs| {
+            let dir = TempDir::new("site");
+            dir.write(
+                "_site.yml",
+                "site_name: test\nurl: https://example.com\nconvert_ext: [md]\n\
+                 taxonomies: [tag]\nfeeds:\n  site: false\n  dirs: [posts]\nsitemap: true\n",
+            );
+            dir.write(
+                "_templates/page.liquid",
+                "{{ page.title }}|\
+                 {% if page contains \"next\" %}{{ id_to_page[page.next].title }}{% endif %}\
+                 {% if page contains \"last\" %}{{ id_to_page[page.last].title }}{% endif %}|\
+                 {% for id in all_pages %}{{ id_to_page[id].title }}{% endfor %}|\
+                 {% for t in taxo.tag._keys %}{{ t }}{% endfor %}",
+            );
+            let pages = [
+                ("a", "date: 2024-01-01\ntag: [t]"),
+                ("b", "date: 2024-01-02\ntag: [t, draft]\ndraft: true"),
+                ("c", "date: 2999-01-01\ntag: [t, future]"),
+                ("e", "date: 2024-01-03\ntag: [t]"),
+            ];
+            for (name, fm) in pages {
+                dir.write(
+                    &format!("posts/{}.md", name),
+                    &format!(
+                        "---\ntitle: {}\nlayout: page\n{}\n---\n",
+                        name.to_uppercase(),
+                        fm
+                    ),
+                );
+            }
+            build_with(dir.path(), site_configs);
+            let read = |x: &str| fs::read_to_string(dir.path().join("_gen").join(x)).ok();
+            let generated = ["a", "b", "c", "e"]
+                .into_iter()
+                .filter(|x| read(&format!("posts/{}.html", x)).is_some())
+                .collect::<String>();
+            (
+                generated,
+                read("posts/a.html").unwrap(),
+                read("sitemap.xml").unwrap(),
+                read("posts/atom.xml").unwrap(),
+            )
+        };
+        let in_feed = |feed: &str, title: &str| feed.contains(&format!("<title>{}</title>", title));
+
+        let (generated, a, sitemap, feed) = build_site(site_configs());
+        assert_eq!(generated, "ae");
+        // a and e only are listed, neighbours of each other
+        assert_eq!(a, "A|E|EA|t\n");
+        assert!(!sitemap.contains("posts/b.html") && !sitemap.contains("posts/c.html"));
+        assert!(in_feed(&feed, "A") && !in_feed(&feed, "B") && !in_feed(&feed, "C"));
+
+        let (generated, a, sitemap, feed) = build_site(SiteConfigs {
+            drafts: true,
+            ..site_configs()
+        });
+        assert_eq!(generated, "abe");
+        assert_eq!(a, "A|B|EBA|draftt\n");
+        assert!(sitemap.contains("posts/b.html") && !sitemap.contains("posts/c.html"));
+        assert!(in_feed(&feed, "B") && !in_feed(&feed, "C"));
+
+        let (generated, a, sitemap, feed) = build_site(SiteConfigs {
+            future: true,
+            ..site_configs()
+        });
+        assert_eq!(generated, "ace");
+        assert_eq!(a, "A|E|CEA|futuret\n");
+        assert!(sitemap.contains("posts/c.html") && !sitemap.contains("posts/b.html"));
+        assert!(in_feed(&feed, "C") && !in_feed(&feed, "B"));
+    }
+
+    #[test]
+    fn prune_stale_outputs() {
+        let dir = TempDir::new("site");
+        dir.write("_site.yml", "site_name: test\nconvert_ext: [md]\n");
+        dir.write("_templates/page.liquid", "{{ page.title }}");
+        dir.write("a.md", "---\ntitle: A\nlayout: page\n---\n");
+        dir.write("old/b.md", "---\ntitle: B\nlayout: page\n---\n");
+        dir.write("assets/x.css", "body {}");
+        build(dir.path());
+        fs::remove_dir_all(dir.path().join("old")).unwrap();
+        fs::remove_dir_all(dir.path().join("assets")).unwrap();
+        dir.write("_gen/stray.txt", "");
+        dir.write("_gen/.nojekyll", "");
+        dir.write("_gen/.git/config", "");
+        fs::create_dir(dir.path().join("_gen/empty")).unwrap();
+
+        let gen = dir.path().join("_gen");
+        let prune = |dry_run: bool| {
+            let report = build_with(
+                dir.path(),
+                SiteConfigs {
+                    prune: !dry_run,
+                    dry_run,
+                    ..site_configs()
+                },
+            );
+            let mut pruned = report
+                .pruned
+                .iter()
+                .map(|x| {
+                    x.strip_prefix("./_gen")
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect_vec();
+            pruned.sort();
+            pruned
+        };
+        let stale = [
+            "assets",
+            "assets/x.css",
+            "empty",
+            "old",
+            "old/b.html",
+            "stray.txt",
+        ];
+        // a dry run only lists what would be removed
+        let outputs = || {
+            let mut tree = read_tree(&gen);
+            tree.remove(Path::new(MANIFEST_FILE));
+            tree
+        };
+        let before = outputs();
+        assert_eq!(prune(true), stale);
+        assert_eq!(outputs(), before);
+        assert!(gen.join("empty").is_dir());
+
+        assert_eq!(prune(false), stale);
+        for path in stale {
+            assert!(!gen.join(path).exists(), "{} is left", path);
+        }
+        for path in ["a.html", ".nojekyll", ".git/config"] {
+            assert!(gen.join(path).exists(), "{} is removed", path);
+        }
+    }
+
+    #[test]
+    fn backlinks_and_related_pages() {
+        let dir = TempDir::new("site");
+        dir.write(
+            "_site.yml",
+            "site_name: test\nurl: https://example.com\nconvert_ext: [md]\n\
+             converter_choice:\n  md: __internal__\ntaxonomies: [tag]\nrelated_limit: 3\n",
+        );
+        dir.write(
+            "_templates/page.liquid",
+            "{% for id in page.backlinks %}{{ id_to_page[id].title }} {% endfor %}|\
+             {% for id in page.related %}{{ id_to_page[id].title }} {% endfor %}",
+        );
+        let pages = [
+            // links to itself do not count
+            ("posts/t", "01", "[x, y]", "[me](t.html) [top](#top)"),
+            // twice, with a fragment
+            ("posts/rel", "02", "[x]", "[t](t.html#a) [t](./t.html)"),
+            ("abs", "03", "[x, y]", "[t](/posts/t.html)"),
+            (
+                "full",
+                "04",
+                "[y]",
+                "[t](https://example.com/posts/t.html?q=1)",
+            ),
+            ("ext", "05", "[x, y]", "[t](https://other.com/posts/t.html)"),
+            ("up/deep", "06", "[]", "[t](../posts/t.html)"),
+        ];
+        for (path, day, tags, body) in pages {
+            let title = path.rsplit('/').next().unwrap();
+            dir.write(
+                &format!("{}.md", path),
+                &format!(
+                    "---\ntitle: {title}\nlayout: page\ndate: 2024-01-{day}\ntag: {tags}\n---\n{body}\n"
+                ),
+            );
+        }
+        build(dir.path());
+        let read = |x: &str| fs::read_to_string(dir.path().join("_gen").join(x)).unwrap();
+        // backlinks latest first; related pages sharing the most terms first,
+        // then latest first
+        assert_eq!(read("posts/t.html"), "deep full abs rel |ext abs full \n");
+        assert_eq!(read("posts/rel.html"), "|ext abs t \n");
+    }
+
+    #[test]
+    fn malformed_math_fails_its_page() {
+        let dir = TempDir::new("site");
+        dir.write(
+            "_site.yml",
+            "site_name: test\nconvert_ext: [md]\nconverter_choice:\n  md: __internal__\n",
+        );
+        dir.write("_templates/page.liquid", "{{ content }}");
+        dir.write("good.md", "---\nlayout: page\n---\n$x^2$\n");
+        dir.write(
+            "posts/bad.md",
+            "---\nlayout: page\n---\nText\n\n$\\frac{1}$\n",
+        );
+        let report = in_dir(dir.path(), || {
+            let mut site = Site::parse_site_dir(".".into(), false, site_configs()).unwrap();
+            site.generate_site()
+        });
+        let messages = report.errors.iter().map(|x| x.to_string()).collect_vec();
+        assert_eq!(report.errors.len(), 1, "{:?}", messages);
+        assert!(
+            matches!(&report.errors[0], SushiError::Converter { path, .. } if path.ends_with("posts/bad.md")),
+            "{:?}",
+            messages
+        );
+        assert!(
+            messages[0].contains("line 6: cannot render math \"\\frac{1}\""),
+            "{}",
+            messages[0]
+        );
+        assert!(dir.path().join("_gen/good.html").exists());
+        assert!(!dir.path().join("_gen/posts/bad.html").exists());
+    }
+
+    #[test]
+    fn sitemap_lists_taxonomy_pages() {
+        let dir = TempDir::new("site");
+        dir.write(
+            "_site.yml",
+            "site_name: test\nurl: https://example.com\nconvert_ext: [md]\n\
+             taxonomies: [tag]\ntaxonomy_pages: true\nsitemap: true\n",
+        );
+        dir.write("_templates/page.liquid", "{{ page.title }}");
+        dir.write("a.md", "---\ntitle: A\nlayout: page\ntag: [x]\n---\n");
+        let sitemap = || fs::read_to_string(dir.path().join("_gen/sitemap.xml")).unwrap();
+
+        build(dir.path());
+        assert!(sitemap().contains("<loc>https://example.com/tag/x/</loc>"));
+        assert!(sitemap().contains("<loc>https://example.com/tag/</loc>"));
+
+        // a new term is listed too
+        dir.write("b.md", "---\ntitle: B\nlayout: page\ntag: [y]\n---\n");
+        build(dir.path());
+        assert!(sitemap().contains("<loc>https://example.com/tag/y/</loc>"));
+    }
+}
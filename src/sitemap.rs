@@ -0,0 +1,295 @@
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::configuration_loader as confld;
+use crate::error::SushiError;
+use crate::feed::escape;
+use crate::manifest::{self, Inputs};
+use crate::site::{BuildReport, Site, SITEMAP_SOURCE, TAXONOMY_PAGES_SOURCE};
+
+// limits of the sitemap protocol, for a single sitemap file
+pub const MAX_URLS: usize = 50_000;
+pub const MAX_BYTES: usize = 50 * 1024 * 1024;
+
+pub const DEFAULT_ROBOTS: &str = "User-agent: *
+Allow: /
+
+Sitemap: {{ sitemap_url }}
+";
+
+/// `sitemap` section of `_site.yml`. `sitemap: true` uses the defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SitemapConfig {
+    /// whether to generate `robots.txt`
+    #[serde(default = "confld::default_true")]
+    pub robots: bool,
+    pub changefreq: Option<String>,
+    pub priority: Option<f64>,
+}
+
+impl Default for SitemapConfig {
+    fn default() -> Self {
+        Self {
+            robots: true,
+            changefreq: None,
+            priority: None,
+        }
+    }
+}
+
+/// `sitemap` in the front matter of a page. `sitemap: false` leaves the page
+/// out of the sitemap.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PageSitemap {
+    pub changefreq: Option<String>,
+    pub priority: Option<f64>,
+}
+
+pub struct SitemapEntry {
+    pub url: String,
+    pub lastmod: Option<String>,
+    pub changefreq: Option<String>,
+    pub priority: Option<f64>,
+}
+
+const URLSET_HEAD: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>
+<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">
+";
+const URLSET_TAIL: &str = "</urlset>\n";
+
+fn url_element(entry: &SitemapEntry) -> String {
+    let mut s = String::new();
+    s.push_str("  <url>\n");
+    let _ = writeln!(s, "    <loc>{}</loc>", escape(&entry.url));
+    if let Some(lastmod) = &entry.lastmod {
+        let _ = writeln!(s, "    <lastmod>{}</lastmod>", escape(lastmod));
+    }
+    if let Some(changefreq) = &entry.changefreq {
+        let _ = writeln!(s, "    <changefreq>{}</changefreq>", escape(changefreq));
+    }
+    if let Some(priority) = &entry.priority {
+        let _ = writeln!(s, "    <priority>{}</priority>", priority);
+    }
+    s.push_str("  </url>\n");
+    s
+}
+
+/// Render the entries into as many sitemaps as the protocol limits require.
+pub fn urlsets(entries: &[SitemapEntry]) -> Vec<String> {
+    let mut sitemaps = vec![];
+    let mut current = String::from(URLSET_HEAD);
+    let mut count = 0;
+    for entry in entries {
+        let element = url_element(entry);
+        let too_large = current.len() + element.len() + URLSET_TAIL.len() > MAX_BYTES;
+        if count == MAX_URLS || (count > 0 && too_large) {
+            current.push_str(URLSET_TAIL);
+            sitemaps.push(current);
+            current = String::from(URLSET_HEAD);
+            count = 0;
+        }
+        current.push_str(&element);
+        count += 1;
+    }
+    current.push_str(URLSET_TAIL);
+    sitemaps.push(current);
+    sitemaps
+}
+
+/// Sitemap index pointing at each of the given sitemaps.
+pub fn sitemap_index(urls: &[String]) -> String {
+    let mut s = String::new();
+    s.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    s.push_str("<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for url in urls {
+        let _ = writeln!(s, "  <sitemap><loc>{}</loc></sitemap>", escape(url));
+    }
+    s.push_str("</sitemapindex>\n");
+    s
+}
+
+impl Site {
+    /// Generate `sitemap.xml` (split into several sitemaps and an index if
+    /// needed) and `robots.txt`.
+    pub(crate) fn gen_sitemap(
+        &self,
+        config: &SitemapConfig,
+        globals: &liquid::Object,
+        report: &mut BuildReport,
+    ) {
+        let key = Path::new(SITEMAP_SOURCE);
+        // pages of taxonomies and terms, generated just before
+        let taxonomy_outputs = self
+            .next_manifest
+            .lock()
+            .unwrap()
+            .get(TAXONOMY_PAGES_SOURCE)
+            .map(|x| x.outputs.clone())
+            .unwrap_or_default();
+        let inputs = Inputs {
+            source: manifest::hash_parts(
+                std::iter::once(self.site_index_hash.as_bytes())
+                    .chain(taxonomy_outputs.iter().map(|x| x.as_bytes())),
+            ),
+            layouts: self.template_hashes.get("robots").cloned(),
+            site: Some(self.config_hash.clone()),
+            ..Default::default()
+        };
+        if self._skip_synthetic(key, &inputs, "sitemap") {
+            return;
+        }
+
+        let mut pages = self.pages.iter().map(|x| x.read().unwrap()).collect_vec();
+        pages.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut entries = vec![];
+        for page in pages {
+            let page_sitemap = match page.front_matter.get("sitemap") {
+                Some(Value::Bool(false)) => continue,
+                None | Some(Value::Bool(true)) => PageSitemap::default(),
+                Some(value) => match serde_yaml::from_value(value.clone()) {
+                    Ok(page_sitemap) => page_sitemap,
+                    Err(e) => {
+                        report.errors.push(SushiError::FrontMatter {
+                            path: page.path.clone(),
+                            line: None,
+                            message: format!("invalid `sitemap`: {}", e),
+                        });
+                        PageSitemap::default()
+                    }
+                },
+            };
+            let lastmod = if page.front_matter.contains_key("date") || self.source_times.is_some() {
+                Some(*page.date())
+            } else {
+                fs::metadata(&page.path)
+                    .and_then(|x| x.modified())
+                    .ok()
+                    .map(|x| self.zone.at(&chrono::DateTime::<chrono::Utc>::from(x)))
+            };
+            // later batches of paginated pages
+            let gen_path = page
+                .gen_path
+                .strip_prefix(&self.gen_dir)
+                .unwrap_or(&page.gen_path);
+            let batches = self
+                .next_manifest
+                .lock()
+                .unwrap()
+                .get(&self._source_key(&page.path))
+                .map(|x| {
+                    x.outputs
+                        .iter()
+                        .filter(|x| Path::new(x) != gen_path)
+                        .map(|x| {
+                            self._get_batch_url_from_dest(
+                                &self.gen_dir.join(x),
+                                page.url.ends_with('/'),
+                            )
+                        })
+                        .collect_vec()
+                })
+                .unwrap_or_default();
+            for url in std::iter::once(page.url.clone()).chain(batches) {
+                entries.push(SitemapEntry {
+                    url,
+                    lastmod: lastmod.map(|x| x.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)),
+                    changefreq: page_sitemap
+                        .changefreq
+                        .clone()
+                        .or_else(|| config.changefreq.clone()),
+                    priority: page_sitemap.priority.or(config.priority),
+                });
+            }
+        }
+
+        for output in taxonomy_outputs {
+            let path = self.gen_dir.join(output);
+            let pretty = path.file_name() == Some(OsStr::new("index.html"));
+            entries.push(SitemapEntry {
+                url: self._get_batch_url_from_dest(&path, pretty),
+                lastmod: None,
+                changefreq: config.changefreq.clone(),
+                priority: config.priority,
+            });
+        }
+
+        let mut files = vec![];
+        let sitemap_path = self.gen_dir.join("sitemap.xml");
+        let urlsets = urlsets(&entries);
+        if urlsets.len() == 1 {
+            files.extend(urlsets.into_iter().map(|x| (sitemap_path.clone(), x)));
+        } else {
+            let paths = (1..=urlsets.len())
+                .map(|i| self.gen_dir.join(format!("sitemap-{}.xml", i)))
+                .collect_vec();
+            let urls = paths.iter().map(|x| self._gen_url(x)).collect_vec();
+            files.push((sitemap_path.clone(), sitemap_index(&urls)));
+            files.extend(paths.into_iter().zip(urlsets));
+        }
+        if config.robots {
+            let mut locals = liquid::object!({
+                "sitemap_url": self._gen_url(&sitemap_path),
+            });
+            let path = self.gen_dir.join("robots.txt");
+            let rendered = match self.templates.get("robots") {
+                Some(template) => template
+                    .render(globals, &mut locals)
+                    .map_err(|e| Self::_render_error(&path, "robots", e)),
+                None => liquid::ParserBuilder::with_stdlib()
+                    .build()
+                    .and_then(|x| x.parse(DEFAULT_ROBOTS))
+                    .and_then(|x| x.render(&locals))
+                    .map_err(|e| Self::_render_error(&path, "robots", e)),
+            };
+            match rendered {
+                Ok(rendered) => files.push((path, rendered)),
+                Err(e) => {
+                    report.errors.push(e);
+                    return;
+                }
+            }
+        }
+
+        let tree_outputs = self._tree_outputs();
+        let mut outputs = vec![];
+        for (path, content) in files {
+            match Self::_write_synthetic(&tree_outputs, &path, content, "site") {
+                Ok(true) => outputs.push(path),
+                Ok(false) => (),
+                Err(e) => {
+                    report.errors.push(SushiError::io(&path, e));
+                    return;
+                }
+            }
+        }
+        self._record(key, inputs, outputs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_at_max_urls() {
+        let entries = (0..MAX_URLS + 1)
+            .map(|i| SitemapEntry {
+                url: format!("https://example.com/{}.html", i),
+                lastmod: None,
+                changefreq: None,
+                priority: None,
+            })
+            .collect::<Vec<_>>();
+        let sitemaps = urlsets(&entries);
+        assert_eq!(sitemaps.len(), 2);
+        assert_eq!(sitemaps[0].matches("<url>").count(), MAX_URLS);
+        assert_eq!(sitemaps[1].matches("<url>").count(), 1);
+    }
+}
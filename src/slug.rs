@@ -0,0 +1,16 @@
+/// Turn a title, a taxonomy term or a heading into a url component, e.g.
+/// `Rust Lang` -> `rust-lang`. Non-ASCII letters (e.g. Chinese) are kept.
+pub fn slugify(term: &str) -> String {
+    let mut slug = String::new();
+    for c in term.chars().flat_map(|c| c.to_lowercase()) {
+        if c.is_alphanumeric() || c == '_' {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
@@ -0,0 +1,159 @@
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::page::Page;
+
+/// What pages are sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    /// latest first
+    #[serde(alias = "date")]
+    DateDesc,
+    /// earliest first
+    DateAsc,
+    /// `title` of the front matter, or the file name
+    Title,
+    /// `weight` (or `order`) of the front matter, smallest first; pages
+    /// without one come last
+    Weight,
+    /// path of the source, so index pages are sorted by their directory
+    Filename,
+}
+
+/// Keys to sort by, the later ones breaking ties of the earlier ones. Pages
+/// still tied are sorted by date (latest first), then by path.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "serde_yaml::Value")]
+pub struct SortOrder(pub Vec<SortKey>);
+
+/// A key, or a list of keys.
+impl TryFrom<serde_yaml::Value> for SortOrder {
+    type Error = serde_yaml::Error;
+
+    fn try_from(value: serde_yaml::Value) -> Result<Self, Self::Error> {
+        match value {
+            serde_yaml::Value::Sequence(_) => Ok(SortOrder(serde_yaml::from_value(value)?)),
+            value => Ok(SortOrder(vec![serde_yaml::from_value(value)?])),
+        }
+    }
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder(vec![SortKey::DateDesc])
+    }
+}
+
+impl SortOrder {
+    pub fn compare(&self, a: &Page, b: &Page) -> Ordering {
+        self.0
+            .iter()
+            .chain([SortKey::DateDesc, SortKey::Filename].iter())
+            .fold(Ordering::Equal, |ordering, key| {
+                ordering.then_with(|| compare_by(*key, a, b))
+            })
+    }
+}
+
+fn compare_by(key: SortKey, a: &Page, b: &Page) -> Ordering {
+    match key {
+        SortKey::DateDesc => b.date().cmp(a.date()),
+        SortKey::DateAsc => a.date().cmp(b.date()),
+        SortKey::Title => {
+            let (a, b) = (title_of(a), title_of(b));
+            a.to_lowercase().cmp(&b.to_lowercase()).then(a.cmp(&b))
+        }
+        SortKey::Weight => match (weight_of(a), weight_of(b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        },
+        SortKey::Filename => a.path.cmp(&b.path),
+    }
+}
+
+fn title_of(page: &Page) -> String {
+    match page.front_matter.get("title") {
+        Some(serde_yaml::Value::String(title)) => title.clone(),
+        _ => page
+            .path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+    }
+}
+
+fn weight_of(page: &Page) -> Option<f64> {
+    ["weight", "order"]
+        .iter()
+        .find_map(|key| page.front_matter.get(*key).and_then(|x| x.as_f64()))
+}
+
+/// Sort orders of `_site.yml`: `sort` for the whole site (and `all_pages`),
+/// and `sorts` for directories (relative to the site directory).
+#[derive(Debug, Default)]
+pub struct SortOrders {
+    global: SortOrder,
+    /// sorted by depth, deepest first
+    dirs: Vec<(PathBuf, SortOrder)>,
+}
+
+impl SortOrders {
+    pub fn new(global: SortOrder, dirs: HashMap<String, SortOrder>) -> Self {
+        let mut dirs = dirs
+            .into_iter()
+            .map(|(dir, order)| (PathBuf::from(dir.trim_matches('/')), order))
+            .collect::<Vec<_>>();
+        dirs.sort_by(|a, b| {
+            b.0.components()
+                .count()
+                .cmp(&a.0.components().count())
+                .then(a.0.cmp(&b.0))
+        });
+        Self { global, dirs }
+    }
+
+    pub fn global(&self) -> &SortOrder {
+        &self.global
+    }
+
+    /// Order of the pages in `dir` (relative to the site directory), from the
+    /// closest directory that has one, or the global one.
+    pub fn order_for(&self, dir: &Path) -> &SortOrder {
+        self.dirs
+            .iter()
+            .find(|(x, _)| dir.starts_with(x))
+            .map(|(_, order)| order)
+            .unwrap_or(&self.global)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_orders() {
+        let orders: HashMap<String, SortOrder> =
+            serde_yaml::from_str("docs: weight\nposts/old: [date_asc, title]\n").unwrap();
+        let orders = SortOrders::new(SortOrder::default(), orders);
+        assert_eq!(
+            orders.order_for(Path::new("docs/a")).0,
+            vec![SortKey::Weight]
+        );
+        assert_eq!(
+            orders.order_for(Path::new("posts/old")).0,
+            vec![SortKey::DateAsc, SortKey::Title]
+        );
+        assert_eq!(
+            orders.order_for(Path::new("posts")).0,
+            vec![SortKey::DateDesc]
+        );
+        assert!(serde_yaml::from_str::<SortOrder>("size").is_err());
+    }
+}
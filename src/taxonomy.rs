@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::converters::Converter;
+use crate::error::SushiError;
+use crate::extract_frontmatter::{extract_front_matter, FrontMatter};
+use crate::manifest;
+
+/// Term of a taxonomy with empty segments dropped, e.g. `dev//rust/` ->
+/// `dev/rust`; `None` if nothing is left.
+pub fn normalize(term: &str) -> Option<String> {
+    let term = term
+        .split('/')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    (!term.is_empty()).then_some(term)
+}
+
+/// Parent of a nested term, e.g. `dev/rust` for `dev/rust/async`.
+pub fn parent(term: &str) -> Option<&str> {
+    term.rsplit_once('/').map(|(parent, _)| parent)
+}
+
+/// Last segment of a term, e.g. `async` for `dev/rust/async`.
+pub fn name(term: &str) -> &str {
+    term.rsplit_once('/').map_or(term, |(_, name)| name)
+}
+
+/// The term and its ancestors, e.g. `dev`, `dev/rust` and `dev/rust/async`
+/// for `dev/rust/async`.
+pub fn with_ancestors(term: &str) -> impl Iterator<Item = &str> {
+    term.match_indices('/')
+        .map(|(i, _)| &term[..i])
+        .chain([term])
+}
+
+/// `_taxonomies/<taxonomy>/<term>.md`, describing a term.
+#[derive(Debug, Clone)]
+pub struct TermFile {
+    pub front_matter: FrontMatter,
+    /// `description` of the front matter, else the content as html
+    pub description: Option<String>,
+    pub hash: String,
+}
+
+/// taxonomy -> term (path of the file without `.md`) -> file
+pub type TermFiles = BTreeMap<String, BTreeMap<String, TermFile>>;
+
+/// Load the files of `dir` (`_taxonomies` of the site directory), each
+/// directory being a taxonomy. Files in subdirectories describe nested terms,
+/// e.g. `category/dev/rust.md` is `dev/rust` of `category`.
+pub fn load_term_files(dir: &Path, converter: &dyn Converter) -> (TermFiles, Vec<SushiError>) {
+    let mut files = TermFiles::new();
+    let mut errors = vec![];
+    let mut paths = vec![];
+    collect_md_files(dir, &mut paths, &mut errors);
+    for path in paths {
+        let rel_path = path.strip_prefix(dir).unwrap_or(&path).with_extension("");
+        let mut segments = rel_path
+            .components()
+            .map(|x| x.as_os_str().to_string_lossy().to_string());
+        let Some(taxo) = segments.next() else {
+            continue;
+        };
+        let Some(term) = normalize(&segments.collect::<Vec<_>>().join("/")) else {
+            // `_taxonomies/<file>.md`, not in a taxonomy
+            continue;
+        };
+        let (front_matter, content, _) = match extract_front_matter(&path) {
+            Ok(x) => x,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        let front_matter = front_matter.unwrap_or_default();
+        let description = match front_matter.get("description") {
+            Some(serde_yaml::Value::String(description)) => Some(description.clone()),
+            _ if content.trim().is_empty() => None,
+            _ => match converter.convert(content.clone().into_bytes()) {
+                Ok(html) => Some(String::from_utf8_lossy(&html).to_string()),
+                Err(message) => {
+                    errors.push(SushiError::Converter {
+                        path: path.clone(),
+                        converter: "__internal__".to_string(),
+                        message,
+                    });
+                    continue;
+                }
+            },
+        };
+        let hash = manifest::hash_parts([
+            manifest::hash_config(&front_matter).as_bytes(),
+            content.as_bytes(),
+        ]);
+        files.entry(taxo).or_default().insert(
+            term,
+            TermFile {
+                front_matter,
+                description,
+                hash,
+            },
+        );
+    }
+    (files, errors)
+}
+
+fn collect_md_files(dir: &Path, paths: &mut Vec<std::path::PathBuf>, errors: &mut Vec<SushiError>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            errors.push(SushiError::io(dir, e));
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_md_files(&path, paths, errors);
+        } else if path.extension().is_some_and(|x| x == "md") {
+            paths.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_terms() {
+        assert_eq!(normalize(" dev//rust/ ").as_deref(), Some("dev/rust"));
+        assert_eq!(normalize("/"), None);
+        assert_eq!(
+            with_ancestors("dev/rust/async").collect::<Vec<_>>(),
+            vec!["dev", "dev/rust", "dev/rust/async"]
+        );
+        assert_eq!(parent("dev/rust/async"), Some("dev/rust"));
+        assert_eq!(parent("dev"), None);
+        assert_eq!(name("dev/rust/async"), "async");
+    }
+}
@@ -0,0 +1,427 @@
+use itertools::Itertools;
+use log::info;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, SushiError};
+use crate::layout::Layout;
+use crate::manifest::Inputs;
+use crate::paginator::{self, Paginator};
+use crate::permalink;
+use crate::site::{BuildReport, Site, TAXONOMY_PAGES_SOURCE};
+use crate::slug::{self, slugify};
+use crate::taxonomy;
+
+pub const DEFAULT_TERM: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{{ page.title | escape }}</title>
+</head>
+<body>
+<h1>{{ page.title | escape }}</h1>
+<ul>
+{% for id in paginator.current_batch %}{% assign p = id_to_page[id] %}<li><a href=\"{{ p.url }}\">{% if p.title %}{{ p.title | escape }}{% else %}{{ p.url }}{% endif %}</a></li>
+{% endfor %}</ul>
+{% if paginator.last_batch_num %}<a href=\"{{ paginator.batch_urls[paginator.last_batch_num] }}\">newer</a>{% endif %}
+{% if paginator.next_batch_num %}<a href=\"{{ paginator.batch_urls[paginator.next_batch_num] }}\">older</a>{% endif %}
+</body>
+</html>
+";
+
+pub const DEFAULT_TAXONOMY: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{{ page.title | escape }}</title>
+</head>
+<body>
+<h1>{{ page.title | escape }}</h1>
+<ul>
+{% for term in paginator.current_batch %}<li><a href=\"{{ term.url }}\">{{ term.name | escape }}</a> ({{ term.count }})</li>
+{% endfor %}</ul>
+{% if paginator.last_batch_num %}<a href=\"{{ paginator.batch_urls[paginator.last_batch_num] }}\">previous</a>{% endif %}
+{% if paginator.next_batch_num %}<a href=\"{{ paginator.batch_urls[paginator.next_batch_num] }}\">next</a>{% endif %}
+</body>
+</html>
+";
+
+/// `taxonomy_pages` section of `_site.yml`. `taxonomy_pages: true` generates
+/// pages for every taxonomy with the defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TaxonomyPagesConfig {
+    /// taxonomies to generate pages for, all of them if empty
+    #[serde(default)]
+    pub taxonomies: Vec<String>,
+    /// template of the page of a term, listing its pages
+    #[serde(default = "default_term_template")]
+    pub term_template: String,
+    /// template of the page of a taxonomy, listing its terms
+    #[serde(default = "default_taxonomy_template")]
+    pub taxonomy_template: String,
+    /// url pattern of the page of a term, with `:taxonomy` and `:term`
+    #[serde(default = "default_term_url")]
+    pub term_url: String,
+    /// url pattern of the page of a taxonomy, with `:taxonomy`
+    #[serde(default = "default_taxonomy_url")]
+    pub taxonomy_url: String,
+    /// items of a batch, `0` for a single batch
+    #[serde(default = "default_paginate")]
+    pub paginate: usize,
+}
+
+fn default_term_template() -> String {
+    "term".to_string()
+}
+
+fn default_taxonomy_template() -> String {
+    "taxonomy".to_string()
+}
+
+fn default_term_url() -> String {
+    "/:taxonomy/:term/".to_string()
+}
+
+fn default_taxonomy_url() -> String {
+    "/:taxonomy/".to_string()
+}
+
+fn default_paginate() -> usize {
+    10
+}
+
+impl Default for TaxonomyPagesConfig {
+    fn default() -> Self {
+        Self {
+            taxonomies: vec![],
+            term_template: default_term_template(),
+            taxonomy_template: default_taxonomy_template(),
+            term_url: default_term_url(),
+            taxonomy_url: default_taxonomy_url(),
+            paginate: default_paginate(),
+        }
+    }
+}
+
+/// Url component of every term, e.g. `Rust Lang` -> `rust-lang`. Nested
+/// terms join the slugs of their segments, e.g. `Dev/Rust Lang` ->
+/// `dev/rust-lang`, and `slug` of a term replaces the slug of its last
+/// segment. Segments without letters or digits are `term`, and terms with
+/// the same slug get `-1`, `-2`... in the order of `terms`, parents first.
+pub fn term_slugs<'a>(
+    terms: impl IntoIterator<Item = &'a String>,
+    slug: impl Fn(&str) -> Option<String>,
+) -> HashMap<String, String> {
+    let mut terms = terms.into_iter().collect::<Vec<_>>();
+    terms.sort_by_key(|x| x.matches('/').count());
+    let mut used = HashSet::new();
+    let mut slugs: HashMap<String, String> = HashMap::new();
+    for term in terms {
+        let name = match slug(term) {
+            Some(slug) if !slug.trim_matches('/').is_empty() => slug.trim_matches('/').to_string(),
+            _ => segment_slug(taxonomy::name(term)),
+        };
+        let base = match taxonomy::parent(term) {
+            Some(parent) => {
+                let parent = slugs.get(parent).cloned().unwrap_or_else(|| {
+                    parent
+                        .split('/')
+                        .map(segment_slug)
+                        .collect::<Vec<_>>()
+                        .join("/")
+                });
+                format!("{}/{}", parent, name)
+            }
+            None => name,
+        };
+        let mut slug = base.clone();
+        let mut n = 0;
+        while used.contains(&slug) {
+            n += 1;
+            slug = format!("{}-{}", base, n);
+        }
+        used.insert(slug.clone());
+        slugs.insert(term.clone(), slug);
+    }
+    slugs
+}
+
+fn segment_slug(segment: &str) -> String {
+    match slugify(segment) {
+        slug if slug.is_empty() => "term".to_string(),
+        slug => slug,
+    }
+}
+
+impl Site {
+    pub(crate) fn _taxonomies_with_pages<'a>(
+        &'a self,
+        config: &'a TaxonomyPagesConfig,
+    ) -> Vec<&'a String> {
+        if config.taxonomies.is_empty() {
+            self.taxonomies.keys().collect()
+        } else {
+            config.taxonomies.iter().collect()
+        }
+    }
+
+    /// Url and gen path of the page of a taxonomy, or of one of its terms.
+    pub(crate) fn _taxonomy_page_location(
+        &self,
+        config: &TaxonomyPagesConfig,
+        taxo: &str,
+        term: Option<&String>,
+    ) -> std::result::Result<(String, PathBuf), String> {
+        let pattern = match term {
+            Some(_) => &config.term_url,
+            None => &config.taxonomy_url,
+        };
+        let permalink = permalink::expand_with(pattern, "html", |name| match name {
+            "taxonomy" => Some(match slug::slugify(taxo) {
+                slug if slug.is_empty() => taxo.to_string(),
+                slug => slug,
+            }),
+            "term" => term.map(|x| self.term_slugs[taxo][x].clone()),
+            _ => None,
+        })?;
+        Ok((
+            self._site_url_of(permalink.url_path),
+            self.gen_dir.join(permalink.gen_path),
+        ))
+    }
+
+    /// Generate the pages of taxonomies and of their terms, see
+    /// `taxonomy_pages` in `_site.yml`.
+    pub(crate) fn gen_taxonomy_pages(
+        &self,
+        config: &TaxonomyPagesConfig,
+        globals: &liquid::Object,
+        report: &mut BuildReport,
+    ) {
+        let key = Path::new(TAXONOMY_PAGES_SOURCE);
+        let (layouts, partials, _) =
+            self._layout_inputs(&[&config.term_template, &config.taxonomy_template]);
+        let inputs = Inputs {
+            source: self.site_index_hash.clone(),
+            layouts: Some(layouts),
+            partials: Some(partials),
+            site: Some(self.config_hash.clone()),
+            ..Default::default()
+        };
+        if self._skip_synthetic(key, &inputs, "taxonomy pages") {
+            return;
+        }
+
+        let parse_default = |source| {
+            liquid::ParserBuilder::with_stdlib()
+                .build()
+                .and_then(|x| x.parse(source))
+                .map(|x| Layout::new(HashMap::new(), x))
+        };
+        let defaults = match (parse_default(DEFAULT_TERM), parse_default(DEFAULT_TAXONOMY)) {
+            (Ok(term), Ok(taxonomy)) => (term, taxonomy),
+            (Err(e), _) | (_, Err(e)) => {
+                report
+                    .errors
+                    .push(Self::_render_error(&self.gen_dir, "taxonomy", e));
+                return;
+            }
+        };
+        let tree_outputs = self._tree_outputs();
+        let mut outputs = vec![];
+        let mut failed = false;
+        for taxo in self._taxonomies_with_pages(config) {
+            let mut term_objects = vec![];
+            let terms = self.taxonomies[taxo]
+                .iter()
+                .map(|(term, pages)| (Some(term), pages));
+            // the page of the taxonomy comes last, with the list of its terms
+            for (term, pages) in terms.chain([(None, &vec![])]) {
+                let (url, gen_path) = match self._taxonomy_page_location(config, taxo, term) {
+                    Ok(location) => location,
+                    Err(e) => {
+                        report.errors.push(SushiError::config(&self.config_path, e));
+                        failed = true;
+                        continue;
+                    }
+                };
+                if tree_outputs.contains(&gen_path) {
+                    report.errors.push(SushiError::config(
+                        &self.config_path,
+                        format!(
+                            "the page of {} collides with {}, generated from {}",
+                            term.map_or(format!("taxonomy \"{}\"", taxo), |x| format!(
+                                "term \"{}\" of {}",
+                                x, taxo
+                            )),
+                            gen_path.to_string_lossy(),
+                            self.page_gen_paths
+                                .get(&gen_path)
+                                .map(|x| x.to_string_lossy().to_string())
+                                .unwrap_or_else(|| "a static file".to_string())
+                        ),
+                    ));
+                    failed = true;
+                    continue;
+                }
+                let (page, items, template, default) = match term {
+                    Some(term) => {
+                        let ids = pages
+                            .iter()
+                            .map(|x| {
+                                liquid::model::Value::scalar(
+                                    x.read().unwrap().get_page_id().clone(),
+                                )
+                            })
+                            .collect_vec();
+                        let mut object = self._term_object(taxo, term);
+                        object.insert(Value::from("url"), Value::String(url.clone()));
+                        let object = liquid::model::to_object(&object)
+                            .map_err(|e| Self::_render_error(&gen_path, "taxonomy", e));
+                        let object = match object {
+                            Ok(object) => object,
+                            Err(e) => {
+                                report.errors.push(e);
+                                failed = true;
+                                continue;
+                            }
+                        };
+                        term_objects.push(object.clone());
+                        let mut page = object;
+                        page.insert(
+                            "taxonomy".into(),
+                            liquid::model::Value::scalar(taxo.clone()),
+                        );
+                        page.insert("term".into(), liquid::model::Value::scalar(term.clone()));
+                        (page, ids, &config.term_template, &defaults.0)
+                    }
+                    None => {
+                        let page = liquid::object!({
+                            "title": taxo,
+                            "url": url,
+                            "taxonomy": taxo,
+                        });
+                        let items = term_objects
+                            .iter()
+                            .cloned()
+                            .map(liquid::model::Value::Object)
+                            .collect_vec();
+                        (page, items, &config.taxonomy_template, &defaults.1)
+                    }
+                };
+                let rendered = self._render_taxonomy_page(
+                    (template, default),
+                    page,
+                    &items,
+                    config.paginate,
+                    (&gen_path, url.ends_with('/')),
+                    globals,
+                );
+                match rendered {
+                    Ok(paths) => {
+                        report.pages += paths.len();
+                        outputs.extend(paths);
+                    }
+                    Err(e) => {
+                        report.errors.push(e);
+                        failed = true;
+                    }
+                }
+            }
+        }
+        if failed {
+            // retried in the next build, like redirects
+            if let Some(entry) = self.manifest.get(TAXONOMY_PAGES_SOURCE) {
+                outputs.extend(entry.outputs.iter().map(|x| self.gen_dir.join(x)));
+            }
+            self._record(key, Inputs::default(), outputs);
+        } else {
+            self._record(key, inputs, outputs);
+        }
+    }
+
+    /// Render the batches of the page of a taxonomy or term, with the
+    /// template of `_site.yml` if there is one in `_templates`, else the
+    /// default one.
+    fn _render_taxonomy_page(
+        &self,
+        (template, default): (&String, &Layout),
+        page: liquid::Object,
+        items: &[liquid::model::Value],
+        batch_size: usize,
+        (gen_path, pretty): (&Path, bool),
+        globals: &liquid::Object,
+    ) -> Result<Vec<PathBuf>> {
+        let p = Paginator::new(items, batch_size, gen_path.to_path_buf(), pretty);
+        let batch_urls = p
+            .batch_paths()
+            .iter()
+            .map(|x| self._get_batch_url_from_dest(x, pretty))
+            .collect_vec();
+        let mut paginator_object = p.gen_paginator_object(&batch_urls);
+        let mut outputs = vec![];
+        for (i, (dest_path, batch)) in p.batches().into_iter().enumerate() {
+            Paginator::set_current_batch(
+                &mut paginator_object,
+                i,
+                &batch,
+                &batch_urls,
+                paginator::DEFAULT_WINDOW,
+            );
+            let locals = liquid::object!({
+                "page": page,
+                "paginator": paginator_object,
+            });
+            let rendered = self._render_layouts(
+                template,
+                Some(default),
+                &locals,
+                String::new(),
+                globals,
+                dest_path,
+            )?;
+            info!("[>>taxo]  {}", dest_path.to_string_lossy());
+            dest_path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(dest_path, rendered))
+                .map_err(|e| SushiError::io(dest_path, e))?;
+            outputs.push(dest_path.clone());
+        }
+        Ok(outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unique_slugs() {
+        let terms = [
+            "Rust Lang",
+            "rust-lang",
+            "静态 网站",
+            "C++",
+            "!",
+            "dev",
+            "dev/Rust Lang",
+            "dev/web",
+        ]
+        .map(String::from);
+        let slugs = term_slugs(terms.iter(), |x| {
+            (x == "dev/web").then(|| "www".to_string())
+        });
+        assert_eq!(slugs["Rust Lang"], "rust-lang");
+        assert_eq!(slugs["rust-lang"], "rust-lang-1");
+        assert_eq!(slugs["静态 网站"], "静态-网站");
+        assert_eq!(slugs["C++"], "c");
+        assert_eq!(slugs["!"], "term");
+        assert_eq!(slugs["dev/Rust Lang"], "dev/rust-lang");
+        assert_eq!(slugs["dev/web"], "dev/www");
+    }
+}
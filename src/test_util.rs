@@ -0,0 +1,94 @@
+use crate::site::SiteConfigs;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// A directory of the system temp dir for a test, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "sushi-test-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Write `content` to `rel_path` of the directory, creating its parents.
+    pub fn write(&self, rel_path: &str, content: &str) {
+        let path = self.0.join(rel_path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Configs of a build from the command line without options.
+pub fn site_configs() -> SiteConfigs {
+    SiteConfigs {
+        config: "_site.yml".to_string(),
+        gen: None,
+        converters: None,
+        includes: None,
+        templates: None,
+        theme: None,
+        subpath: None,
+        prune: false,
+        dry_run: false,
+        drafts: false,
+        future: false,
+        url: None,
+        jobs: None,
+    }
+}
+
+/// Run `f` in `dir`, as the command line builds the current directory. Tests
+/// changing the current directory take turns.
+pub fn in_dir<T>(dir: &Path, f: impl FnOnce() -> T) -> T {
+    static CURRENT_DIR: Mutex<()> = Mutex::new(());
+    struct Restore(PathBuf);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let _ = std::env::set_current_dir(&self.0);
+        }
+    }
+    let _lock = CURRENT_DIR.lock().unwrap_or_else(|e| e.into_inner());
+    let _restore = Restore(std::env::current_dir().unwrap());
+    std::env::set_current_dir(dir).unwrap();
+    f()
+}
+
+/// Path relative to `dir` -> content of every file under `dir`.
+pub fn read_tree(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut tree = BTreeMap::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for entry in fs::read_dir(&current).unwrap().flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                let content = fs::read(&path).unwrap();
+                tree.insert(path.strip_prefix(dir).unwrap().to_path_buf(), content);
+            }
+        }
+    }
+    tree
+}
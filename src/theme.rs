@@ -1,12 +1,12 @@
 use log::{info, warn};
 use serde_yaml::Value;
 use std::collections::HashMap;
-use std::ffi::OsString;
 use std::fs;
 use std::path::PathBuf;
 use std::string::String;
 
 use crate::configuration_loader as confld;
+use crate::error::{Result, SushiError};
 
 #[allow(dead_code)]
 pub struct Theme {
@@ -19,21 +19,19 @@ pub struct Theme {
 }
 
 impl Theme {
-    pub fn new(theme_dir: PathBuf) -> Theme {
-        let temp_config = fs::read_dir(theme_dir.clone())
-            .expect("cannot open theme directory.")
-            .find(|x| {
-                if let Ok(file) = x {
-                    file.file_name() == OsString::from("_site.yml") && file.path().is_file()
-                } else {
-                    false
-                }
-            })
-            .expect(format! {"cannot find theme configuration file: {}", "_site.yml"}.as_str())
-            .unwrap();
-        let config = confld::parse_config_file(temp_config.path());
+    pub fn new(theme_dir: PathBuf) -> Result<Theme> {
+        let config_path = theme_dir.join("_site.yml");
+        let temp_config = fs::read_dir(&theme_dir)
+            .map_err(|e| SushiError::io(&theme_dir, e))?
+            .flatten()
+            .find(|file| file.file_name() == "_site.yml" && file.path().is_file())
+            .ok_or_else(|| {
+                SushiError::config(&config_path, "cannot find theme configuration file")
+            })?;
+        let config = confld::parse_config_file(temp_config.path())?;
 
-        let theme_name = Self::_get_theme_name(&config);
+        let theme_name = Self::_get_theme_name(&config)
+            .ok_or_else(|| SushiError::config(&config_path, "theme_name not found"))?;
 
         info!("[theme] theme_name: {}", &theme_name);
 
@@ -57,21 +55,21 @@ impl Theme {
         };
 
         // search for _templates
-        let temp_templates = confld::find_dir_or_panic(&theme_dir, &_templates_dir);
-        let template_list = confld::parse_templates(temp_templates.path());
+        let temp_templates = confld::find_required_dir(&theme_dir, &_templates_dir)?;
+        let template_list = confld::parse_templates(temp_templates.path())?;
 
         // search for _converters
-        let temp_converters = confld::find_dir_or_panic(&theme_dir, &_converters_dir);
-        let converter_list = confld::parse_converters(temp_converters.path());
+        let temp_converters = confld::find_required_dir(&theme_dir, &_converters_dir)?;
+        let converter_list = confld::parse_converters(temp_converters.path())?;
 
-        Theme {
+        Ok(Theme {
             theme_dir,
             config,
             partial_list,
             converter_list,
             template_list,
             theme_name,
-        }
+        })
     }
 
     fn _decide_theme_config(yml_config: Option<String>, default_config: String) -> String {
@@ -83,11 +81,11 @@ impl Theme {
         }
     }
 
-    fn _get_theme_name(config: &HashMap<String, Value>) -> String {
+    fn _get_theme_name(config: &HashMap<String, Value>) -> Option<String> {
         if let Some(Value::String(name)) = config.get("theme_name") {
-            name.clone()
+            Some(name.clone())
         } else {
-            panic!("theme_name not found in theme _site.yml");
+            None
         }
     }
 }
@@ -1,16 +1,14 @@
 // Since this is in the tools module, we need to go up one more level to access the crate root
+use clap::Parser as cp;
+use pulldown_cmark::{Options, Parser, TextMergeStream};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use sushi_gen::markdown_parser::event_processor::ProcessWith;
 use sushi_gen::markdown_parser::{
-    highlight_event_processor::HighlightEventProcessor,
-    image_event_processor::ImageEventProcessor,
-    math_event_processor::MathEventProcessor,
-    event_processor::DebugEventProcessor,
+    event_processor::DebugEventProcessor, highlight_event_processor::HighlightEventProcessor,
+    image_event_processor::ImageEventProcessor, math_event_processor::MathEventProcessor,
 };
-use sushi_gen::markdown_parser::event_processor::ProcessWith;
 use sushi_gen::render_pipeline;
-use std::path::PathBuf;
-use pulldown_cmark::{Options, Parser, TextMergeStream};
-use std::cell::RefCell;
-use clap::Parser as cp;
 
 #[derive(cp, Debug)]
 #[command(name = "pulldown-cmark-event-view", author = "nth233", about)]
@@ -55,10 +53,7 @@ fn main() {
         );
         println!("[output]\n{}", html);
     } else {
-        let html = render_pipeline!(
-            parser,
-            debug_event_processor
-        );
+        let html = render_pipeline!(parser, debug_event_processor);
         println!("[output]\n{}", html);
     }
 }
@@ -0,0 +1,250 @@
+use log::{debug, error, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::site::{Site, SiteConfigs};
+
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// What sushi knows about the site after a build, used to tell changes
+/// to the site from unrelated files. Which outputs are affected by a change
+/// is decided by the build manifest.
+struct WatchState {
+    site_dir: PathBuf,
+    gen_dir: PathBuf,
+    theme_dir: Option<PathBuf>,
+    // sources, templates, partials, converters and the configuration file
+    known: HashSet<PathBuf>,
+}
+
+impl WatchState {
+    fn from_site(site: &Site) -> Self {
+        let mut known: HashSet<PathBuf> =
+            site.source_paths().iter().map(|x| canonical(x)).collect();
+        for paths in [
+            site.template_paths(),
+            site.partial_paths(),
+            site.converter_paths(),
+        ] {
+            known.extend(paths.values().map(|x| canonical(x)));
+        }
+        known.insert(canonical(site.config_path()));
+        Self {
+            site_dir: canonical(site.site_dir()),
+            gen_dir: canonical(site.gen_dir()),
+            theme_dir: site.theme_dir().map(|x| canonical(x)),
+            known,
+        }
+    }
+
+    fn is_relevant(&self, path: &Path) -> bool {
+        if path.starts_with(&self.gen_dir) {
+            return false;
+        }
+        let root = match &self.theme_dir {
+            Some(theme_dir) if path.starts_with(theme_dir) => theme_dir,
+            _ => &self.site_dir,
+        };
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        // dotfiles, editor swap and backup files
+        if relative
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+            || file_name.ends_with('~')
+        {
+            return false;
+        }
+        if !path.exists() {
+            // a known file was removed or renamed. Unknown ones are temporary
+            // files of editors.
+            return self.known.contains(path);
+        }
+        !path.is_dir()
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or(path.to_path_buf())
+}
+
+/// Wait for the next burst of file changes and return the paths touched,
+/// once nothing has changed for `DEBOUNCE_TIMEOUT`. Reads (including our
+/// own) are not changes.
+fn next_changes(rx: &mpsc::Receiver<notify::Result<Event>>) -> Option<Vec<PathBuf>> {
+    let mut paths = vec![];
+    let add_event = |result: notify::Result<Event>, paths: &mut Vec<PathBuf>| match result {
+        Ok(event) => {
+            if !matches!(event.kind, EventKind::Access(_)) {
+                paths.extend(event.paths);
+            }
+        }
+        Err(e) => warn!("watch error: {}", e),
+    };
+    while paths.is_empty() {
+        add_event(rx.recv().ok()?, &mut paths);
+    }
+    while let Ok(result) = rx.recv_timeout(DEBOUNCE_TIMEOUT) {
+        add_event(result, &mut paths);
+    }
+    paths.sort();
+    paths.dedup();
+    Some(paths)
+}
+
+/// Build the site and report its errors, or return `None` if it cannot be
+/// loaded at all.
+fn build(site_dir: &Path, site_configs: SiteConfigs, regen_all: bool) -> Option<Site> {
+    let mut site = match Site::parse_site_dir(site_dir.to_path_buf(), regen_all, site_configs) {
+        Ok(site) => site,
+        Err(e) => {
+            error!("{}", e);
+            error!("build failed, waiting for further changes");
+            return None;
+        }
+    };
+    site.generate_site().print_summary();
+    Some(site)
+}
+
+/// Build the site, then rebuild it whenever a source file, template,
+/// partial, converter or the configuration changes. Every rebuild is a full
+/// incremental build: the site is loaded and hashed again, and the manifest
+/// tells which outputs are affected by the change. `on_rebuild` is called after
+/// every build that got as far as generating the site. Never returns unless
+/// the watcher fails.
+pub fn watch_site(
+    site_dir: PathBuf,
+    site_configs: impl Fn() -> SiteConfigs,
+    regen_all: bool,
+    mut on_rebuild: impl FnMut(&Site),
+) {
+    let mut state = None;
+    if let Some(site) = build(&site_dir, site_configs(), regen_all) {
+        state = Some(WatchState::from_site(&site));
+        on_rebuild(&site);
+    }
+
+    let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = match RecommendedWatcher::new(tx, notify::Config::default()) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("cannot start file watcher: {}", e);
+            return;
+        }
+    };
+
+    let mut watched: HashSet<PathBuf> = HashSet::new();
+    let site_root = canonical(&site_dir);
+    let mut watch_dirs = |state: &Option<WatchState>| {
+        let mut dirs = vec![site_root.clone()];
+        if let Some(theme_dir) = state.as_ref().and_then(|x| x.theme_dir.clone()) {
+            if !theme_dir.starts_with(&site_root) {
+                dirs.push(theme_dir);
+            }
+        }
+        for dir in dirs {
+            if watched.contains(&dir) {
+                continue;
+            }
+            match watcher.watch(&dir, RecursiveMode::Recursive) {
+                Ok(_) => {
+                    info!("[ watch] {:?}", &dir);
+                    watched.insert(dir);
+                }
+                Err(e) => warn!("cannot watch {:?}: {}", &dir, e),
+            }
+        }
+    };
+    watch_dirs(&state);
+
+    // used to ignore our own output before the first successful build
+    let fallback_gen_dir = site_root.join(site_configs().gen.unwrap_or("_gen".to_string()));
+
+    while let Some(paths) = next_changes(&rx) {
+        let mut changed = 0;
+        for path in paths.iter() {
+            let path = canonical(path);
+            let relevant = match &state {
+                Some(current) => current.is_relevant(&path),
+                None => !path.starts_with(&fallback_gen_dir),
+            };
+            if relevant {
+                changed += 1;
+                debug!("[ watch] changed {:?}", &path);
+            }
+        }
+        if changed == 0 {
+            continue;
+        }
+
+        let start = Instant::now();
+        info!("[ watch] {} file(s) changed, rebuilding", changed);
+        if let Some(site) = build(&site_dir, site_configs(), false) {
+            info!("[ watch] done in {:?}", start.elapsed());
+            state = Some(WatchState::from_site(&site));
+            watch_dirs(&state);
+            on_rebuild(&site);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use notify::event::{AccessKind, ModifyKind};
+    use std::fs;
+    use std::thread;
+
+    #[test]
+    fn ignored_paths() {
+        let dir = TempDir::new("watch");
+        for file in ["posts/a.md", "posts/a.md~", ".git/HEAD", "_gen/a.html"] {
+            dir.write(file, "");
+        }
+        let site_dir = canonical(dir.path());
+        let state = WatchState {
+            gen_dir: site_dir.join("_gen"),
+            theme_dir: None,
+            known: HashSet::from([site_dir.join("posts/b.md")]),
+            site_dir: site_dir.clone(),
+        };
+        assert!(state.is_relevant(&site_dir.join("posts/a.md")));
+        assert!(!state.is_relevant(&site_dir.join("posts/a.md~")));
+        assert!(!state.is_relevant(&site_dir.join(".git/HEAD")));
+        assert!(!state.is_relevant(&site_dir.join("_gen/a.html")));
+        assert!(!state.is_relevant(&site_dir.join("posts")));
+        // removed files
+        assert!(state.is_relevant(&site_dir.join("posts/b.md")));
+        assert!(!state.is_relevant(&site_dir.join("posts/.a.md.swp")));
+        assert!(!state.is_relevant(&site_dir.join("posts/4913")));
+        fs::remove_file(site_dir.join("posts/a.md")).unwrap();
+        assert!(!state.is_relevant(&site_dir.join("posts/a.md")));
+    }
+
+    #[test]
+    fn bursts_of_changes() {
+        let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+        let event = |kind, path: &str| Ok(Event::new(kind).add_path(PathBuf::from(path)));
+        let sender = thread::spawn(move || {
+            let modify = EventKind::Modify(ModifyKind::Any);
+            tx.send(event(modify, "b")).unwrap();
+            thread::sleep(DEBOUNCE_TIMEOUT / 3);
+            tx.send(event(modify, "a")).unwrap();
+            tx.send(event(modify, "b")).unwrap();
+            tx.send(event(EventKind::Access(AccessKind::Any), "c"))
+                .unwrap();
+            thread::sleep(DEBOUNCE_TIMEOUT * 3);
+            tx.send(event(modify, "d")).unwrap();
+        });
+        let paths = |x: &[&str]| x.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(next_changes(&rx), Some(paths(&["a", "b"])));
+        assert_eq!(next_changes(&rx), Some(paths(&["d"])));
+        sender.join().unwrap();
+        assert_eq!(next_changes(&rx), None);
+    }
+}